@@ -0,0 +1,274 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::data::{Item, ItemAlias};
+
+const DEFAULT_LIMIT: usize = 10;
+const MAX_LIMIT: usize = 100;
+
+/// Where a name of the alias table comes from. When two items claim the same name, the
+/// earlier variant wins, so an alias never shadows the name of another item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AliasSource {
+    /// The item's own name.
+    Name,
+    /// A user-defined alias.
+    Alias,
+    /// The item's name on a language variant of the wiki.
+    Localized,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    name: String,
+    item: String,
+    language: Option<String>,
+    source: AliasSource,
+}
+
+/// Every name an item can be looked up by: its own, its localized names and the user-defined
+/// aliases. Lookups ignore case, surrounding whitespace and underscores for spaces.
+#[derive(Debug, Clone, Default)]
+pub struct AliasTable {
+    entries: HashMap<String, Entry>,
+}
+
+/// An item whose name or alias matches a search.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ItemMatch {
+    pub item: String,
+    /// The name that matched, which is the item's own unless it matched an alias.
+    pub matched: String,
+    pub language: Option<String>,
+    pub source: AliasSource,
+}
+
+/// Query of `/dsp/items/search`.
+#[derive(Debug, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ItemSearch {
+    /// Start or part of an item name, localized name or alias.
+    pub q: String,
+    /// Matches to return, 10 by default and at most 100.
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl AliasTable {
+    pub fn new(items: &[Item], aliases: &[ItemAlias]) -> Self {
+        let mut table = Self::default();
+        for item in items {
+            table.insert(Entry {
+                name: item.name.clone(),
+                item: item.name.clone(),
+                language: None,
+                source: AliasSource::Name,
+            });
+            let mut localized: Vec<(&String, &String)> = item.localized_names.iter().collect();
+            localized.sort();
+            for (language, name) in localized {
+                table.insert(Entry {
+                    name: name.clone(),
+                    item: item.name.clone(),
+                    language: Some(language.clone()),
+                    source: AliasSource::Localized,
+                });
+            }
+        }
+        for alias in aliases {
+            // aliases of unknown items are kept as written
+            let item = table.canonical(&alias.item).to_string();
+            table.insert(Entry {
+                name: alias.alias.clone(),
+                item,
+                language: alias.language.clone(),
+                source: AliasSource::Alias,
+            });
+        }
+        table
+    }
+
+    fn insert(&mut self, entry: Entry) {
+        let key = normalize(&entry.name);
+        if key.is_empty() {
+            return;
+        }
+        match self.entries.get(&key) {
+            Some(existing) if existing.source <= entry.source => {}
+            _ => {
+                self.entries.insert(key, entry);
+            }
+        }
+    }
+
+    /// Name of the item `name` refers to.
+    pub fn resolve(&self, name: &str) -> Option<&str> {
+        self.entries
+            .get(&normalize(name))
+            .map(|entry| entry.item.as_str())
+    }
+
+    /// Like `resolve`, but unknown names are returned as is.
+    pub fn canonical<'a>(&'a self, name: &'a str) -> &'a str {
+        self.resolve(name).unwrap_or(name)
+    }
+
+    /// Whether `name` is the own name of an item rather than an alias.
+    pub fn is_item_name(&self, name: &str) -> bool {
+        self.entries
+            .get(&normalize(name))
+            .is_some_and(|entry| entry.source == AliasSource::Name)
+    }
+
+    /// Items with a name starting with `query`, then those with a word starting with it, then
+    /// those containing it. Each item is listed once, with its best matching name.
+    pub fn search(&self, query: &str, limit: Option<usize>) -> Vec<ItemMatch> {
+        let query = normalize(query);
+        if query.is_empty() {
+            return vec![];
+        }
+        let mut best: HashMap<&str, (u8, &Entry)> = HashMap::new();
+        for (key, entry) in self.entries.iter() {
+            let Some(rank) = match_rank(key, &query) else {
+                continue;
+            };
+            let better = match best.get(entry.item.as_str()) {
+                Some((best_rank, best_entry)) => {
+                    (rank, entry.source, &entry.name)
+                        < (*best_rank, best_entry.source, &best_entry.name)
+                }
+                None => true,
+            };
+            if better {
+                best.insert(&entry.item, (rank, entry));
+            }
+        }
+
+        let mut matches: Vec<(u8, &Entry)> = best.into_values().collect();
+        matches.sort_by_key(|(rank, entry)| {
+            (*rank, entry.source, entry.item.len(), entry.item.clone())
+        });
+        matches
+            .into_iter()
+            .take(limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT))
+            .map(|(_, entry)| ItemMatch {
+                item: entry.item.clone(),
+                matched: entry.name.clone(),
+                language: entry.language.clone(),
+                source: entry.source,
+            })
+            .collect()
+    }
+}
+
+/// Lowercase with single spaces, so that `iron_ingot` and ` Iron  Ingot` are the same name.
+pub fn normalize(name: &str) -> String {
+    name.replace('_', " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// 0 for an exact match, 1 for a prefix, 2 for a word prefix and 3 for a substring.
+fn match_rank(name: &str, query: &str) -> Option<u8> {
+    if name == query {
+        Some(0)
+    } else if name.starts_with(query) {
+        Some(1)
+    } else if name.split([' ', '-']).any(|word| word.starts_with(query)) {
+        Some(2)
+    } else if name.contains(query) {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(name: &str, localized_names: &[(&str, &str)]) -> Item {
+        let mut item = Item::new(name);
+        item.localized_names = localized_names
+            .iter()
+            .map(|(language, name)| (language.to_string(), name.to_string()))
+            .collect();
+        item
+    }
+
+    fn alias(alias: &str, item: &str) -> ItemAlias {
+        ItemAlias {
+            alias: alias.to_string(),
+            item: item.to_string(),
+            language: None,
+        }
+    }
+
+    fn table() -> AliasTable {
+        AliasTable::new(
+            &[
+                item("Super-Magnetic Ring", &[("zh", "电磁涡轮")]),
+                item("Magnet", &[("zh", "磁铁"), ("ja", "磁石")]),
+                item("Magnetic Coil", &[("zh", "磁线圈")]),
+                item("Electromagnetic Matrix", &[]),
+            ],
+            &[
+                alias("SMR", "super-magnetic ring"),
+                alias("Blue science", "Electromagnetic Matrix"),
+                alias("magnet", "Magnetic Coil"),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_resolve() {
+        let table = table();
+        assert_eq!(table.resolve("smr"), Some("Super-Magnetic Ring"));
+        assert_eq!(
+            table.resolve(" blue_science "),
+            Some("Electromagnetic Matrix")
+        );
+        assert_eq!(table.resolve("磁石"), Some("Magnet"));
+        // an alias doesn't shadow the name of another item
+        assert_eq!(table.resolve("MAGNET"), Some("Magnet"));
+        assert_eq!(table.resolve("Iron Ingot"), None);
+        assert_eq!(table.canonical("Iron Ingot"), "Iron Ingot");
+        assert!(table.is_item_name("magnetic coil"));
+        assert!(!table.is_item_name("smr"));
+    }
+
+    #[test]
+    fn test_search() {
+        let table = table();
+        let items = |query: &str| -> Vec<String> {
+            table
+                .search(query, None)
+                .into_iter()
+                .map(|found| found.item)
+                .collect()
+        };
+        assert_eq!(
+            items("magn"),
+            [
+                "Magnet",
+                "Magnetic Coil",
+                "Super-Magnetic Ring",
+                "Electromagnetic Matrix"
+            ]
+        );
+        assert_eq!(
+            items("磁"),
+            ["Magnet", "Magnetic Coil", "Super-Magnetic Ring"]
+        );
+        assert!(items(" ").is_empty());
+
+        let found = table.search("blue", Some(1));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].matched, "Blue science");
+        assert_eq!(found[0].source, AliasSource::Alias);
+    }
+}
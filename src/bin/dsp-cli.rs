@@ -0,0 +1,248 @@
+use std::error::Error;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use alex_api_rs::aliases::AliasTable;
+use alex_api_rs::data::Recipe;
+use alex_api_rs::diff;
+use alex_api_rs::dsp::{self, ComputedRecipeRequest, RefreshRequest};
+use alex_api_rs::error::AppError;
+use alex_api_rs::scrape::{self, ScrapeObserver, ScrapedPage, Scraper};
+use alex_api_rs::transfer::{self, DatasetFormat};
+use alex_api_rs::validate::Validator;
+use clap::{Parser, Subcommand};
+
+#[derive(Debug, Parser)]
+#[command(about = "Manage the DSP recipe dataset without going through the HTTP server")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Export the stored dataset as json, csv or yaml
+    Export {
+        #[arg(long, default_value = "json")]
+        format: DatasetFormat,
+        /// Write to this file instead of stdout
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+    /// Validate a dataset file and store it as a new version
+    Import {
+        path: String,
+        /// Defaults to the file extension
+        #[arg(long)]
+        format: Option<DatasetFormat>,
+    },
+    /// Scrape the wiki into a dataset file, or into the database without --output
+    Scrape {
+        /// Item names or wiki urls to scrape instead of every item
+        items: Vec<String>,
+        /// Write the recipes to this file, in the format of its extension
+        #[arg(long, short)]
+        output: Option<String>,
+        /// Compare with the stored data instead of storing the result
+        #[arg(long, conflicts_with = "output")]
+        dry_run: bool,
+    },
+    /// Check a dataset file without storing it
+    Validate {
+        path: String,
+        /// Defaults to the file extension
+        #[arg(long)]
+        format: Option<DatasetFormat>,
+    },
+    /// Compute the production plan for an item, like POST /dsp/computedRecipes
+    Plan {
+        item: String,
+        /// Items per second
+        rate: f64,
+        /// Recipe index to use for an item, as item=index
+        #[arg(long = "requirement", value_parser = parse_pair::<i64>)]
+        requirements: Vec<(String, i64)>,
+        /// Facility to use for an item, as item=facility
+        #[arg(long = "facility", value_parser = parse_pair::<String>)]
+        facilities: Vec<(String, String)>,
+        /// Facility to prefer for items without --facility
+        #[arg(long = "prefer")]
+        preferred_facilities: Vec<String>,
+        /// Plan with the recipes these technologies unlock only
+        #[arg(long = "researched")]
+        researched: Option<Vec<String>>,
+        /// Plan with this dataset file instead of the stored recipes
+        #[arg(long)]
+        dataset: Option<String>,
+    },
+    /// Compare two dataset files
+    Diff { old: String, new: String },
+}
+
+/// Parses `key=value` arguments.
+fn parse_pair<T>(arg: &str) -> Result<(String, T), String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let (key, value) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected key=value, got {}", arg))?;
+    let value = value.parse().map_err(|err| format!("{}: {}", value, err))?;
+    Ok((key.to_string(), value))
+}
+
+fn read_dataset(path: &str, format: Option<DatasetFormat>) -> Result<Vec<Recipe>, Box<dyn Error>> {
+    let format = format
+        .or_else(|| DatasetFormat::from_path(path))
+        .ok_or("unable to infer the format, pass --format")?;
+    let input = fs::read_to_string(path)?;
+    Ok(transfer::import_recipes(&input, format)?)
+}
+
+fn print_json(value: &impl serde::Serialize) -> Result<(), Box<dyn Error>> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Reports scrape progress on stderr.
+#[derive(Debug, Default)]
+struct Progress {
+    queued: AtomicUsize,
+    done: AtomicUsize,
+}
+
+impl ScrapeObserver for Progress {
+    fn pages_queued(&self, count: usize) {
+        self.queued.fetch_add(count, Ordering::SeqCst);
+    }
+
+    fn page_scraped(&self, page: &ScrapedPage) {
+        let done = self.done.fetch_add(1, Ordering::SeqCst) + 1;
+        let queued = self.queued.load(Ordering::SeqCst);
+        match &page.error {
+            Some(err) => eprintln!("[{}/{}] {}: {}", done, queued, page.url, err),
+            None => eprintln!("[{}/{}] {}", done, queued, page.url),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    dotenv::dotenv().ok();
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Export { format, output } => {
+            let body = dsp::export_recipes(format).await?;
+            match output {
+                Some(path) => fs::write(path, body)?,
+                None => print!("{}", body),
+            }
+        }
+        Command::Import { path, format } => {
+            let format = format
+                .or_else(|| DatasetFormat::from_path(&path))
+                .ok_or("unable to infer the format, pass --format")?;
+            let input = fs::read_to_string(&path)?;
+            let recipes = transfer::import_recipes(&input, format)?;
+            let outcome = match dsp::import_recipes(recipes, format).await {
+                Err(AppError::ValidationFailed(report)) => {
+                    eprintln!("{}", serde_json::to_string_pretty(&report)?);
+                    return Err("dataset failed validation".into());
+                }
+                outcome => outcome?,
+            };
+            print_json(&outcome)?;
+        }
+        Command::Scrape {
+            items,
+            output: Some(path),
+            ..
+        } => {
+            let format = DatasetFormat::from_path(&path)
+                .ok_or("unable to infer the format from the output file extension")?;
+            let scraper = Scraper::new()
+                .map_err(AppError::from)?
+                .with_observer(Arc::new(Progress::default()));
+            let urls = items
+                .iter()
+                .map(|item| scrape::page_url(&scraper.config().base_url, item))
+                .collect();
+            let run = scraper
+                .scrape_dsp_data(urls)
+                .await
+                .map_err(AppError::from)?;
+            let recipes: Vec<Recipe> = run.recipe_lists.into_iter().flatten().collect();
+            let report = Validator::new().validate(&recipes);
+            fs::write(&path, transfer::export_recipes(&recipes, format)?)?;
+            eprintln!(
+                "Wrote {} recipes to {}, {} validation errors, {} warnings",
+                recipes.len(),
+                path,
+                report.error_count,
+                report.warning_count
+            );
+            print_json(&run.summary)?;
+        }
+        Command::Scrape { items, dry_run, .. } => {
+            let request = RefreshRequest { items, dry_run };
+            let outcome =
+                match dsp::refresh_data(request, Some(Arc::new(Progress::default()))).await {
+                    Err(AppError::ValidationFailed(report)) => {
+                        eprintln!("{}", serde_json::to_string_pretty(&report)?);
+                        return Err("scraped recipes failed validation".into());
+                    }
+                    outcome => outcome?,
+                };
+            print_json(&outcome)?;
+        }
+        Command::Validate { path, format } => {
+            let report = Validator::new().validate(&read_dataset(&path, format)?);
+            print_json(&report)?;
+            if !report.is_valid() {
+                return Err("dataset failed validation".into());
+            }
+        }
+        Command::Plan {
+            item,
+            rate,
+            requirements,
+            facilities,
+            preferred_facilities,
+            researched,
+            dataset,
+        } => {
+            let request = ComputedRecipeRequest {
+                name: item,
+                rate,
+                requirements: requirements.into_iter().collect(),
+                facilities: facilities.into_iter().collect(),
+                preferred_facilities,
+                researched,
+            };
+            let plan = match dataset {
+                Some(path) => {
+                    let recipes = dsp::group_recipes(read_dataset(&path, None)?);
+                    dsp::plan_recipes(request, recipes, vec![], &[], AliasTable::default())?
+                }
+                None => dsp::compute_recipes(request).await?,
+            };
+            print_json(&plan)?;
+        }
+        Command::Diff { old, new } => {
+            let diff = diff::diff_recipes(&read_dataset(&old, None)?, &read_dataset(&new, None)?);
+            eprintln!(
+                "{} added, {} removed, {} changed, {} unchanged",
+                diff.added.len(),
+                diff.removed.len(),
+                diff.changed.len(),
+                diff.unchanged
+            );
+            print_json(&diff)?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,18 +1,29 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use utoipa::ToSchema;
 
 pub mod dsp;
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct Recipe {
     pub output_item: String,
     pub output_item_count: f64,
     pub min_output_item_count: Option<f64>,
     pub max_output_item_count: Option<f64>,
+    /// Default facility, the first one listed on the wiki.
     pub facility: String,
+    /// Every facility that can craft the recipe, including `facility`.
+    #[serde(default)]
+    pub facilities: Vec<String>,
     pub time: f64,
+    /// Count of each material consumed per craft, by item name.
+    #[schema(value_type = HashMap<String, f64>)]
     pub materials: Materials,
     pub image: Option<String>,
     pub market_data: Option<MarketData>,
+    /// Technology whose research unlocks the recipe. `None` for recipes available from the
+    /// start.
+    #[serde(default)]
+    pub technology: Option<String>,
 }
 
 impl Recipe {
@@ -23,17 +34,138 @@ impl Recipe {
             min_output_item_count: None,
             max_output_item_count: None,
             facility: "".to_string(),
+            facilities: vec![],
             time: 0.0,
             materials: HashMap::new(),
             image: None,
             market_data: None,
+            technology: None,
         }
     }
+
+    /// Facilities the recipe can be crafted in. Recipes stored before `facilities` was scraped
+    /// only know their default facility.
+    pub fn eligible_facilities(&self) -> Vec<&str> {
+        if self.facilities.is_empty() {
+            vec![self.facility.as_str()]
+        } else {
+            self.facilities
+                .iter()
+                .map(|facility| facility.as_str())
+                .collect()
+        }
+    }
+
+    /// Returns the facility as it is spelled in the recipe if it can craft the recipe.
+    pub fn find_facility(&self, facility: &str) -> Option<&str> {
+        self.eligible_facilities()
+            .into_iter()
+            .find(|eligible| eligible.eq_ignore_ascii_case(facility))
+    }
+}
+
+impl Default for Recipe {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 pub type Materials = HashMap<String, f64>;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Item metadata from the infobox of the item's wiki page. Recipes refer to items by name.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Item {
+    pub name: String,
+    pub url: Option<String>,
+    pub category: Option<String>,
+    pub stack_size: Option<f64>,
+    pub description: Option<String>,
+    /// Energy released when the item is burnt, in joules.
+    pub fuel_value: Option<f64>,
+    pub image: Option<String>,
+    /// Names of the item on the language variants of the wiki, by language code.
+    #[serde(default)]
+    pub localized_names: HashMap<String, String>,
+}
+
+impl Item {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            url: None,
+            category: None,
+            stack_size: None,
+            description: None,
+            fuel_value: None,
+            image: None,
+            localized_names: HashMap::new(),
+        }
+    }
+}
+
+/// Building stats from the infobox of a facility's wiki page.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Facility {
+    pub name: String,
+    pub url: Option<String>,
+    /// Multiplier applied to recipe speeds, e.g. 0.75 for Assembling Machine Mk.I.
+    pub crafting_speed: Option<f64>,
+    /// Power drawn while crafting, in watts.
+    pub work_power: Option<f64>,
+    /// Power drawn while idle, in watts.
+    pub idle_power: Option<f64>,
+    pub footprint: Option<Footprint>,
+    pub sorter_slots: Option<u32>,
+}
+
+impl Facility {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            url: None,
+            crafting_speed: None,
+            work_power: None,
+            idle_power: None,
+            footprint: None,
+            sorter_slots: None,
+        }
+    }
+}
+
+/// A research from the tech tree.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Technology {
+    pub name: String,
+    pub url: Option<String>,
+    /// Matrices (or items, for the earliest technologies) consumed by the research, per name.
+    #[schema(value_type = HashMap<String, f64>)]
+    pub cost: Materials,
+    /// Technologies that must be researched first.
+    pub prerequisites: Vec<String>,
+    /// Items and buildings whose recipes the research unlocks.
+    pub unlocks: Vec<String>,
+}
+
+impl Technology {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            url: None,
+            cost: HashMap::new(),
+            prerequisites: vec![],
+            unlocks: vec![],
+        }
+    }
+}
+
+/// Grid cells covered by a building.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Footprint {
+    pub width: f64,
+    pub length: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
 pub struct MarketData {
     pub last_update_attempt: i64,
     pub last_updated: i64,
@@ -42,3 +174,64 @@ pub struct MarketData {
     pub total_trade_count: Option<f64>,
     pub name: Option<String>,
 }
+
+/// A manual correction to the scraped recipes. Overrides live in their own collection so that
+/// refreshing the scraped data never removes them; they are applied whenever recipes are loaded.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RecipeOverride {
+    pub id: String,
+    /// Item whose recipes are targeted. Ignored for `add`, which uses the recipe's own output.
+    pub output_item: String,
+    /// Only target the recipe made in this facility. Targets every recipe of the item when unset.
+    pub facility: Option<String>,
+    #[serde(flatten)]
+    pub action: OverrideAction,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum OverrideAction {
+    Patch { patch: RecipePatch },
+    Replace { recipe: Recipe },
+    Add { recipe: Recipe },
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+pub struct RecipePatch {
+    pub output_item_count: Option<f64>,
+    pub min_output_item_count: Option<f64>,
+    pub max_output_item_count: Option<f64>,
+    pub facility: Option<String>,
+    pub facilities: Option<Vec<String>>,
+    pub time: Option<f64>,
+    #[schema(value_type = Option<HashMap<String, f64>>)]
+    pub materials: Option<Materials>,
+    pub image: Option<String>,
+    pub technology: Option<String>,
+}
+
+/// Another name for an item, e.g. an abbreviation like `SMR` for Super-Magnetic Ring.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ItemAlias {
+    /// Taken from the url when saved through the api.
+    #[serde(default)]
+    pub alias: String,
+    /// Name of the item the alias stands for.
+    pub item: String,
+    /// Language of the alias, for localized names the wiki doesn't list.
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+/// A snapshot of a recipe dataset that was promoted to be the active one.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct RecipeVersion {
+    pub version: i64,
+    pub created_at: i64,
+    /// Where the dataset came from, e.g. `scrape` or `import:csv`.
+    pub source: String,
+    pub recipe_count: usize,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recipes: Vec<Recipe>,
+}
@@ -1,9 +1,70 @@
 use std::env;
 
-use super::Recipe;
+use super::{Facility, Item, ItemAlias, Recipe, RecipeOverride, RecipeVersion, Technology};
+use crate::error::{AppError, AppResult};
+use crate::scrape::report::ScrapeReport;
 use futures::TryStreamExt;
 use mongodb::bson::Document;
-use mongodb::{bson::doc, Client, Collection};
+use mongodb::error::{ErrorKind, WriteError, WriteFailure};
+use mongodb::options::{
+    Collation, CollationStrength, DeleteOptions, FindOneOptions, FindOptions, IndexOptions,
+    ReplaceOptions,
+};
+use mongodb::{bson::doc, Client, Collection, Database, IndexModel};
+use serde::Serialize;
+
+/// Recipe names and facilities are matched case-insensitively, so the indexes and the queries
+/// that use them must share this collation.
+fn case_insensitive_collation() -> Collation {
+    Collation::builder()
+        .locale("en")
+        .strength(CollationStrength::Secondary)
+        .build()
+}
+
+const DATABASE: &str = "dsp";
+
+/// Mongo's error code for a write violating a unique index.
+const DUPLICATE_KEY: i32 = 11000;
+
+/// Indexes for the recipe queries.
+fn recipe_indexes() -> Vec<IndexModel> {
+    ["output_item", "facility", "facilities", "time"]
+        .map(|field| {
+            IndexModel::builder()
+                .keys(doc! { field: 1 })
+                .options(
+                    IndexOptions::builder()
+                        .collation(case_insensitive_collation())
+                        .build(),
+                )
+                .build()
+        })
+        .to_vec()
+}
+
+/// The unique name index of the `items`, `facilities` and `technologies` collections.
+fn name_index() -> IndexModel {
+    IndexModel::builder()
+        .keys(doc! { "name": 1 })
+        .options(
+            IndexOptions::builder()
+                .collation(case_insensitive_collation())
+                .unique(true)
+                .build(),
+        )
+        .build()
+}
+
+fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    matches!(
+        err.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(WriteError {
+            code: DUPLICATE_KEY,
+            ..
+        }))
+    )
+}
 
 #[derive(Debug)]
 pub struct DB {}
@@ -13,11 +74,19 @@ impl DB {
         Self {}
     }
 
+    async fn client(&self) -> AppResult<Client> {
+        let uri = env::var("MONGODB_URI")
+            .map_err(|_| AppError::DatabaseUnavailable("MONGODB_URI is not set".to_string()))?;
+        Ok(Client::with_uri_str(uri).await?)
+    }
+
+    async fn database(&self) -> AppResult<Database> {
+        Ok(self.client().await?.database(DATABASE))
+    }
+
     #[tracing::instrument]
-    pub async fn test(&self) -> mongodb::error::Result<()> {
-        let uri = env::var("MONGODB_URI").unwrap();
-        let client = Client::with_uri_str(uri).await?;
-        let database: mongodb::Database = client.database("dsp");
+    pub async fn test(&self) -> AppResult<()> {
+        let database = self.database().await?;
         let recipes_coll: Collection<Document> = database.collection("recipes");
         // Find a movie based on the title value
         let recipe = recipes_coll
@@ -29,45 +98,428 @@ impl DB {
     }
 
     #[tracing::instrument]
-    pub async fn get_recipes(&self) -> mongodb::error::Result<Vec<Recipe>> {
-        let uri = env::var("MONGODB_URI").unwrap();
-        let client = Client::with_uri_str(uri).await?;
-        let database: mongodb::Database = client.database("dsp");
+    pub async fn get_recipes(&self) -> AppResult<Vec<Recipe>> {
+        let database = self.database().await?;
         let recipes_coll: Collection<Recipe> = database.collection("recipes");
         let recipes = recipes_coll.find(doc! {}, None).await?;
         let recipes = recipes.try_collect().await?;
         Ok(recipes)
     }
 
+    /// Finds stored recipes matching `filter`, or whose output item is one of `output_items`.
     #[tracing::instrument]
-    pub async fn delete_recipes(&self) -> mongodb::error::Result<()> {
-        let uri = env::var("MONGODB_URI").unwrap();
-        let client = Client::with_uri_str(uri).await?;
-        let database: mongodb::Database = client.database("dsp");
+    pub async fn find_recipes(
+        &self,
+        filter: Document,
+        output_items: Vec<String>,
+    ) -> AppResult<Vec<Recipe>> {
+        let database = self.database().await?;
+        let recipes_coll: Collection<Recipe> = database.collection("recipes");
+        let filter = if output_items.is_empty() {
+            filter
+        } else {
+            doc! { "$or": [filter, { "output_item": { "$in": output_items } }] }
+        };
+        let options = FindOptions::builder()
+            .collation(case_insensitive_collation())
+            .build();
+        let recipes = recipes_coll.find(filter, options).await?;
+        let recipes = recipes.try_collect().await?;
+        Ok(recipes)
+    }
+
+    #[tracing::instrument]
+    pub async fn ensure_recipe_indexes(&self) -> AppResult<()> {
+        let database = self.database().await?;
         let recipes_coll: Collection<Document> = database.collection("recipes");
+        recipes_coll.create_indexes(recipe_indexes(), None).await?;
+        let versions_coll: Collection<Document> = database.collection("recipe_versions");
+        let index = IndexModel::builder()
+            .keys(doc! { "version": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        versions_coll.create_index(index, None).await?;
+        Ok(())
+    }
 
-        recipes_coll.delete_many(doc! {}, None).await?;
+    /// Replaces every stored recipe with `recipes` of dataset `version` in one step. They are
+    /// written to a staging collection which is then renamed over `recipes`, so readers never
+    /// see a partial dataset and a failed write leaves the stored recipes intact.
+    #[tracing::instrument(skip(recipes))]
+    pub async fn replace_recipes(&self, version: i64, recipes: Vec<Recipe>) -> AppResult<()> {
+        let staging = format!("recipes_staging_{}", version);
+        self.replace_collection("recipes", &staging, recipe_indexes(), recipes)
+            .await
+    }
 
+    /// Replaces the stored recipes of `output_items` with `recipes`, leaving other items intact.
+    #[tracing::instrument(skip(recipes))]
+    pub async fn replace_recipes_of(
+        &self,
+        output_items: Vec<String>,
+        recipes: Vec<Recipe>,
+    ) -> AppResult<()> {
+        let database = self.database().await?;
+        let recipes_coll: Collection<Recipe> = database.collection("recipes");
+        let options = DeleteOptions::builder()
+            .collation(case_insensitive_collation())
+            .build();
+        recipes_coll
+            .delete_many(doc! { "output_item": { "$in": output_items } }, options)
+            .await?;
+        if !recipes.is_empty() {
+            recipes_coll.insert_many(recipes, None).await?;
+        }
         Ok(())
     }
 
     #[tracing::instrument]
-    pub async fn save_recipes(&self, recipes: Vec<Recipe>) -> mongodb::error::Result<()> {
-        let uri = env::var("MONGODB_URI").unwrap();
-        let client = Client::with_uri_str(uri).await?;
-        let database = client.database("dsp");
+    pub async fn save_recipe(&self, recipe: Recipe) -> AppResult<()> {
+        let database = self.database().await?;
         let recipes_coll: Collection<Recipe> = database.collection("recipes");
-        recipes_coll.insert_many(recipes, None).await?;
+        recipes_coll.insert_one(recipe, None).await?;
         Ok(())
     }
 
     #[tracing::instrument]
-    pub async fn save_recipe(&self, recipe: Recipe) -> mongodb::error::Result<()> {
-        let uri = env::var("MONGODB_URI").unwrap();
-        let client = Client::with_uri_str(uri).await?;
-        let database = client.database("dsp");
-        let recipes_coll: Collection<Recipe> = database.collection("recipes");
-        recipes_coll.insert_one(recipe, None).await?;
+    pub async fn get_recipe_overrides(&self) -> AppResult<Vec<RecipeOverride>> {
+        let database = self.database().await?;
+        let overrides_coll: Collection<RecipeOverride> = database.collection("recipe_overrides");
+        let overrides = overrides_coll.find(doc! {}, None).await?;
+        let overrides = overrides.try_collect().await?;
+        Ok(overrides)
+    }
+
+    #[tracing::instrument]
+    pub async fn get_recipe_override(&self, id: &str) -> AppResult<Option<RecipeOverride>> {
+        let database = self.database().await?;
+        let overrides_coll: Collection<RecipeOverride> = database.collection("recipe_overrides");
+        let recipe_override = overrides_coll.find_one(doc! { "id": id }, None).await?;
+        Ok(recipe_override)
+    }
+
+    #[tracing::instrument]
+    pub async fn save_recipe_override(&self, recipe_override: RecipeOverride) -> AppResult<()> {
+        let database = self.database().await?;
+        let overrides_coll: Collection<RecipeOverride> = database.collection("recipe_overrides");
+        let options = ReplaceOptions::builder().upsert(true).build();
+        overrides_coll
+            .replace_one(
+                doc! { "id": recipe_override.id.clone() },
+                recipe_override,
+                options,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Returns whether an override with the given id existed.
+    #[tracing::instrument]
+    pub async fn delete_recipe_override(&self, id: &str) -> AppResult<bool> {
+        let database = self.database().await?;
+        let overrides_coll: Collection<Document> = database.collection("recipe_overrides");
+        let result = overrides_coll.delete_one(doc! { "id": id }, None).await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    #[tracing::instrument]
+    pub async fn get_item_aliases(&self) -> AppResult<Vec<ItemAlias>> {
+        let database = self.database().await?;
+        let aliases_coll: Collection<ItemAlias> = database.collection("item_aliases");
+        let options = FindOptions::builder()
+            .sort(doc! { "alias": 1 })
+            .collation(case_insensitive_collation())
+            .build();
+        let aliases = aliases_coll.find(doc! {}, options).await?;
+        let aliases = aliases.try_collect().await?;
+        Ok(aliases)
+    }
+
+    /// Saves `alias`, replacing the alias with the same name, ignoring case.
+    #[tracing::instrument]
+    pub async fn save_item_alias(&self, alias: ItemAlias) -> AppResult<()> {
+        let database = self.database().await?;
+        let aliases_coll: Collection<ItemAlias> = database.collection("item_aliases");
+        let options = ReplaceOptions::builder()
+            .upsert(true)
+            .collation(case_insensitive_collation())
+            .build();
+        aliases_coll
+            .replace_one(doc! { "alias": alias.alias.clone() }, alias, options)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns whether the alias existed.
+    #[tracing::instrument]
+    pub async fn delete_item_alias(&self, alias: &str) -> AppResult<bool> {
+        let database = self.database().await?;
+        let aliases_coll: Collection<Document> = database.collection("item_aliases");
+        let options = DeleteOptions::builder()
+            .collation(case_insensitive_collation())
+            .build();
+        let result = aliases_coll
+            .delete_one(doc! { "alias": alias }, options)
+            .await?;
+        Ok(result.deleted_count > 0)
+    }
+
+    #[tracing::instrument]
+    pub async fn get_latest_recipe_version_number(&self) -> AppResult<Option<i64>> {
+        let database = self.database().await?;
+        let versions_coll: Collection<RecipeVersion> = database.collection("recipe_versions");
+        let options = FindOneOptions::builder()
+            .sort(doc! { "version": -1 })
+            .projection(doc! { "recipes": 0 })
+            .build();
+        let version = versions_coll.find_one(doc! {}, options).await?;
+        Ok(version.map(|v| v.version))
+    }
+
+    /// Lists every stored version without its recipes.
+    #[tracing::instrument]
+    pub async fn get_recipe_versions(&self) -> AppResult<Vec<RecipeVersion>> {
+        let database = self.database().await?;
+        let versions_coll: Collection<RecipeVersion> = database.collection("recipe_versions");
+        let options = FindOptions::builder()
+            .sort(doc! { "version": -1 })
+            .projection(doc! { "recipes": 0 })
+            .build();
+        let versions = versions_coll.find(doc! {}, options).await?;
+        let versions = versions.try_collect().await?;
+        Ok(versions)
+    }
+
+    /// Returns false when the version number is already taken, e.g. by a concurrent save.
+    #[tracing::instrument(skip(version))]
+    pub async fn save_recipe_version(&self, version: RecipeVersion) -> AppResult<bool> {
+        let database = self.database().await?;
+        let versions_coll: Collection<RecipeVersion> = database.collection("recipe_versions");
+        match versions_coll.insert_one(version, None).await {
+            Ok(_) => Ok(true),
+            Err(err) if is_duplicate_key(&err) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Indexes the `items`, `facilities` and `technologies` collections by name.
+    #[tracing::instrument]
+    pub async fn ensure_item_indexes(&self) -> AppResult<()> {
+        let database = self.database().await?;
+        for collection in ["items", "facilities", "technologies"] {
+            let coll: Collection<Document> = database.collection(collection);
+            coll.create_index(name_index(), None).await?;
+        }
         Ok(())
     }
+
+    #[tracing::instrument]
+    pub async fn get_items(&self) -> AppResult<Vec<Item>> {
+        let database = self.database().await?;
+        let items_coll: Collection<Item> = database.collection("items");
+        let options = FindOptions::builder()
+            .sort(doc! { "name": 1 })
+            .collation(case_insensitive_collation())
+            .build();
+        let items = items_coll.find(doc! {}, options).await?;
+        let items = items.try_collect().await?;
+        Ok(items)
+    }
+
+    /// Looks an item up by name, ignoring case.
+    #[tracing::instrument]
+    pub async fn get_item(&self, name: &str) -> AppResult<Option<Item>> {
+        let database = self.database().await?;
+        let items_coll: Collection<Item> = database.collection("items");
+        let options = FindOneOptions::builder()
+            .collation(case_insensitive_collation())
+            .build();
+        let item = items_coll.find_one(doc! { "name": name }, options).await?;
+        Ok(item)
+    }
+
+    /// Replaces every stored item with `items` in one step.
+    #[tracing::instrument(skip(items))]
+    pub async fn replace_items(&self, items: Vec<Item>) -> AppResult<()> {
+        self.replace_collection("items", "items_staging", vec![name_index()], items)
+            .await
+    }
+
+    /// Inserts `items` or replaces the stored ones with the same name.
+    #[tracing::instrument(skip(items))]
+    pub async fn upsert_items(&self, items: Vec<Item>) -> AppResult<()> {
+        self.upsert_by_name("items", items, |item| &item.name).await
+    }
+
+    #[tracing::instrument]
+    pub async fn get_facilities(&self) -> AppResult<Vec<Facility>> {
+        let database = self.database().await?;
+        let facilities_coll: Collection<Facility> = database.collection("facilities");
+        let options = FindOptions::builder()
+            .sort(doc! { "name": 1 })
+            .collation(case_insensitive_collation())
+            .build();
+        let facilities = facilities_coll.find(doc! {}, options).await?;
+        let facilities = facilities.try_collect().await?;
+        Ok(facilities)
+    }
+
+    /// Replaces every stored facility with `facilities` in one step.
+    #[tracing::instrument(skip(facilities))]
+    pub async fn replace_facilities(&self, facilities: Vec<Facility>) -> AppResult<()> {
+        self.replace_collection(
+            "facilities",
+            "facilities_staging",
+            vec![name_index()],
+            facilities,
+        )
+        .await
+    }
+
+    /// Inserts `facilities` or replaces the stored ones with the same name.
+    #[tracing::instrument(skip(facilities))]
+    pub async fn upsert_facilities(&self, facilities: Vec<Facility>) -> AppResult<()> {
+        self.upsert_by_name("facilities", facilities, |facility| &facility.name)
+            .await
+    }
+
+    #[tracing::instrument]
+    pub async fn get_technologies(&self) -> AppResult<Vec<Technology>> {
+        let database = self.database().await?;
+        let technologies_coll: Collection<Technology> = database.collection("technologies");
+        let options = FindOptions::builder()
+            .sort(doc! { "name": 1 })
+            .collation(case_insensitive_collation())
+            .build();
+        let technologies = technologies_coll.find(doc! {}, options).await?;
+        let technologies = technologies.try_collect().await?;
+        Ok(technologies)
+    }
+
+    /// Replaces every stored technology with `technologies` in one step.
+    #[tracing::instrument(skip(technologies))]
+    pub async fn replace_technologies(&self, technologies: Vec<Technology>) -> AppResult<()> {
+        self.replace_collection(
+            "technologies",
+            "technologies_staging",
+            vec![name_index()],
+            technologies,
+        )
+        .await
+    }
+
+    /// Inserts `technologies` or replaces the stored ones with the same name.
+    #[tracing::instrument(skip(technologies))]
+    pub async fn upsert_technologies(&self, technologies: Vec<Technology>) -> AppResult<()> {
+        self.upsert_by_name("technologies", technologies, |technology| &technology.name)
+            .await
+    }
+
+    #[tracing::instrument(skip(report))]
+    pub async fn save_scrape_report(&self, report: ScrapeReport) -> AppResult<()> {
+        let database = self.database().await?;
+        let reports_coll: Collection<ScrapeReport> = database.collection("scrape_reports");
+        reports_coll.insert_one(report, None).await?;
+        Ok(())
+    }
+
+    /// Lists every stored scrape report without its pages, newest first.
+    #[tracing::instrument]
+    pub async fn get_scrape_reports(&self) -> AppResult<Vec<ScrapeReport>> {
+        let database = self.database().await?;
+        let reports_coll: Collection<ScrapeReport> = database.collection("scrape_reports");
+        let options = FindOptions::builder()
+            .sort(doc! { "started_at": -1 })
+            .projection(doc! { "pages": 0 })
+            .build();
+        let reports = reports_coll.find(doc! {}, options).await?;
+        let reports = reports.try_collect().await?;
+        Ok(reports)
+    }
+
+    #[tracing::instrument]
+    pub async fn get_scrape_report(&self, id: &str) -> AppResult<Option<ScrapeReport>> {
+        let database = self.database().await?;
+        let reports_coll: Collection<ScrapeReport> = database.collection("scrape_reports");
+        let report = reports_coll.find_one(doc! { "id": id }, None).await?;
+        Ok(report)
+    }
+
+    #[tracing::instrument]
+    pub async fn set_scrape_report_promoted(&self, id: &str) -> AppResult<()> {
+        let database = self.database().await?;
+        let reports_coll: Collection<Document> = database.collection("scrape_reports");
+        reports_coll
+            .update_one(
+                doc! { "id": id },
+                doc! { "$set": { "promoted": true, "error": null } },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Writes `entries` to the `staging` collection with `indexes` and renames it over
+    /// `collection`, dropping the old one.
+    async fn replace_collection<T>(
+        &self,
+        collection: &str,
+        staging: &str,
+        indexes: Vec<IndexModel>,
+        entries: Vec<T>,
+    ) -> AppResult<()>
+    where
+        T: Serialize + Send + Sync,
+    {
+        let client = self.client().await?;
+        let database = client.database(DATABASE);
+        let staging_coll: Collection<T> = database.collection(staging);
+        // left over from an earlier attempt that failed
+        staging_coll.drop(None).await?;
+        database.create_collection(staging, None).await?;
+        staging_coll.create_indexes(indexes, None).await?;
+        if !entries.is_empty() {
+            staging_coll.insert_many(entries, None).await?;
+        }
+        client
+            .database("admin")
+            .run_command(
+                doc! {
+                    "renameCollection": format!("{}.{}", DATABASE, staging),
+                    "to": format!("{}.{}", DATABASE, collection),
+                    "dropTarget": true,
+                },
+                None,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn upsert_by_name<T>(
+        &self,
+        collection: &str,
+        entries: Vec<T>,
+        name: impl Fn(&T) -> &String,
+    ) -> AppResult<()>
+    where
+        T: Serialize + Send + Sync,
+    {
+        let database = self.database().await?;
+        let coll: Collection<T> = database.collection(collection);
+        for entry in entries {
+            let options = ReplaceOptions::builder()
+                .upsert(true)
+                .collation(case_insensitive_collation())
+                .build();
+            coll.replace_one(doc! { "name": name(&entry) }, &entry, options)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for DB {
+    fn default() -> Self {
+        Self::new()
+    }
 }
@@ -0,0 +1,172 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use utoipa::ToSchema;
+
+use crate::data::Recipe;
+
+/// Differences between two recipe datasets.
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct RecipeDiff {
+    pub added: Vec<Recipe>,
+    pub removed: Vec<Recipe>,
+    pub changed: Vec<RecipeChange>,
+    pub unchanged: usize,
+}
+
+impl RecipeDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RecipeChange {
+    pub before: Recipe,
+    pub after: Recipe,
+    /// Names of the fields that differ.
+    pub fields: Vec<String>,
+}
+
+/// Matches the recipes of `old` and `new` by output item and material names, which tells the
+/// alternate recipes of an item apart. Recipes sharing both are matched in order.
+pub fn diff_recipes(old: &[Recipe], new: &[Recipe]) -> RecipeDiff {
+    let mut remaining: HashMap<String, VecDeque<&Recipe>> = HashMap::new();
+    for recipe in old {
+        remaining
+            .entry(recipe_key(recipe))
+            .or_default()
+            .push_back(recipe);
+    }
+
+    let mut diff = RecipeDiff::default();
+    for recipe in new {
+        let before = remaining
+            .get_mut(&recipe_key(recipe))
+            .and_then(|recipes| recipes.pop_front());
+        let Some(before) = before else {
+            diff.added.push(recipe.clone());
+            continue;
+        };
+        let fields = changed_fields(before, recipe);
+        if fields.is_empty() {
+            diff.unchanged += 1;
+        } else {
+            diff.changed.push(RecipeChange {
+                before: before.clone(),
+                after: recipe.clone(),
+                fields,
+            });
+        }
+    }
+
+    // keep the order of `old` for the recipes left over
+    for recipe in old {
+        let key = recipe_key(recipe);
+        if let Some(recipes) = remaining.get_mut(&key) {
+            if recipes
+                .front()
+                .is_some_and(|left| std::ptr::eq(*left, recipe))
+            {
+                recipes.pop_front();
+                diff.removed.push(recipe.clone());
+            }
+        }
+    }
+    diff
+}
+
+fn recipe_key(recipe: &Recipe) -> String {
+    let mut materials: Vec<String> = recipe
+        .materials
+        .keys()
+        .map(|name| name.to_lowercase())
+        .collect();
+    materials.sort();
+    format!(
+        "{}<-{}",
+        recipe.output_item.to_lowercase(),
+        materials.join(",")
+    )
+}
+
+fn changed_fields(before: &Recipe, after: &Recipe) -> Vec<String> {
+    let (Ok(Value::Object(before)), Ok(Value::Object(after))) =
+        (serde_json::to_value(before), serde_json::to_value(after))
+    else {
+        return vec![];
+    };
+    let mut fields: Vec<String> = before
+        .keys()
+        .chain(after.keys())
+        .filter(|field| before.get(*field) != after.get(*field))
+        .cloned()
+        .collect();
+    fields.sort();
+    fields.dedup();
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipe(output_item: &str, materials: &[(&str, f64)], time: f64) -> Recipe {
+        let mut recipe = Recipe::new();
+        recipe.output_item = output_item.to_string();
+        recipe.output_item_count = 1.0;
+        recipe.time = time;
+        recipe.materials = materials
+            .iter()
+            .map(|(name, count)| (name.to_string(), *count))
+            .collect();
+        recipe
+    }
+
+    #[test]
+    fn test_diff_recipes() {
+        let old = vec![
+            recipe("Iron Ingot", &[("Iron Ore", 1.0)], 1.0),
+            recipe("Magnet", &[("Iron Ore", 1.0)], 1.5),
+            recipe("Graphene", &[("Energetic Graphite", 3.0)], 3.0),
+            recipe("Graphene", &[("Fire Ice", 2.0)], 2.0),
+        ];
+        let new = vec![
+            recipe("Iron Ingot", &[("Iron Ore", 1.0)], 1.0),
+            recipe("magnet", &[("iron ore", 1.0)], 1.5),
+            recipe("Graphene", &[("Fire Ice", 2.0)], 4.0),
+            recipe(
+                "Magnetic Coil",
+                &[("Magnet", 2.0), ("Copper Ingot", 1.0)],
+                1.0,
+            ),
+        ];
+
+        let diff = diff_recipes(&old, &new);
+        assert_eq!(diff.unchanged, 1);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].output_item, "Magnetic Coil");
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(
+            diff.removed[0].materials.get("Energetic Graphite"),
+            Some(&3.0)
+        );
+
+        let fields: Vec<(&str, Vec<String>)> = diff
+            .changed
+            .iter()
+            .map(|change| (change.before.output_item.as_str(), change.fields.clone()))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                (
+                    "Magnet",
+                    vec!["materials".to_string(), "output_item".to_string()]
+                ),
+                ("Graphene", vec!["time".to_string()]),
+            ]
+        );
+        assert!(diff_recipes(&old, &old).is_empty());
+    }
+}
@@ -1,26 +1,67 @@
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
 
 use crate::{
-    data::{self, Recipe},
-    scrape::Scraper,
+    aliases::{self, AliasTable, ItemMatch, ItemSearch},
+    data::{self, Facility, Item, ItemAlias, Recipe, RecipeVersion, Technology},
+    diff::{self, RecipeDiff},
+    error::{AppError, AppResult},
+    optimizer::Optimizer,
+    overrides,
+    plan::{self, Plan},
+    query::{RecipePage, RecipeQuery},
+    research::{self, Research},
+    scrape::{
+        self,
+        images::ImageStore,
+        report::{self, PageReport, ScrapeReport},
+        ScrapeConfig, ScrapeObserver, ScrapeSummary, Scraper,
+    },
     timekeeper::TimeKeeper,
+    transfer::{self, DatasetFormat},
+    validate::{ValidationReport, Validator},
 };
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct ComputedRecipeRequest {
+    /// Item to produce.
     pub name: String,
+    /// Items to produce per second.
     pub rate: f64,
+    /// Index of the recipe to use per item name, for items with alternate recipes.
+    #[schema(value_type = HashMap<String, i64>)]
     pub requirements: RecipeRequirements,
+    /// Facility to use per item name.
+    #[serde(default)]
+    #[schema(value_type = HashMap<String, String>)]
+    pub facilities: FacilityChoices,
+    /// Facilities to prefer for items without an entry in `facilities`, e.g. the highest tier
+    /// of assembler that has been built.
+    #[serde(default)]
+    pub preferred_facilities: Vec<String>,
+    /// Technologies researched so far. When set, only unlocked recipes are used and requests
+    /// needing more research fail with the missing technologies. Recipe indexes in
+    /// `requirements` then refer to the unlocked recipes of each item.
+    #[serde(default)]
+    pub researched: Option<Vec<String>>,
 }
 
 pub type RecipeRequirements = HashMap<String, i64>;
 
-#[derive(Debug, Clone, Serialize)]
+pub type FacilityChoices = HashMap<String, String>;
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct ComputedRecipe {
     pub output_item: String,
     pub facility: String,
     pub num_facilities_needed: f64,
+    /// Power drawn by all the facilities while working, in watts. Unknown without facility
+    /// stats.
+    pub power_usage: Option<f64>,
     pub items_consumed_per_sec: HashMap<String, f64>,
     pub seconds_spent_per_craft: f64,
     pub crafting_per_sec: f64,
@@ -30,51 +71,808 @@ pub struct ComputedRecipe {
 }
 
 #[tracing::instrument]
-pub async fn load_recipes() -> HashMap<String, Vec<Recipe>> {
+pub async fn load_recipes() -> AppResult<HashMap<String, Vec<Recipe>>> {
     let db = data::dsp::DB::new();
-    let recipes = db.get_recipes().await.unwrap();
+    let recipes = db.get_recipes().await?;
 
-    let mut recipe_map = HashMap::new();
+    let mut recipe_map = group_recipes(recipes);
+
+    let recipe_overrides = db.get_recipe_overrides().await?;
+    overrides::apply_overrides(&mut recipe_map, &recipe_overrides);
+
+    Ok(recipe_map)
+}
 
+/// Recipes by lowercase output item.
+pub fn group_recipes(recipes: Vec<Recipe>) -> HashMap<String, Vec<Recipe>> {
+    let mut recipe_map = HashMap::new();
     for recipe in recipes {
         let entry = recipe_map
             .entry(recipe.output_item.clone().to_lowercase())
             .or_insert(vec![]);
         entry.push(recipe);
     }
-
     recipe_map
 }
 
+fn check_rate(rate: f64) -> AppResult<()> {
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err(AppError::InvalidRequest(format!(
+            "rate must be a positive number, got {}",
+            rate
+        )));
+    }
+    Ok(())
+}
+
+#[tracing::instrument]
+pub async fn compute_recipes(request: ComputedRecipeRequest) -> AppResult<Vec<ComputedRecipe>> {
+    check_rate(request.rate)?;
+    let recipes = load_recipes().await?;
+    let db = data::dsp::DB::new();
+    let facilities = db.get_facilities().await?;
+    let technologies = match request.researched {
+        Some(_) => db.get_technologies().await?,
+        None => vec![],
+    };
+    let aliases = load_aliases().await?;
+    plan_recipes(request, recipes, facilities, &technologies, aliases)
+}
+
+/// Plans `request` like `compute_recipes`, as a tree of steps with the totals of the chain.
+#[tracing::instrument]
+pub async fn compute_plan(request: ComputedRecipeRequest) -> AppResult<Plan> {
+    let steps = compute_recipes(request).await?;
+    plan::build_plan(steps).ok_or_else(|| AppError::Internal("the plan has no steps".to_string()))
+}
+
+/// Plans `request` with the given recipes, by lowercase output item, and facility stats.
+/// `technologies` is only needed to name the prerequisites of missing research. Item names of
+/// the request may be any of the names in `aliases`.
+pub fn plan_recipes(
+    request: ComputedRecipeRequest,
+    recipes: HashMap<String, Vec<Recipe>>,
+    facilities: Vec<Facility>,
+    technologies: &[Technology],
+    aliases: AliasTable,
+) -> AppResult<Vec<ComputedRecipe>> {
+    check_rate(request.rate)?;
+    let request = resolve_names(request, &aliases);
+    if !recipes.contains_key(&request.name.to_lowercase()) {
+        return Err(AppError::NotFound(format!(
+            "no recipe found for item {}",
+            request.name
+        )));
+    }
+
+    for (item_name, facility) in request.facilities.iter() {
+        let item_recipes = recipes
+            .get(&item_name.to_lowercase())
+            .ok_or_else(|| AppError::InvalidRequest(format!("unknown item {}", item_name)))?;
+        if !item_recipes
+            .iter()
+            .any(|recipe| recipe.find_facility(facility).is_some())
+        {
+            let mut eligible: Vec<&str> = item_recipes
+                .iter()
+                .flat_map(|recipe| recipe.eligible_facilities())
+                .collect();
+            eligible.sort();
+            eligible.dedup();
+            return Err(AppError::InvalidRequest(format!(
+                "{} cannot be crafted in {}, use one of: {}",
+                item_name,
+                facility,
+                eligible.join(", ")
+            )));
+        }
+    }
+
+    let mut optimizer = Optimizer::new();
+    optimizer.set_aliases(aliases);
+    optimizer.set_facilities(facilities);
+    optimizer.set_facility_choices(request.facilities, request.preferred_facilities);
+
+    let Some(researched) = request.researched.as_deref().map(Research::new) else {
+        optimizer.set_recipes(recipes);
+        let mut seen = HashMap::new();
+        return Ok(optimizer.get_optimal_recipe(
+            request.name,
+            request.rate,
+            "".to_string(),
+            &mut seen,
+            0,
+            request.requirements,
+        ));
+    };
+
+    // Plan with the unlocked recipes. Items left without one need research, which may in turn
+    // unlock recipes consuming other locked items, so plan again until nothing is blocked.
+    let mut research = researched.clone();
+    let mut missing: Vec<String> = vec![];
+    loop {
+        let mut unlocked = recipes.clone();
+        let locked = research.lock_recipes(&mut unlocked);
+        optimizer.set_recipes(unlocked);
+        let mut seen = HashMap::new();
+        let plan = optimizer.get_optimal_recipe(
+            request.name.clone(),
+            request.rate,
+            "".to_string(),
+            &mut seen,
+            0,
+            request.requirements.clone(),
+        );
+
+        let blocking = research::blocking_technologies(&request.name, &plan, &locked);
+        if blocking.is_empty() {
+            if missing.is_empty() {
+                return Ok(plan);
+            }
+            return Err(AppError::MissingTechnologies(research::with_prerequisites(
+                missing,
+                technologies,
+                &researched,
+            )));
+        }
+        for technology in blocking {
+            research.add(&technology);
+            missing.push(technology);
+        }
+    }
+}
+
+/// Replaces the item names of `request` with the names the recipes use.
+fn resolve_names(request: ComputedRecipeRequest, aliases: &AliasTable) -> ComputedRecipeRequest {
+    let canonical = |name: String| match aliases.resolve(&name) {
+        Some(item) => item.to_string(),
+        None => name,
+    };
+    ComputedRecipeRequest {
+        name: canonical(request.name),
+        requirements: request
+            .requirements
+            .into_iter()
+            .map(|(name, index)| (canonical(name), index))
+            .collect(),
+        facilities: request
+            .facilities
+            .into_iter()
+            .map(|(name, facility)| (canonical(name), facility))
+            .collect(),
+        ..request
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ItemDetail {
+    #[serde(flatten)]
+    pub item: Item,
+    /// Recipes producing the item, with overrides applied.
+    pub recipes: Vec<Recipe>,
+    /// Items whose recipes consume this item.
+    pub used_in: Vec<String>,
+}
+
+#[tracing::instrument]
+pub async fn get_items() -> AppResult<Vec<Item>> {
+    data::dsp::DB::new().get_items().await
+}
+
+#[tracing::instrument]
+pub async fn get_facilities() -> AppResult<Vec<Facility>> {
+    data::dsp::DB::new().get_facilities().await
+}
+
+#[tracing::instrument]
+pub async fn get_technologies() -> AppResult<Vec<Technology>> {
+    data::dsp::DB::new().get_technologies().await
+}
+
+/// The alias table built from the stored items and aliases, until a refresh or an alias change
+/// invalidates it.
+static ALIAS_TABLE: Lazy<RwLock<Option<AliasTable>>> = Lazy::new(Default::default);
+
+/// Names items can be looked up by: their own, their localized names and the user-defined
+/// aliases. Built once and kept until the items or aliases change.
+#[tracing::instrument]
+pub async fn load_aliases() -> AppResult<AliasTable> {
+    if let Some(table) = ALIAS_TABLE.read().unwrap().as_ref() {
+        return Ok(table.clone());
+    }
+    let db = data::dsp::DB::new();
+    let items = db.get_items().await?;
+    let aliases = db.get_item_aliases().await?;
+    let table = AliasTable::new(&items, &aliases);
+    *ALIAS_TABLE.write().unwrap() = Some(table.clone());
+    Ok(table)
+}
+
+/// Drops the cached alias table, so that the next lookup rebuilds it.
+fn invalidate_aliases() {
+    *ALIAS_TABLE.write().unwrap() = None;
+}
+
 #[tracing::instrument]
-pub async fn refresh_data() {
-    let s = Scraper::new();
-    // use empty vec to scrape all recipes
-    let urls = vec![
-        // "https://dsp-wiki.com/Stone_Brick".to_string(),
-        // "https://dsp-wiki.com/Storage_Mk.I".to_string(),
-        // "https://dsp-wiki.com/Storage_Mk.II".to_string(),
-        // "https://dsp-wiki.com/Storage_Tank".to_string(),
-        // "https://dsp-wiki.com/Strange_Matter".to_string(),
-        // "https://dsp-wiki.com/Structure_Matrix".to_string(),
-        // "https://dsp-wiki.com/Sulfuric_Acid".to_string(),
-        // "https://dsp-wiki.com/Super-Magnetic_Ring".to_string(),
-        // "https://dsp-wiki.com/Tesla_Tower".to_string(),
-        // "https://dsp-wiki.com/Thermal_Power_Plant".to_string(),
-        // "https://dsp-wiki.com/Thruster".to_string(),
-        // "https://dsp-wiki.com/Titanium_Alloy".to_string(),
-    ];
-    let recipe_lists = s.scrape_dsp_data(urls).await;
+pub async fn search_items(search: ItemSearch) -> AppResult<Vec<ItemMatch>> {
+    Ok(load_aliases().await?.search(&search.q, search.limit))
+}
+
+#[tracing::instrument]
+pub async fn get_item_aliases() -> AppResult<Vec<ItemAlias>> {
+    data::dsp::DB::new().get_item_aliases().await
+}
+
+/// Stores a user-defined alias for a known item. Names of other items can't be aliases since
+/// they would never resolve to the alias.
+#[tracing::instrument]
+pub async fn save_item_alias(mut alias: ItemAlias) -> AppResult<ItemAlias> {
+    alias.alias = alias.alias.trim().to_string();
+    if aliases::normalize(&alias.alias).is_empty() {
+        return Err(AppError::InvalidRequest(
+            "alias must not be empty".to_string(),
+        ));
+    }
+    let table = load_aliases().await?;
+    let Some(item) = table.resolve(&alias.item) else {
+        return Err(AppError::InvalidRequest(format!(
+            "unknown item {}",
+            alias.item
+        )));
+    };
+    if table.is_item_name(&alias.alias) && table.resolve(&alias.alias) != Some(item) {
+        return Err(AppError::InvalidRequest(format!(
+            "{} is the name of another item",
+            alias.alias
+        )));
+    }
+    alias.item = item.to_string();
+    data::dsp::DB::new().save_item_alias(alias.clone()).await?;
+    invalidate_aliases();
+    Ok(alias)
+}
 
+#[tracing::instrument]
+pub async fn delete_item_alias(alias: &str) -> AppResult<()> {
+    if !data::dsp::DB::new().delete_item_alias(alias).await? {
+        return Err(AppError::NotFound(format!("alias {} not found", alias)));
+    }
+    invalidate_aliases();
+    Ok(())
+}
+
+/// Looks the item up by any of its names.
+#[tracing::instrument]
+pub async fn get_item(name: &str) -> AppResult<ItemDetail> {
+    let aliases = load_aliases().await?;
+    let item = data::dsp::DB::new()
+        .get_item(aliases.canonical(name))
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("item {} not found", name)))?;
+
+    let recipe_map = load_recipes().await?;
+    let recipes = recipe_map
+        .get(&item.name.to_lowercase())
+        .cloned()
+        .unwrap_or_default();
+    let mut used_in: Vec<String> = recipe_map
+        .values()
+        .flatten()
+        .filter(|recipe| {
+            recipe
+                .materials
+                .keys()
+                .any(|material| material.eq_ignore_ascii_case(&item.name))
+        })
+        .map(|recipe| recipe.output_item.clone())
+        .collect();
+    used_in.sort();
+    used_in.dedup();
+
+    Ok(ItemDetail {
+        item,
+        recipes,
+        used_in,
+    })
+}
+
+/// Runs `query` against the stored recipes with overrides applied. The indexed filters are
+/// pushed down to Mongo; recipes targeted by an override are always fetched since the override
+/// may change whether they match.
+#[tracing::instrument]
+pub async fn query_recipes(query: RecipeQuery) -> AppResult<RecipePage> {
     let db = data::dsp::DB::new();
-    let _ = db.delete_recipes().await;
+    let recipe_overrides = db.get_recipe_overrides().await?;
+    let overridden_items = recipe_overrides
+        .iter()
+        .map(|o| o.output_item.clone())
+        .collect();
+    let recipes = db
+        .find_recipes(query.mongo_filter(), overridden_items)
+        .await?;
+
+    let mut recipe_map: HashMap<String, Vec<Recipe>> = HashMap::new();
+    for recipe in recipes {
+        recipe_map
+            .entry(recipe.output_item.to_lowercase())
+            .or_default()
+            .push(recipe);
+    }
+    overrides::apply_overrides(&mut recipe_map, &recipe_overrides);
+
+    Ok(query.apply(recipe_map.into_values().flatten().collect()))
+}
+
+#[tracing::instrument]
+pub async fn validate_recipes() -> AppResult<ValidationReport> {
+    let recipes: Vec<Recipe> = load_recipes().await?.into_values().flatten().collect();
+    Ok(Validator::new().validate(&recipes))
+}
 
-    let mut timekeeper = TimeKeeper::new();
-    println!("Start Save Recipes {:?}", timekeeper.start());
-    let flattened_recipe_lists: Vec<Recipe> = recipe_lists
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct RefreshRequest {
+    /// Item names or wiki urls to refresh. Everything is refreshed when empty.
+    #[serde(default)]
+    pub items: Vec<String>,
+    /// Scrape and validate without storing anything.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RefreshOutcome {
+    pub scrape: ScrapeSummary,
+    pub validation: ValidationReport,
+    /// Items whose recipes a partial refresh replaced. `None` after a full refresh.
+    pub items: Option<Vec<String>>,
+    /// Pages that could not be scraped. The full list is in the run's report.
+    pub failed_pages: Vec<PageReport>,
+    pub report_id: String,
+    pub dry_run: bool,
+    /// Changes a dry run would make to the live recipes.
+    pub diff: Option<RecipeDiff>,
+    /// Scraped data of a dry run, kept until it is promoted or discarded.
+    #[serde(skip)]
+    pub pending: Option<Arc<ScrapedDataset>>,
+}
+
+/// Data of a refresh that has been scraped but not stored.
+#[derive(Debug)]
+pub struct ScrapedDataset {
+    pub recipes: Vec<Recipe>,
+    pub items: Vec<Item>,
+    pub facilities: Vec<Facility>,
+    pub technologies: Vec<Technology>,
+    /// Items whose recipes are replaced. `None` replaces every recipe.
+    pub refreshed_items: Option<Vec<String>>,
+    pub report_id: String,
+    pub attempted_pages: usize,
+    pub failed_pages: usize,
+}
+
+impl ScrapedDataset {
+    /// The recipes stored once `current` is refreshed with this data.
+    pub fn apply_to(&self, current: Vec<Recipe>) -> Vec<Recipe> {
+        match &self.refreshed_items {
+            Some(items) => merge_recipes(current, &self.recipes, items),
+            None => self.recipes.clone(),
+        }
+    }
+
+    async fn store(&self, db: &data::dsp::DB, recipes: Vec<Recipe>) -> AppResult<()> {
+        let mut timekeeper = TimeKeeper::new();
+        timekeeper.start();
+        let recipe_count = recipes.len();
+        if let Some(items) = &self.refreshed_items {
+            save_recipe_version(db, recipes, "scrape:partial").await?;
+            db.replace_recipes_of(items.clone(), self.recipes.clone())
+                .await?;
+            db.upsert_items(self.items.clone()).await?;
+            db.upsert_facilities(self.facilities.clone()).await?;
+            db.upsert_technologies(self.technologies.clone()).await?;
+        } else {
+            promote_recipes(recipes, "scrape").await?;
+            db.replace_items(self.items.clone()).await?;
+            db.replace_facilities(self.facilities.clone()).await?;
+            db.replace_technologies(self.technologies.clone()).await?;
+        }
+        invalidate_aliases();
+        tracing::info!(
+            "Saved {} recipes in {} ms",
+            recipe_count,
+            timekeeper.end().time_since_start
+        );
+        Ok(())
+    }
+}
+
+/// Scrapes the wiki and stores the result. With `items`, item names or wiki urls, only those
+/// pages are scraped and their recipes replaced; everything else is left intact. Without, every
+/// stored recipe is replaced. The scraped data is only promoted when the resulting dataset
+/// passes validation and not too many pages failed; otherwise the error explaining why is
+/// returned. A dry run stores nothing and returns the changes it would make instead, whether
+/// valid or not. A cancelled refresh keeps the current data. Every run leaves a report of the
+/// pages it attempted.
+#[tracing::instrument(skip(observer))]
+pub async fn refresh_data(
+    request: RefreshRequest,
+    observer: Option<Arc<dyn ScrapeObserver>>,
+) -> AppResult<RefreshOutcome> {
+    let mut report = ScrapeReport::new(request.dry_run);
+    let result = run_refresh(request, observer, &mut report).await;
+    report.finish(&result);
+    if let Err(err) = data::dsp::DB::new().save_scrape_report(report).await {
+        tracing::warn!("Unable to save the scrape report: {}", err);
+    }
+    result
+}
+
+async fn run_refresh(
+    request: RefreshRequest,
+    observer: Option<Arc<dyn ScrapeObserver>>,
+    report: &mut ScrapeReport,
+) -> AppResult<RefreshOutcome> {
+    let mut s = Scraper::new()?;
+    if let Some(observer) = observer.clone() {
+        s = s.with_observer(observer);
+    }
+    // an empty list scrapes every item page
+    let mut urls: Vec<String> = request
+        .items
+        .iter()
+        .map(|target| scrape::page_url(&s.config().base_url, target))
+        .collect();
+    urls.sort();
+    urls.dedup();
+    let partial = !urls.is_empty();
+    let run = s.scrape_dsp_data(urls).await?;
+    report.set_pages(run.pages);
+    if observer.is_some_and(|observer| observer.is_cancelled()) {
+        return Err(AppError::Cancelled("refresh was cancelled".to_string()));
+    }
+    let recipes: Vec<Recipe> = run
+        .recipe_lists
         .into_iter()
         .flat_map(|list| list.into_iter())
         .collect();
-    let _ = db.save_recipes(flattened_recipe_lists).await;
-    println!("End Save Recipes {:?}", timekeeper.end());
+    let refreshed_items = if partial {
+        let items = refreshed_items(&recipes);
+        if items.is_empty() {
+            return Err(AppError::ScrapeFailed(
+                "none of the requested pages could be scraped".to_string(),
+            ));
+        }
+        Some(items)
+    } else {
+        None
+    };
+    let scraped = ScrapedDataset {
+        recipes,
+        items: dedup_by_name(run.items, |item| &item.name),
+        facilities: dedup_by_name(run.facilities, |facility| &facility.name),
+        technologies: dedup_by_name(run.technologies, |technology| &technology.name),
+        refreshed_items,
+        report_id: report.id.clone(),
+        attempted_pages: report.attempted,
+        failed_pages: report.failed,
+    };
+
+    let db = data::dsp::DB::new();
+    let current = if request.dry_run || partial {
+        db.get_recipes().await?
+    } else {
+        vec![]
+    };
+    let recipes = scraped.apply_to(current.clone());
+    let validation = Validator::new().validate(&recipes);
+    let mut outcome = RefreshOutcome {
+        scrape: run.summary,
+        validation: validation.clone(),
+        items: scraped.refreshed_items.clone(),
+        failed_pages: report.failed_pages(),
+        report_id: report.id.clone(),
+        dry_run: request.dry_run,
+        diff: None,
+        pending: None,
+    };
+    if request.dry_run {
+        outcome.diff = Some(diff::diff_recipes(&current, &recipes));
+        outcome.pending = Some(Arc::new(scraped));
+        return Ok(outcome);
+    }
+    report::check_failures(report.failed, report.attempted, s.config().max_failed_ratio)?;
+    if !validation.is_valid() {
+        tracing::warn!(
+            "Scraped recipes failed validation with {} errors, keeping current data",
+            validation.error_count
+        );
+        return Err(AppError::ValidationFailed(validation));
+    }
+    scraped.store(&db, recipes).await?;
+    Ok(outcome)
+}
+
+/// Stores the data of a dry run, merged with the live recipes as they are now.
+#[tracing::instrument(skip(scraped))]
+pub async fn promote_scraped(scraped: &ScrapedDataset) -> AppResult<ValidationReport> {
+    report::check_failures(
+        scraped.failed_pages,
+        scraped.attempted_pages,
+        ScrapeConfig::from_env().max_failed_ratio,
+    )?;
+    let db = data::dsp::DB::new();
+    let current = match scraped.refreshed_items {
+        Some(_) => db.get_recipes().await?,
+        None => vec![],
+    };
+    let recipes = scraped.apply_to(current);
+    let report = Validator::new().validate(&recipes);
+    if !report.is_valid() {
+        return Err(AppError::ValidationFailed(report));
+    }
+    scraped.store(&db, recipes).await?;
+    db.set_scrape_report_promoted(&scraped.report_id).await?;
+    Ok(report)
+}
+
+/// A mirrored icon and its content type.
+#[tracing::instrument]
+pub async fn get_image(hash: &str) -> AppResult<(&'static str, Vec<u8>)> {
+    let image = match ScrapeConfig::from_env().image_dir {
+        Some(dir) => ImageStore::new(dir).get(hash).await,
+        None => None,
+    };
+    image.ok_or_else(|| AppError::NotFound(format!("image {} not found", hash)))
+}
+
+/// Stored reports of past refresh runs, newest first, without their pages.
+#[tracing::instrument]
+pub async fn get_scrape_reports() -> AppResult<Vec<ScrapeReport>> {
+    let db = data::dsp::DB::new();
+    db.get_scrape_reports().await
+}
+
+#[tracing::instrument]
+pub async fn get_scrape_report(id: &str) -> AppResult<ScrapeReport> {
+    let db = data::dsp::DB::new();
+    db.get_scrape_report(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("scrape report {} not found", id)))
+}
+
+/// Items a partial refresh replaces: the outputs of the scraped recipes. Pages that failed or
+/// had no production table produced no recipes, so the recipes stored for them are kept.
+fn refreshed_items(recipes: &[Recipe]) -> Vec<String> {
+    let mut names: Vec<String> = recipes
+        .iter()
+        .map(|recipe| recipe.output_item.clone())
+        .collect();
+    names.sort_by_key(|name| name.to_lowercase());
+    names.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+    names
+}
+
+/// The stored recipes with those of `items` replaced by `scraped`.
+pub fn merge_recipes(current: Vec<Recipe>, scraped: &[Recipe], items: &[String]) -> Vec<Recipe> {
+    current
+        .into_iter()
+        .filter(|recipe| {
+            !items
+                .iter()
+                .any(|item| item.eq_ignore_ascii_case(&recipe.output_item))
+        })
+        .chain(scraped.iter().cloned())
+        .collect()
+}
+
+/// Keeps the first entry per name; the same page can be listed under several urls.
+fn dedup_by_name<T>(entries: Vec<T>, name: impl Fn(&T) -> &String) -> Vec<T> {
+    let mut seen = std::collections::HashSet::new();
+    entries
+        .into_iter()
+        .filter(|entry| seen.insert(name(entry).to_lowercase()))
+        .collect()
+}
+
+/// Attempts at claiming a version number before giving up on concurrent saves.
+const VERSION_ATTEMPTS: usize = 5;
+
+/// Stores `recipes` as a new dataset version and makes it the active dataset. The stored
+/// recipes are swapped in one step, so a failure leaves the previous ones active.
+#[tracing::instrument(skip(recipes))]
+pub async fn promote_recipes(recipes: Vec<Recipe>, source: &str) -> AppResult<i64> {
+    let db = data::dsp::DB::new();
+    let version = save_recipe_version(&db, recipes.clone(), source).await?;
+    db.replace_recipes(version, recipes).await?;
+    Ok(version)
+}
+
+/// Stores `recipes` as the next dataset version without activating it. Version numbers are
+/// unique, so a save racing another one takes the number after it.
+async fn save_recipe_version(
+    db: &data::dsp::DB,
+    recipes: Vec<Recipe>,
+    source: &str,
+) -> AppResult<i64> {
+    for _ in 0..VERSION_ATTEMPTS {
+        let version = db
+            .get_latest_recipe_version_number()
+            .await?
+            .map(|version| version + 1)
+            .unwrap_or(1);
+
+        let saved = db
+            .save_recipe_version(RecipeVersion {
+                version,
+                created_at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs() as i64,
+                source: source.to_string(),
+                recipe_count: recipes.len(),
+                recipes: recipes.clone(),
+            })
+            .await?;
+        if saved {
+            return Ok(version);
+        }
+    }
+    Err(AppError::Internal(
+        "unable to claim a recipe dataset version".to_string(),
+    ))
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ImportOutcome {
+    pub version: i64,
+    pub validation: ValidationReport,
+}
+
+/// Exports the stored dataset. Manual overrides are a separate layer and are not included.
+#[tracing::instrument]
+pub async fn export_recipes(format: DatasetFormat) -> AppResult<String> {
+    let db = data::dsp::DB::new();
+    let recipes = db.get_recipes().await?;
+    transfer::export_recipes(&recipes, format).map_err(|err| AppError::Internal(err.to_string()))
+}
+
+/// Validates an imported dataset and promotes it to a new version when it is valid.
+#[tracing::instrument(skip(recipes))]
+pub async fn import_recipes(
+    recipes: Vec<Recipe>,
+    format: DatasetFormat,
+) -> AppResult<ImportOutcome> {
+    let validation = Validator::new().validate(&recipes);
+    if !validation.is_valid() {
+        return Err(AppError::ValidationFailed(validation));
+    }
+
+    let source = format!("import:{}", format.extension());
+    let version = promote_recipes(recipes, &source).await?;
+    Ok(ImportOutcome {
+        version,
+        validation,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipe(output_item: &str, facility: &str) -> Recipe {
+        let mut recipe = Recipe::new();
+        recipe.output_item = output_item.to_string();
+        recipe.facility = facility.to_string();
+        recipe
+    }
+
+    #[test]
+    fn test_merge_recipes() {
+        let current = vec![
+            recipe("Iron Ingot", "Arc Smelter"),
+            recipe("Magnet", "Arc Smelter"),
+            recipe("Magnetic Coil", "Assembling Machine Mk.I"),
+        ];
+        let scraped = vec![recipe("Iron Ingot", "Plane Smelter")];
+        let items = refreshed_items(&scraped);
+        assert_eq!(items, ["Iron Ingot"]);
+
+        let merged = merge_recipes(current, &scraped, &items);
+        let merged: Vec<(&str, &str)> = merged
+            .iter()
+            .map(|recipe| (recipe.output_item.as_str(), recipe.facility.as_str()))
+            .collect();
+        assert_eq!(
+            merged,
+            [
+                ("Magnet", "Arc Smelter"),
+                ("Magnetic Coil", "Assembling Machine Mk.I"),
+                ("Iron Ingot", "Plane Smelter"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_keeps_recipes_of_pages_without_table() {
+        // the Magnet page was scraped but its production table is missing
+        let current = vec![
+            recipe("Iron Ingot", "Arc Smelter"),
+            recipe("Magnet", "Arc Smelter"),
+        ];
+        let scraped = vec![recipe("Iron Ingot", "Plane Smelter")];
+        let items = refreshed_items(&scraped);
+        assert_eq!(items, ["Iron Ingot"]);
+
+        let merged = merge_recipes(current, &scraped, &items);
+        let merged: Vec<(&str, &str)> = merged
+            .iter()
+            .map(|recipe| (recipe.output_item.as_str(), recipe.facility.as_str()))
+            .collect();
+        assert_eq!(
+            merged,
+            [("Magnet", "Arc Smelter"), ("Iron Ingot", "Plane Smelter")]
+        );
+    }
+
+    #[test]
+    fn test_plan_recipes() {
+        let mut ingot = recipe("Iron Ingot", "Arc Smelter");
+        ingot.output_item_count = 1.0;
+        ingot.time = 1.0;
+        ingot.materials = HashMap::from([("Iron Ore".to_string(), 1.0)]);
+        let recipes = group_recipes(vec![ingot]);
+        let request = |name: &str, rate: f64| ComputedRecipeRequest {
+            name: name.to_string(),
+            rate,
+            requirements: HashMap::new(),
+            facilities: HashMap::new(),
+            preferred_facilities: vec![],
+            researched: None,
+        };
+
+        let aliases = AliasTable::new(
+            &[Item::new("Iron Ingot")],
+            &[ItemAlias {
+                alias: "ingot".to_string(),
+                item: "Iron Ingot".to_string(),
+                language: None,
+            }],
+        );
+        let plan = plan_recipes(
+            request("iron ingot", 2.0),
+            recipes.clone(),
+            vec![],
+            &[],
+            AliasTable::default(),
+        )
+        .unwrap();
+        assert_eq!(plan[0].output_item, "Iron Ingot");
+        assert_eq!(plan[0].facility, "Arc Smelter");
+        assert_eq!(plan[0].num_facilities_needed, 2.0);
+
+        let plan =
+            plan_recipes(request("Ingot", 1.0), recipes.clone(), vec![], &[], aliases).unwrap();
+        assert_eq!(plan[0].output_item, "Iron Ingot");
+
+        assert!(matches!(
+            plan_recipes(
+                request("Iron Ingot", 0.0),
+                recipes.clone(),
+                vec![],
+                &[],
+                AliasTable::default()
+            ),
+            Err(AppError::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            plan_recipes(
+                request("Magnet", 1.0),
+                recipes,
+                vec![],
+                &[],
+                AliasTable::default()
+            ),
+            Err(AppError::NotFound(_))
+        ));
+    }
 }
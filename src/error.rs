@@ -0,0 +1,195 @@
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::Serialize;
+use serde_json::json;
+use tracing_opentelemetry_instrumentation_sdk::find_current_trace_id;
+use utoipa::ToSchema;
+
+use crate::scrape::RetryRequestError;
+use crate::transfer::TransferError;
+use crate::validate::ValidationReport;
+
+pub type AppResult<T> = Result<T, AppError>;
+
+#[derive(Debug)]
+pub enum AppError {
+    NotFound(String),
+    InvalidRequest(String),
+    ValidationFailed(ValidationReport),
+    /// The request needs recipes unlocked by technologies that haven't been researched.
+    MissingTechnologies(Vec<String>),
+    DatabaseUnavailable(String),
+    ScrapeFailed(String),
+    /// A background job stopped because it was asked to.
+    Cancelled(String),
+    Internal(String),
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::InvalidRequest(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::ValidationFailed(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::MissingTechnologies(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::DatabaseUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::ScrapeFailed(_) => StatusCode::BAD_GATEWAY,
+            AppError::Cancelled(_) => StatusCode::CONFLICT,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable, machine readable identifier of the error kind.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "not_found",
+            AppError::InvalidRequest(_) => "invalid_request",
+            AppError::ValidationFailed(_) => "validation_failed",
+            AppError::MissingTechnologies(_) => "missing_technologies",
+            AppError::DatabaseUnavailable(_) => "database_unavailable",
+            AppError::ScrapeFailed(_) => "scrape_failed",
+            AppError::Cancelled(_) => "cancelled",
+            AppError::Internal(_) => "internal",
+        }
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::NotFound(message)
+            | AppError::InvalidRequest(message)
+            | AppError::Cancelled(message)
+            | AppError::Internal(message) => write!(f, "{}", message),
+            AppError::ValidationFailed(report) => write!(
+                f,
+                "dataset failed validation with {} errors",
+                report.error_count
+            ),
+            AppError::MissingTechnologies(technologies) => {
+                write!(f, "research {} first", technologies.join(", "))
+            }
+            AppError::DatabaseUnavailable(message) => {
+                write!(f, "database unavailable: {}", message)
+            }
+            AppError::ScrapeFailed(message) => write!(f, "scrape failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+/// Body of every error response.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ErrorBody {
+    /// Stable identifier of the error kind, e.g. `not_found`.
+    pub code: String,
+    pub message: String,
+    pub trace_id: Option<String>,
+    /// The validation report of `validation_failed`, or the `missing_technologies`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub details: Option<serde_json::Value>,
+}
+
+impl From<mongodb::error::Error> for AppError {
+    fn from(err: mongodb::error::Error) -> Self {
+        AppError::DatabaseUnavailable(err.to_string())
+    }
+}
+
+impl From<TransferError> for AppError {
+    fn from(err: TransferError) -> Self {
+        AppError::InvalidRequest(err.to_string())
+    }
+}
+
+impl From<RetryRequestError> for AppError {
+    fn from(err: RetryRequestError) -> Self {
+        AppError::ScrapeFailed(err.to_string())
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        AppError::InvalidRequest(rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::InvalidRequest(rejection.body_text())
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            tracing::error!("{}", self);
+        } else {
+            tracing::warn!("{}", self);
+        }
+
+        let details = match &self {
+            AppError::ValidationFailed(report) => Some(json!(report)),
+            AppError::MissingTechnologies(technologies) => {
+                Some(json!({ "missing_technologies": technologies }))
+            }
+            _ => None,
+        };
+        let body = ErrorResponse {
+            error: ErrorBody {
+                code: self.code().to_string(),
+                message: self.to_string(),
+                trace_id: find_current_trace_id(),
+                details,
+            },
+        };
+
+        (status, axum::Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_codes() {
+        assert_eq!(
+            AppError::NotFound("x".to_string()).status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            AppError::InvalidRequest("x".to_string()).status(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(
+            AppError::DatabaseUnavailable("x".to_string()).status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            AppError::ScrapeFailed("x".to_string()).status(),
+            StatusCode::BAD_GATEWAY
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_body() {
+        let response = AppError::NotFound("item Unobtainium not found".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "not_found");
+        assert_eq!(body["error"]["message"], "item Unobtainium not found");
+        assert!(body["error"].get("trace_id").is_some());
+    }
+}
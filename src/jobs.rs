@@ -0,0 +1,607 @@
+use futures::{stream, Stream, StreamExt};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::{self, error::RecvError};
+use utoipa::ToSchema;
+
+use crate::data::Recipe;
+use crate::dsp::{self, RefreshOutcome, RefreshRequest, ScrapedDataset};
+use crate::error::{AppError, AppResult};
+use crate::scrape::{PageStatus, ScrapeObserver, ScrapedPage};
+
+/// Finished jobs kept around for their status, oldest dropped first.
+const FINISHED_JOBS_KEPT: usize = 20;
+
+/// Events buffered per subscriber. Slow subscribers skip the ones they missed.
+const EVENTS_BUFFERED: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    /// Waiting for the previous refresh to finish.
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobState::Succeeded | JobState::Failed | JobState::Cancelled
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JobStatus {
+    pub id: String,
+    pub state: JobState,
+    pub created_at: i64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+    pub pages_done: usize,
+    /// Pages known so far. Grows while building and technology pages are discovered.
+    pub pages_total: usize,
+    /// Seconds until the known pages are scraped, at the average pace so far.
+    pub eta_seconds: Option<f64>,
+    /// Pages that failed, and the error that ended the job.
+    pub errors: Vec<String>,
+    pub outcome: Option<RefreshOutcome>,
+}
+
+/// What a refresh job reports while it runs.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobEvent {
+    PageStarted {
+        url: String,
+    },
+    RecipeParsed {
+        url: String,
+        recipe: Recipe,
+    },
+    PageFailed {
+        url: String,
+        reason: String,
+    },
+    Progress {
+        state: JobState,
+        pages_done: usize,
+        pages_total: usize,
+        eta_seconds: Option<f64>,
+    },
+    /// The last event of a job, with its summary.
+    Finished {
+        status: JobStatus,
+    },
+}
+
+impl JobEvent {
+    /// The event type, as in the serialized `type` field.
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobEvent::PageStarted { .. } => "page_started",
+            JobEvent::RecipeParsed { .. } => "recipe_parsed",
+            JobEvent::PageFailed { .. } => "page_failed",
+            JobEvent::Progress { .. } => "progress",
+            JobEvent::Finished { .. } => "finished",
+        }
+    }
+
+    fn progress(status: &JobStatus) -> Self {
+        JobEvent::Progress {
+            state: status.state,
+            pages_done: status.pages_done,
+            pages_total: status.pages_total,
+            eta_seconds: status.eta_seconds,
+        }
+    }
+}
+
+/// A refresh running in the background. It follows the scrape to report progress and tells the
+/// scraper to stop once cancelled.
+#[derive(Debug)]
+pub struct RefreshJob {
+    status: Mutex<JobStatus>,
+    started: Mutex<Option<Instant>>,
+    cancelled: AtomicBool,
+    events: broadcast::Sender<JobEvent>,
+}
+
+impl RefreshJob {
+    fn new(id: String) -> Self {
+        Self {
+            status: Mutex::new(JobStatus {
+                id,
+                state: JobState::Queued,
+                created_at: now(),
+                started_at: None,
+                finished_at: None,
+                pages_done: 0,
+                pages_total: 0,
+                eta_seconds: None,
+                errors: vec![],
+                outcome: None,
+            }),
+            started: Mutex::new(None),
+            cancelled: AtomicBool::new(false),
+            events: broadcast::channel(EVENTS_BUFFERED).0,
+        }
+    }
+
+    /// Receives the events sent from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<JobEvent> {
+        self.events.subscribe()
+    }
+
+    fn emit(&self, event: JobEvent) {
+        // Nobody listening is fine.
+        let _ = self.events.send(event);
+    }
+
+    pub fn status(&self) -> JobStatus {
+        let mut status = self.status.lock().unwrap().clone();
+        let started = *self.started.lock().unwrap();
+        if let (JobState::Running, Some(started)) = (status.state, started) {
+            if status.pages_done > 0 {
+                let per_page = started.elapsed().as_secs_f64() / status.pages_done as f64;
+                let remaining = status.pages_total.saturating_sub(status.pages_done);
+                status.eta_seconds = Some(per_page * remaining as f64);
+            }
+        }
+        status
+    }
+
+    /// Asks the job to stop. Returns false for jobs that already finished.
+    pub fn cancel(&self) -> bool {
+        let mut status = self.status.lock().unwrap();
+        if status.state.is_finished() {
+            return false;
+        }
+        self.cancelled.store(true, Ordering::SeqCst);
+        if status.state == JobState::Queued {
+            status.state = JobState::Cancelled;
+            status.finished_at = Some(now());
+            self.emit(JobEvent::Finished {
+                status: status.clone(),
+            });
+        }
+        true
+    }
+
+    fn start(&self) -> bool {
+        let mut status = self.status.lock().unwrap();
+        if status.state != JobState::Queued {
+            return false;
+        }
+        status.state = JobState::Running;
+        status.started_at = Some(now());
+        *self.started.lock().unwrap() = Some(Instant::now());
+        self.emit(JobEvent::progress(&status));
+        true
+    }
+
+    fn take_pending(&self) -> Option<Arc<ScrapedDataset>> {
+        let mut status = self.status.lock().unwrap();
+        status.outcome.as_mut()?.pending.take()
+    }
+
+    fn restore_pending(&self, pending: Arc<ScrapedDataset>) {
+        if let Some(outcome) = self.status.lock().unwrap().outcome.as_mut() {
+            outcome.pending = Some(pending);
+        }
+    }
+
+    fn finish(&self, result: AppResult<RefreshOutcome>) {
+        let mut status = self.status.lock().unwrap();
+        status.finished_at = Some(now());
+        // A cancel arriving after the data was stored doesn't undo it, so the outcome stands.
+        // Likewise a run that fails for another reason after the cancel request has failed.
+        status.state = match result {
+            Ok(outcome) => {
+                status.outcome = Some(outcome);
+                JobState::Succeeded
+            }
+            Err(AppError::Cancelled(_)) => JobState::Cancelled,
+            Err(err) => {
+                status.errors.push(err.to_string());
+                JobState::Failed
+            }
+        };
+        self.emit(JobEvent::Finished {
+            status: status.clone(),
+        });
+    }
+}
+
+impl ScrapeObserver for RefreshJob {
+    fn pages_queued(&self, count: usize) {
+        self.status.lock().unwrap().pages_total += count;
+    }
+
+    fn page_started(&self, url: &str) {
+        self.emit(JobEvent::PageStarted {
+            url: url.to_string(),
+        });
+    }
+
+    fn recipe_parsed(&self, url: &str, recipe: &Recipe) {
+        self.emit(JobEvent::RecipeParsed {
+            url: url.to_string(),
+            recipe: recipe.clone(),
+        });
+    }
+
+    fn page_scraped(&self, page: &ScrapedPage) {
+        {
+            let mut status = self.status.lock().unwrap();
+            status.pages_done += 1;
+            if page.status == PageStatus::Failed {
+                if let Some(err) = &page.error {
+                    status.errors.push(err.to_string());
+                    self.emit(JobEvent::PageFailed {
+                        url: page.url.clone(),
+                        reason: err.to_string(),
+                    });
+                }
+            }
+        }
+        self.emit(JobEvent::progress(&self.status()));
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Refresh jobs of this process. Jobs run one at a time in the order they were started.
+#[derive(Debug, Default)]
+pub struct JobRegistry {
+    jobs: Mutex<HashMap<String, Arc<RefreshJob>>>,
+    runner: tokio::sync::Mutex<()>,
+}
+
+static REFRESH_JOBS: Lazy<Arc<JobRegistry>> = Lazy::new(Default::default);
+
+/// The registry shared by the http handlers.
+pub fn refresh_jobs() -> Arc<JobRegistry> {
+    REFRESH_JOBS.clone()
+}
+
+impl JobRegistry {
+    /// Queues a refresh of the stored data from the wiki.
+    pub fn start_refresh(self: &Arc<Self>, request: RefreshRequest) -> JobStatus {
+        self.start(|job| dsp::refresh_data(request, Some(job)))
+    }
+
+    /// Queues `work` as a new job and returns its initial status.
+    pub fn start<F, Fut>(self: &Arc<Self>, work: F) -> JobStatus
+    where
+        F: FnOnce(Arc<RefreshJob>) -> Fut + Send + 'static,
+        Fut: Future<Output = AppResult<RefreshOutcome>> + Send,
+    {
+        let job = Arc::new(RefreshJob::new(hex::encode(rand::random::<[u8; 8]>())));
+        let status = job.status();
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            prune(&mut jobs);
+            jobs.insert(status.id.clone(), job.clone());
+        }
+
+        let registry = self.clone();
+        tokio::spawn(async move {
+            let _running = registry.runner.lock().await;
+            if !job.start() {
+                return;
+            }
+            let result = work(job.clone()).await;
+            job.finish(result);
+        });
+        status
+    }
+
+    pub fn get(&self, id: &str) -> Option<JobStatus> {
+        self.job(id).map(|job| job.status())
+    }
+
+    /// Every known job, newest first.
+    pub fn list(&self) -> Vec<JobStatus> {
+        let mut statuses: Vec<JobStatus> = self
+            .jobs
+            .lock()
+            .unwrap()
+            .values()
+            .map(|job| job.status())
+            .collect();
+        statuses.sort_by_key(|status| std::cmp::Reverse(status.created_at));
+        statuses
+    }
+
+    /// The job's current status and its events from now on. Ends after the `finished` event, which
+    /// is sent right away for jobs that already finished.
+    pub fn events(&self, id: &str) -> Option<impl Stream<Item = JobEvent>> {
+        let job = self.job(id)?;
+        // Subscribe before reading the status so no event falls in between.
+        let receiver = job.subscribe();
+        Some(event_stream(job.status(), receiver))
+    }
+
+    /// Cancels the job. Returns `None` for unknown jobs and `Some(false)` for finished ones.
+    pub fn cancel(&self, id: &str) -> Option<bool> {
+        self.job(id).map(|job| job.cancel())
+    }
+
+    /// Stores the data scraped by a dry run. It waits for the running refresh, if any, and
+    /// validates against the live recipes as they are then.
+    pub async fn promote(&self, id: &str) -> AppResult<JobStatus> {
+        let job = self
+            .job(id)
+            .ok_or_else(|| AppError::NotFound(format!("job {} not found", id)))?;
+        let pending = job.take_pending().ok_or_else(|| {
+            AppError::InvalidRequest(format!("job {} has no dry run waiting to be promoted", id))
+        })?;
+        let _running = self.runner.lock().await;
+        if let Err(err) = dsp::promote_scraped(&pending).await {
+            job.restore_pending(pending);
+            return Err(err);
+        }
+        Ok(job.status())
+    }
+
+    /// Drops the data scraped by a dry run. Returns `None` for unknown jobs and `Some(false)`
+    /// when there is nothing to discard.
+    pub fn discard(&self, id: &str) -> Option<bool> {
+        self.job(id).map(|job| job.take_pending().is_some())
+    }
+
+    fn job(&self, id: &str) -> Option<Arc<RefreshJob>> {
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+}
+
+fn event_stream(
+    status: JobStatus,
+    receiver: broadcast::Receiver<JobEvent>,
+) -> impl Stream<Item = JobEvent> {
+    let finished = status.state.is_finished();
+    let first = if finished {
+        JobEvent::Finished { status }
+    } else {
+        JobEvent::progress(&status)
+    };
+    let rest = stream::unfold((receiver, finished), |(mut receiver, done)| async move {
+        if done {
+            return None;
+        }
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    let done = matches!(event, JobEvent::Finished { .. });
+                    return Some((event, (receiver, done)));
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    stream::once(async move { first }).chain(rest)
+}
+
+/// Drops the oldest finished jobs beyond `FINISHED_JOBS_KEPT`.
+fn prune(jobs: &mut HashMap<String, Arc<RefreshJob>>) {
+    let mut finished: Vec<JobStatus> = jobs
+        .values()
+        .map(|job| job.status())
+        .filter(|status| status.state.is_finished())
+        .collect();
+    if finished.len() < FINISHED_JOBS_KEPT {
+        return;
+    }
+    finished.sort_by_key(|status| status.finished_at);
+    for status in &finished[..=finished.len() - FINISHED_JOBS_KEPT] {
+        jobs.remove(&status.id);
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scrape::{RetryRequestError, ScrapeSummary};
+    use crate::validate::Validator;
+    use std::time::Duration;
+
+    fn outcome() -> RefreshOutcome {
+        RefreshOutcome {
+            scrape: ScrapeSummary::default(),
+            validation: Validator::new().validate(&[]),
+            items: None,
+            failed_pages: vec![],
+            report_id: "report".to_string(),
+            dry_run: false,
+            diff: None,
+            pending: None,
+        }
+    }
+
+    fn failed_page(url: &str) -> ScrapedPage {
+        ScrapedPage {
+            url: url.to_string(),
+            status: PageStatus::Failed,
+            recipes: vec![],
+            item: None,
+            facility: None,
+            technology: None,
+            error: Some(RetryRequestError::TimeoutError {
+                url: url.to_string(),
+            }),
+            attempts: 4,
+            duration: Duration::from_secs(2),
+        }
+    }
+
+    async fn wait_until_finished(registry: &JobRegistry, id: &str) -> JobStatus {
+        let events: Vec<JobEvent> = registry.events(id).unwrap().collect().await;
+        match events.last() {
+            Some(JobEvent::Finished { status }) => status.clone(),
+            event => panic!("job {} ended with {:?}", id, event),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_job_progress() {
+        let registry = Arc::new(JobRegistry::default());
+        let (reached, reaching) = tokio::sync::oneshot::channel::<()>();
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let status = registry.start(|job| async move {
+            job.pages_queued(4);
+            job.page_scraped(&failed_page("https://dsp-wiki.com/Iron_Ingot"));
+            reached.send(()).unwrap();
+            released.await.unwrap();
+            Ok(outcome())
+        });
+        assert_eq!(status.state, JobState::Queued);
+
+        reaching.await.unwrap();
+        let running = registry.get(&status.id).unwrap();
+        assert_eq!(running.state, JobState::Running);
+        assert_eq!((running.pages_done, running.pages_total), (1, 4));
+        assert!(running.eta_seconds.is_some());
+        assert_eq!(running.errors.len(), 1);
+
+        release.send(()).unwrap();
+        let finished = wait_until_finished(&registry, &status.id).await;
+        assert_eq!(finished.state, JobState::Succeeded);
+        assert!(finished.outcome.is_some());
+        assert_eq!(registry.list().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_job_events() {
+        let registry = Arc::new(JobRegistry::default());
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let status = registry.start(|job| async move {
+            released.await.unwrap();
+            let url = "https://dsp-wiki.com/Iron_Ingot";
+            job.pages_queued(2);
+            job.page_started(url);
+            job.recipe_parsed(url, &Recipe::new());
+            job.page_scraped(&failed_page("https://dsp-wiki.com/Magnet"));
+            Ok(outcome())
+        });
+
+        let events = registry.events(&status.id).unwrap();
+        release.send(()).unwrap();
+        let names: Vec<&str> = events.map(|event| event.name()).collect().await;
+        // The snapshot, and the start unless the job started before subscribing.
+        let names: Vec<&str> = names
+            .into_iter()
+            .skip_while(|name| *name == "progress")
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "page_started",
+                "recipe_parsed",
+                "page_failed",
+                "progress",
+                "finished"
+            ]
+        );
+
+        // A finished job only replays its summary.
+        let events: Vec<JobEvent> = registry.events(&status.id).unwrap().collect().await;
+        assert!(matches!(
+            &events[..],
+            [JobEvent::Finished { status }] if status.state == JobState::Succeeded
+        ));
+        assert!(registry.events("unknown").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_jobs() {
+        let registry = Arc::new(JobRegistry::default());
+        let (started, starting) = tokio::sync::oneshot::channel::<()>();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let running = registry.start(|job| async move {
+            started.send(()).unwrap();
+            stopped.await.unwrap();
+            assert!(job.is_cancelled());
+            Err(AppError::Cancelled("refresh was cancelled".to_string()))
+        });
+        let queued = registry.start(|_| async { Ok(outcome()) });
+
+        // the queued job waits for the running one to finish
+        starting.await.unwrap();
+        assert_eq!(registry.get(&queued.id).unwrap().state, JobState::Queued);
+        assert_eq!(registry.cancel(&queued.id), Some(true));
+        assert_eq!(registry.cancel(&running.id), Some(true));
+        stop.send(()).unwrap();
+
+        let cancelled = wait_until_finished(&registry, &running.id).await;
+        assert_eq!(cancelled.state, JobState::Cancelled);
+        assert!(cancelled.errors.is_empty());
+        assert_eq!(registry.get(&queued.id).unwrap().state, JobState::Cancelled);
+        assert_eq!(registry.cancel(&running.id), Some(false));
+        assert_eq!(registry.cancel("unknown"), None);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_after_store() {
+        let registry = Arc::new(JobRegistry::default());
+        let (started, starting) = tokio::sync::oneshot::channel::<()>();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let status = registry.start(|_| async move {
+            started.send(()).unwrap();
+            stopped.await.unwrap();
+            Ok(outcome())
+        });
+
+        starting.await.unwrap();
+        assert_eq!(registry.cancel(&status.id), Some(true));
+        stop.send(()).unwrap();
+
+        let finished = wait_until_finished(&registry, &status.id).await;
+        assert_eq!(finished.state, JobState::Succeeded);
+        assert!(finished.outcome.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_fail_after_cancel() {
+        let registry = Arc::new(JobRegistry::default());
+        let (started, starting) = tokio::sync::oneshot::channel::<()>();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let status = registry.start(|_| async move {
+            started.send(()).unwrap();
+            stopped.await.unwrap();
+            Err(AppError::DatabaseUnavailable(
+                "connection refused".to_string(),
+            ))
+        });
+
+        starting.await.unwrap();
+        assert_eq!(registry.cancel(&status.id), Some(true));
+        stop.send(()).unwrap();
+
+        let finished = wait_until_finished(&registry, &status.id).await;
+        assert_eq!(finished.state, JobState::Failed);
+        assert_eq!(
+            finished.errors,
+            ["database unavailable: connection refused"]
+        );
+    }
+}
@@ -0,0 +1,17 @@
+#![allow(clippy::let_with_type_underscore)]
+
+pub mod aliases;
+pub mod data;
+pub mod diff;
+pub mod dsp;
+pub mod error;
+pub mod jobs;
+pub mod optimizer;
+pub mod overrides;
+pub mod plan;
+pub mod query;
+pub mod research;
+pub mod scrape;
+pub mod timekeeper;
+pub mod transfer;
+pub mod validate;
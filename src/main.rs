@@ -1,23 +1,41 @@
 #![allow(clippy::let_with_type_underscore)]
 #![allow(clippy::default_constructed_unit_structs)] // warning since 1.71
 
-use axum::extract::Path;
-use axum::{response::IntoResponse, routing::get, routing::post, Router};
+use alex_api_rs::aliases::ItemSearch;
+use alex_api_rs::data::{ItemAlias, Recipe, RecipeOverride};
+use alex_api_rs::dsp::{ComputedRecipeRequest, RefreshRequest};
+use alex_api_rs::error::{AppError, AppResult};
+use alex_api_rs::query::RecipeQuery;
+use alex_api_rs::transfer::{self, DatasetFormat};
+use alex_api_rs::{data, dsp, jobs, validate};
+use axum::body::Bytes;
+use axum::extract::rejection::{JsonRejection, QueryRejection};
+use axum::extract::{Path, Query};
+use axum::http::{header, HeaderValue, Request, StatusCode, Uri};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::Response;
+use axum::{response::IntoResponse, routing::get, routing::post, routing::put, Router};
 use axum_tracing_opentelemetry::middleware::{OtelAxumLayer, OtelInResponseLayer};
-use dsp::ComputedRecipeRequest;
+use futures::StreamExt;
+use serde::Deserialize;
 use serde_json::json;
-use std::collections::HashMap;
 use std::error::Error;
 use std::net::SocketAddr;
 use tracing_opentelemetry_instrumentation_sdk::find_current_trace_id;
+use utoipa::IntoParams;
+use utoipa_swagger_ui::SwaggerUi;
+
+/// Prefix of the first version of the api, which is also served without a prefix.
+const V1: &str = "/v1";
+
+/// Prefix of the current version of the api.
+const V2: &str = "/v2";
 
-mod data;
-mod dsp;
 mod init_otel;
 mod layer;
-mod optimizer;
-mod scrape;
-mod timekeeper;
+mod openapi;
+#[allow(dead_code)]
 mod trace_id_format;
 
 #[tokio::main]
@@ -30,6 +48,19 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // init_tracing_opentelemetry::tracing_subscriber_ext::init_subscribers()?;
     init_otel::init_subscribers()?;
 
+    tokio::spawn(async {
+        let db = data::dsp::DB::new();
+        if let Err(err) = db.ensure_recipe_indexes().await {
+            tracing::warn!("unable to create recipe indexes: {}", err);
+        }
+        if let Err(err) = db.ensure_item_indexes().await {
+            tracing::warn!(
+                "unable to create item, facility and technology indexes: {}",
+                err
+            );
+        }
+    });
+
     let app = app();
     // run it
     let addr = &format!("0.0.0.0:{}", port).parse::<SocketAddr>()?;
@@ -57,9 +88,12 @@ fn app() -> Router {
             get(proxy_handler).post(proxy_handler),
         )
         .route("/", get(index)) // request processed inside span
-        .route("/dsp/recipes", get(dsp_recipes))
-        .route("/dsp/computedRecipes", post(dsp_computed_recipes))
-        .route("/dsp/recipes/reload", get(dsp_reload_recipes))
+        // today's routes, still served without a version for existing clients
+        .merge(v1_routes())
+        .nest(V1, v1_routes())
+        .nest(V2, v2_routes())
+        .merge(SwaggerUi::new(openapi::DOCS_PATH).url(openapi::SPEC_PATH, openapi::spec()))
+        .fallback(not_found)
         // include trace context as header into the response
         .layer(OtelInResponseLayer::default())
         //start OpenTelemetry trace on incoming request
@@ -67,6 +101,78 @@ fn app() -> Router {
         .route("/health", get(health)) // request processed without span / trace
 }
 
+/// Routes shared by every version of the api.
+fn dsp_routes() -> Router {
+    Router::new()
+        .route("/dsp/recipes", get(dsp_recipes))
+        .route("/dsp/recipes/query", get(dsp_query_recipes))
+        .route(
+            "/dsp/recipes/reload",
+            get(dsp_reload_recipes_get).post(dsp_reload_recipes),
+        )
+        .route("/dsp/jobs", get(dsp_jobs))
+        .route("/dsp/jobs/:id", get(dsp_job))
+        .route("/dsp/jobs/:id/events", get(dsp_job_events))
+        .route("/dsp/jobs/:id/cancel", post(dsp_cancel_job))
+        .route("/dsp/jobs/:id/promote", post(dsp_promote_job))
+        .route("/dsp/jobs/:id/discard", post(dsp_discard_job))
+        .route("/dsp/reports", get(dsp_scrape_reports))
+        .route("/dsp/reports/:id", get(dsp_scrape_report))
+        .route(
+            "/dsp/recipes/validate",
+            get(dsp_validate_recipes).post(dsp_validate_recipe_payload),
+        )
+        .route("/dsp/recipes/export", get(dsp_export_recipes))
+        .route("/dsp/recipes/import", post(dsp_import_recipes))
+        .route("/dsp/recipes/versions", get(dsp_recipe_versions))
+        .route("/dsp/items", get(dsp_items))
+        .route("/dsp/items/search", get(dsp_search_items))
+        .route("/dsp/items/:name", get(dsp_item))
+        .route("/dsp/images/:hash", get(dsp_image))
+        .route("/dsp/aliases", get(dsp_item_aliases))
+        .route(
+            "/dsp/aliases/:alias",
+            put(dsp_save_item_alias).delete(dsp_delete_item_alias),
+        )
+        .route("/dsp/facilities", get(dsp_facilities))
+        .route("/dsp/technologies", get(dsp_technologies))
+        .route("/dsp/overrides", get(dsp_recipe_overrides))
+        .route(
+            "/dsp/overrides/:id",
+            get(dsp_recipe_override)
+                .put(dsp_save_recipe_override)
+                .delete(dsp_delete_recipe_override),
+        )
+}
+
+/// The first version of the api, deprecated in favour of `v2_routes`.
+fn v1_routes() -> Router {
+    dsp_routes()
+        .route("/dsp/computedRecipes", post(dsp_computed_recipes))
+        .layer(middleware::from_fn(deprecated))
+}
+
+/// Planning returns a `Plan`, with the steps as a tree and the totals of the chain.
+fn v2_routes() -> Router {
+    dsp_routes().route("/dsp/computedRecipes", post(dsp_plan))
+}
+
+/// Marks a response as deprecated, linking to the same route under `V2`.
+async fn deprecated<B>(request: Request<B>, next: Next<B>) -> Response {
+    let successor = format!(
+        "<{}{}>; rel=\"successor-version\"",
+        V2,
+        request.uri().path()
+    );
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert("deprecation", HeaderValue::from_static("true"));
+    if let Ok(successor) = HeaderValue::from_str(&successor) {
+        headers.insert(header::LINK, successor);
+    }
+    response
+}
+
 async fn health() -> impl IntoResponse {
     axum::Json(json!({ "status" : "UP" }))
 }
@@ -82,37 +188,578 @@ async fn index() -> impl IntoResponse {
     axum::Json(json!({ "my_trace_id": trace_id }))
 }
 
+#[utoipa::path(
+    get,
+    path = "/dsp/recipes",
+    tag = "recipes",
+    responses((
+        status = 200,
+        description = "Recipes with overrides applied, by lowercase output item",
+        body = HashMap<String, Vec<Recipe>>
+    ))
+)]
+#[tracing::instrument]
+async fn dsp_recipes() -> AppResult<impl IntoResponse> {
+    let recipes = dsp::load_recipes().await?;
+    Ok(axum::Json(json!(recipes)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/dsp/recipes/query",
+    tag = "recipes",
+    params(RecipeQuery),
+    responses((status = 200, description = "One page of the matching recipes", body = RecipePage))
+)]
 #[tracing::instrument]
-async fn dsp_recipes() -> impl IntoResponse {
-    let recipes = dsp::load_recipes().await;
-    axum::Json(json!(recipes))
+async fn dsp_query_recipes(
+    query: Result<Query<RecipeQuery>, QueryRejection>,
+) -> AppResult<impl IntoResponse> {
+    let Query(query) = query?;
+    let page = dsp::query_recipes(query).await?;
+    Ok(axum::Json(json!(page)))
 }
 
+#[utoipa::path(
+    post,
+    path = "/dsp/computedRecipes",
+    tag = "recipes",
+    request_body = ComputedRecipeRequest,
+    responses((
+        status = 200,
+        description = "Facilities and materials needed for every step of the production chain",
+        body = Vec<ComputedRecipe>
+    ))
+)]
 #[tracing::instrument]
 #[axum::debug_handler]
 async fn dsp_computed_recipes(
-    axum::Json(payload): axum::Json<ComputedRecipeRequest>,
-) -> impl IntoResponse {
-    let mut optimizer = optimizer::Optimizer::new();
-    let recipes = dsp::load_recipes().await;
-    optimizer.set_recipes(recipes);
-    let mut seen = HashMap::new();
-    let computed_recipes = optimizer.get_optimal_recipe(
-        payload.name,
-        payload.rate,
-        "".to_string(),
-        &mut seen,
-        0,
-        payload.requirements,
-    );
-    axum::Json(json!(computed_recipes))
+    payload: Result<axum::Json<ComputedRecipeRequest>, JsonRejection>,
+) -> AppResult<impl IntoResponse> {
+    let axum::Json(payload) = payload?;
+    let computed_recipes = dsp::compute_recipes(payload).await?;
+    Ok(axum::Json(json!(computed_recipes)))
 }
 
+#[utoipa::path(
+    post,
+    path = "/v2/dsp/computedRecipes",
+    tag = "recipes",
+    request_body = ComputedRecipeRequest,
+    responses((
+        status = 200,
+        description = "The production chain as a tree of steps, with its totals",
+        body = Plan
+    ))
+)]
 #[tracing::instrument]
-#[axum::debug_handler]
-async fn dsp_reload_recipes() -> impl IntoResponse {
-    dsp::refresh_data().await;
-    axum::Json(json!({ "status": "OK" }))
+async fn dsp_plan(
+    payload: Result<axum::Json<ComputedRecipeRequest>, JsonRejection>,
+) -> AppResult<impl IntoResponse> {
+    let axum::Json(payload) = payload?;
+    let plan = dsp::compute_plan(payload).await?;
+    Ok(axum::Json(json!(plan)))
+}
+
+/// Starts a refresh in the background. Its progress is reported at the returned location. A
+/// JSON body of `{"items": [...]}` limits it to those items, and `{"dry_run": true}` previews
+/// the changes without storing them.
+#[utoipa::path(
+    post,
+    path = "/dsp/recipes/reload",
+    tag = "jobs",
+    request_body(
+        content = RefreshRequest,
+        description = "Optional, an empty body refreshes everything"
+    ),
+    responses((
+        status = 202,
+        description = "The refresh job, also at the Location header",
+        body = JobStatus
+    ))
+)]
+#[tracing::instrument(skip(body))]
+async fn dsp_reload_recipes(body: Bytes) -> AppResult<impl IntoResponse> {
+    let request: RefreshRequest = if body.is_empty() {
+        RefreshRequest::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|err| AppError::InvalidRequest(err.to_string()))?
+    };
+    Ok(start_refresh(request))
+}
+
+/// Queues the refresh and answers with the job and its location.
+fn start_refresh(request: RefreshRequest) -> impl IntoResponse {
+    let job = jobs::refresh_jobs().start_refresh(request);
+    (
+        StatusCode::ACCEPTED,
+        [(header::LOCATION, format!("/dsp/jobs/{}", job.id))],
+        axum::Json(json!(job)),
+    )
+}
+
+/// Same as `POST /dsp/recipes/reload` without a body, for clients that still refresh with GET.
+/// It always refreshes everything.
+#[utoipa::path(
+    get,
+    path = "/dsp/recipes/reload",
+    tag = "jobs",
+    responses((
+        status = 202,
+        description = "The refresh job, also at the Location header",
+        body = JobStatus
+    ))
+)]
+#[tracing::instrument]
+async fn dsp_reload_recipes_get() -> impl IntoResponse {
+    start_refresh(RefreshRequest::default())
+}
+
+#[utoipa::path(
+    get,
+    path = "/dsp/jobs",
+    tag = "jobs",
+    responses((
+        status = 200,
+        description = "Refresh jobs, most recent first",
+        body = Vec<JobStatus>
+    ))
+)]
+#[tracing::instrument]
+async fn dsp_jobs() -> impl IntoResponse {
+    axum::Json(json!(jobs::refresh_jobs().list()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/dsp/jobs/{id}",
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job id")),
+    responses((status = 200, body = JobStatus))
+)]
+#[tracing::instrument]
+async fn dsp_job(Path(id): Path<String>) -> AppResult<impl IntoResponse> {
+    let job = jobs::refresh_jobs()
+        .get(&id)
+        .ok_or_else(|| AppError::NotFound(format!("job {} not found", id)))?;
+    Ok(axum::Json(json!(job)))
+}
+
+/// Streams the job's events as server-sent events until it finishes.
+#[utoipa::path(
+    get,
+    path = "/dsp/jobs/{id}/events",
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job id")),
+    responses((
+        status = 200,
+        description = "Server-sent events named after their `type`",
+        body = JobEvent,
+        content_type = "text/event-stream"
+    ))
+)]
+#[tracing::instrument]
+async fn dsp_job_events(Path(id): Path<String>) -> AppResult<impl IntoResponse> {
+    let events = jobs::refresh_jobs()
+        .events(&id)
+        .ok_or_else(|| AppError::NotFound(format!("job {} not found", id)))?;
+    let events = events.map(|event| Event::default().event(event.name()).json_data(&event));
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/dsp/jobs/{id}/cancel",
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job id")),
+    responses((status = 200, body = JobStatus))
+)]
+#[tracing::instrument]
+async fn dsp_cancel_job(Path(id): Path<String>) -> AppResult<impl IntoResponse> {
+    let registry = jobs::refresh_jobs();
+    match registry.cancel(&id) {
+        None => Err(AppError::NotFound(format!("job {} not found", id))),
+        Some(false) => Err(AppError::InvalidRequest(format!(
+            "job {} already finished",
+            id
+        ))),
+        Some(true) => Ok(axum::Json(json!(registry.get(&id)))),
+    }
+}
+
+/// Stores what a dry run scraped.
+#[utoipa::path(
+    post,
+    path = "/dsp/jobs/{id}/promote",
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job id of a dry run")),
+    responses((status = 200, body = JobStatus))
+)]
+#[tracing::instrument]
+async fn dsp_promote_job(Path(id): Path<String>) -> AppResult<impl IntoResponse> {
+    let job = jobs::refresh_jobs().promote(&id).await?;
+    Ok(axum::Json(json!(job)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/dsp/jobs/{id}/discard",
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job id of a dry run")),
+    responses((status = 200, body = JobStatus))
+)]
+#[tracing::instrument]
+async fn dsp_discard_job(Path(id): Path<String>) -> AppResult<impl IntoResponse> {
+    let registry = jobs::refresh_jobs();
+    match registry.discard(&id) {
+        None => Err(AppError::NotFound(format!("job {} not found", id))),
+        Some(false) => Err(AppError::InvalidRequest(format!(
+            "job {} has no dry run waiting to be promoted",
+            id
+        ))),
+        Some(true) => Ok(axum::Json(json!(registry.get(&id)))),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/dsp/reports",
+    tag = "jobs",
+    responses((
+        status = 200,
+        description = "Reports of past runs, most recent first and without their pages",
+        body = Vec<ScrapeReport>
+    ))
+)]
+#[tracing::instrument]
+async fn dsp_scrape_reports() -> AppResult<impl IntoResponse> {
+    let reports = dsp::get_scrape_reports().await?;
+    Ok(axum::Json(json!(reports)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/dsp/reports/{id}",
+    tag = "jobs",
+    params(("id" = String, Path, description = "Report id")),
+    responses((status = 200, body = ScrapeReport))
+)]
+#[tracing::instrument]
+async fn dsp_scrape_report(Path(id): Path<String>) -> AppResult<impl IntoResponse> {
+    let report = dsp::get_scrape_report(&id).await?;
+    Ok(axum::Json(json!(report)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/dsp/recipes/validate",
+    tag = "recipes",
+    responses((status = 200, description = "Issues of the stored recipes", body = ValidationReport))
+)]
+#[tracing::instrument]
+async fn dsp_validate_recipes() -> AppResult<impl IntoResponse> {
+    let report = dsp::validate_recipes().await?;
+    Ok(axum::Json(json!(report)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/dsp/recipes/validate",
+    tag = "recipes",
+    request_body = Vec<Recipe>,
+    responses((status = 200, description = "Issues of the posted recipes", body = ValidationReport))
+)]
+#[tracing::instrument(skip(payload))]
+async fn dsp_validate_recipe_payload(
+    payload: Result<axum::Json<Vec<Recipe>>, JsonRejection>,
+) -> AppResult<impl IntoResponse> {
+    let axum::Json(payload) = payload?;
+    let report = validate::Validator::new().validate(&payload);
+    Ok(axum::Json(json!(report)))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct DatasetFormatQuery {
+    format: Option<DatasetFormat>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/dsp/recipes/export",
+    tag = "recipes",
+    params(DatasetFormatQuery),
+    responses((
+        status = 200,
+        description = "The stored recipes as an attachment in the requested format",
+        body = String,
+        content_type = ["application/json", "text/csv", "application/yaml"]
+    ))
+)]
+#[tracing::instrument]
+async fn dsp_export_recipes(
+    query: Result<Query<DatasetFormatQuery>, QueryRejection>,
+) -> AppResult<impl IntoResponse> {
+    let Query(query) = query?;
+    let format = query.format.unwrap_or(DatasetFormat::Json);
+    let body = dsp::export_recipes(format).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"recipes.{}\"", format.extension()),
+            ),
+        ],
+        body,
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/dsp/recipes/import",
+    tag = "recipes",
+    params(DatasetFormatQuery),
+    request_body(
+        content = String,
+        description = "Recipes as json, csv or yaml, see `format`",
+        content_type = "text/plain"
+    ),
+    responses((status = 200, description = "The new version of the dataset", body = ImportOutcome))
+)]
+#[tracing::instrument(skip(body))]
+async fn dsp_import_recipes(
+    query: Result<Query<DatasetFormatQuery>, QueryRejection>,
+    body: String,
+) -> AppResult<impl IntoResponse> {
+    let Query(query) = query?;
+    let format = query.format.unwrap_or(DatasetFormat::Json);
+    let recipes = transfer::import_recipes(&body, format)?;
+    let outcome = dsp::import_recipes(recipes, format).await?;
+    Ok(axum::Json(json!(outcome)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/dsp/recipes/versions",
+    tag = "recipes",
+    responses((
+        status = 200,
+        description = "Promoted datasets without their recipes",
+        body = Vec<RecipeVersion>
+    ))
+)]
+#[tracing::instrument]
+async fn dsp_recipe_versions() -> AppResult<impl IntoResponse> {
+    let versions = data::dsp::DB::new().get_recipe_versions().await?;
+    Ok(axum::Json(json!(versions)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/dsp/items",
+    tag = "items",
+    responses((status = 200, body = Vec<Item>))
+)]
+#[tracing::instrument]
+async fn dsp_items() -> AppResult<impl IntoResponse> {
+    let items = dsp::get_items().await?;
+    Ok(axum::Json(json!(items)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/dsp/items/{name}",
+    tag = "items",
+    params(("name" = String, Path, description = "Item name, localized name or alias")),
+    responses((status = 200, body = ItemDetail))
+)]
+#[tracing::instrument]
+async fn dsp_item(Path(name): Path<String>) -> AppResult<impl IntoResponse> {
+    let item = dsp::get_item(&name).await?;
+    Ok(axum::Json(json!(item)))
+}
+
+/// Serves a mirrored icon. Images are named after their content, so they never change.
+#[utoipa::path(
+    get,
+    path = "/dsp/images/{hash}",
+    tag = "items",
+    params(("hash" = String, Path, description = "SHA-256 of the image")),
+    responses((
+        status = 200,
+        description = "The image, cacheable forever",
+        body = Vec<u8>,
+        content_type = "image/png"
+    ))
+)]
+#[tracing::instrument]
+async fn dsp_image(Path(hash): Path<String>) -> AppResult<impl IntoResponse> {
+    let (content_type, content) = dsp::get_image(&hash).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CACHE_CONTROL,
+                "public, max-age=31536000, immutable".to_string(),
+            ),
+            (header::ETAG, format!("\"{}\"", hash)),
+        ],
+        content,
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/dsp/facilities",
+    tag = "items",
+    responses((status = 200, body = Vec<Facility>))
+)]
+#[tracing::instrument]
+async fn dsp_facilities() -> AppResult<impl IntoResponse> {
+    let facilities = dsp::get_facilities().await?;
+    Ok(axum::Json(json!(facilities)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/dsp/technologies",
+    tag = "items",
+    responses((status = 200, body = Vec<Technology>))
+)]
+#[tracing::instrument]
+async fn dsp_technologies() -> AppResult<impl IntoResponse> {
+    let technologies = dsp::get_technologies().await?;
+    Ok(axum::Json(json!(technologies)))
+}
+
+/// Items whose name, localized name or alias matches `q`, for autocompletion.
+#[utoipa::path(
+    get,
+    path = "/dsp/items/search",
+    tag = "items",
+    params(ItemSearch),
+    responses((status = 200, description = "Best matches first", body = Vec<ItemMatch>))
+)]
+#[tracing::instrument]
+async fn dsp_search_items(
+    search: Result<Query<ItemSearch>, QueryRejection>,
+) -> AppResult<impl IntoResponse> {
+    let Query(search) = search?;
+    let matches = dsp::search_items(search).await?;
+    Ok(axum::Json(json!(matches)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/dsp/aliases",
+    tag = "items",
+    responses((status = 200, body = Vec<ItemAlias>))
+)]
+#[tracing::instrument]
+async fn dsp_item_aliases() -> AppResult<impl IntoResponse> {
+    let aliases = dsp::get_item_aliases().await?;
+    Ok(axum::Json(json!(aliases)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/dsp/aliases/{alias}",
+    tag = "items",
+    params(("alias" = String, Path, description = "Alias name")),
+    request_body = ItemAlias,
+    responses((status = 200, body = ItemAlias))
+)]
+#[tracing::instrument]
+async fn dsp_save_item_alias(
+    Path(alias): Path<String>,
+    payload: Result<axum::Json<ItemAlias>, JsonRejection>,
+) -> AppResult<impl IntoResponse> {
+    let axum::Json(mut payload) = payload?;
+    payload.alias = alias;
+    let alias = dsp::save_item_alias(payload).await?;
+    Ok(axum::Json(json!(alias)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/dsp/aliases/{alias}",
+    tag = "items",
+    params(("alias" = String, Path, description = "Alias name")),
+    responses((status = 200, description = "The alias was deleted"))
+)]
+#[tracing::instrument]
+async fn dsp_delete_item_alias(Path(alias): Path<String>) -> AppResult<impl IntoResponse> {
+    dsp::delete_item_alias(&alias).await?;
+    Ok(axum::Json(json!({ "status": "OK" })))
+}
+
+#[utoipa::path(
+    get,
+    path = "/dsp/overrides",
+    tag = "overrides",
+    responses((status = 200, body = Vec<RecipeOverride>))
+)]
+#[tracing::instrument]
+async fn dsp_recipe_overrides() -> AppResult<impl IntoResponse> {
+    let overrides = data::dsp::DB::new().get_recipe_overrides().await?;
+    Ok(axum::Json(json!(overrides)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/dsp/overrides/{id}",
+    tag = "overrides",
+    params(("id" = String, Path, description = "Override id")),
+    responses((status = 200, body = RecipeOverride))
+)]
+#[tracing::instrument]
+async fn dsp_recipe_override(Path(id): Path<String>) -> AppResult<impl IntoResponse> {
+    let recipe_override = data::dsp::DB::new()
+        .get_recipe_override(&id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("override {} not found", id)))?;
+    Ok(axum::Json(json!(recipe_override)))
+}
+
+#[utoipa::path(
+    put,
+    path = "/dsp/overrides/{id}",
+    tag = "overrides",
+    params(("id" = String, Path, description = "Override id")),
+    request_body = RecipeOverride,
+    responses((status = 200, body = RecipeOverride))
+)]
+#[tracing::instrument]
+async fn dsp_save_recipe_override(
+    Path(id): Path<String>,
+    payload: Result<axum::Json<RecipeOverride>, JsonRejection>,
+) -> AppResult<impl IntoResponse> {
+    let axum::Json(mut payload) = payload?;
+    payload.id = id;
+    data::dsp::DB::new()
+        .save_recipe_override(payload.clone())
+        .await?;
+    Ok(axum::Json(json!(payload)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/dsp/overrides/{id}",
+    tag = "overrides",
+    params(("id" = String, Path, description = "Override id")),
+    responses((status = 200, description = "The override was deleted"))
+)]
+#[tracing::instrument]
+async fn dsp_delete_recipe_override(Path(id): Path<String>) -> AppResult<impl IntoResponse> {
+    if !data::dsp::DB::new().delete_recipe_override(&id).await? {
+        return Err(AppError::NotFound(format!("override {} not found", id)));
+    }
+    Ok(axum::Json(json!({ "status": "OK" })))
+}
+
+async fn not_found(uri: Uri) -> AppError {
+    AppError::NotFound(format!("no route for {}", uri.path()))
 }
 
 #[tracing::instrument]
@@ -166,3 +813,327 @@ async fn shutdown_signal() {
     tracing::warn!("signal received, starting graceful shutdown");
     opentelemetry::global::shutdown_tracer_provider();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use std::collections::BTreeSet;
+    use tower::ServiceExt;
+
+    fn spec() -> serde_json::Value {
+        serde_json::to_value(openapi::spec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_spec_operations_are_routed() {
+        let spec = spec();
+        let paths = spec["paths"].as_object().unwrap();
+        for path in [
+            "/dsp/computedRecipes",
+            "/v1/dsp/computedRecipes",
+            "/v2/dsp/computedRecipes",
+        ] {
+            assert!(
+                paths[path]["post"].is_object(),
+                "{} is not documented",
+                path
+            );
+        }
+
+        // No route accepts PATCH, so every request ends in the router's 404 fallback or a 405
+        // listing the methods of the path, and no handler touches the database or starts a
+        // refresh.
+        let param_re = regex::Regex::new(r"\{\w+\}").unwrap();
+        for (path, operations) in paths {
+            let uri = param_re.replace_all(path, "route-check").to_string();
+            let request = Request::patch(&uri).body(Body::empty()).unwrap();
+            let response = app().oneshot(request).await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{} has no route",
+                path
+            );
+            let allowed: BTreeSet<String> = response.headers()[header::ALLOW]
+                .to_str()
+                .unwrap()
+                .split(',')
+                .map(|method| method.trim().to_lowercase())
+                .filter(|method| method != "head")
+                .collect();
+            let documented: BTreeSet<String> =
+                operations.as_object().unwrap().keys().cloned().collect();
+            assert_eq!(allowed, documented, "methods of {}", path);
+        }
+    }
+
+    /// Where `value` doesn't match `schema` of `spec`, following references. Constructs the
+    /// spec doesn't use for responses, e.g. `not`, are accepted.
+    fn schema_mismatches(
+        spec: &serde_json::Value,
+        schema: &serde_json::Value,
+        value: &serde_json::Value,
+        at: &str,
+    ) -> Vec<String> {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/components/schemas/");
+            let schema = &spec["components"]["schemas"][name];
+            return schema_mismatches(spec, schema, value, at);
+        }
+        if value.is_null() {
+            return match schema["nullable"].as_bool() {
+                Some(true) => vec![],
+                _ => vec![format!("{} is null", at)],
+            };
+        }
+        if let Some(schemas) = schema["allOf"].as_array() {
+            return schemas
+                .iter()
+                .flat_map(|schema| schema_mismatches(spec, schema, value, at))
+                .collect();
+        }
+        if let Some(schemas) = schema["oneOf"].as_array() {
+            let matches = schemas
+                .iter()
+                .any(|schema| schema_mismatches(spec, schema, value, at).is_empty());
+            return match matches {
+                true => vec![],
+                false => vec![format!("{} matches none of its variants", at)],
+            };
+        }
+        let matches_type = match schema["type"].as_str() {
+            Some("object") => value.is_object(),
+            Some("array") => value.is_array(),
+            Some("string") => value.is_string(),
+            Some("number") => value.is_number(),
+            Some("integer") => value.is_i64() || value.is_u64(),
+            Some("boolean") => value.is_boolean(),
+            _ => true,
+        };
+        if !matches_type {
+            return vec![format!("{} is not of type {}", at, schema["type"])];
+        }
+        let mut mismatches = vec![];
+        if let Some(variants) = schema["enum"].as_array() {
+            if !variants.contains(value) {
+                mismatches.push(format!("{} is not one of {:?}", at, variants));
+            }
+        }
+        if let Some(items) = value.as_array() {
+            for (i, item) in items.iter().enumerate() {
+                let at = format!("{}[{}]", at, i);
+                mismatches.extend(schema_mismatches(spec, &schema["items"], item, &at));
+            }
+        }
+        if let Some(object) = value.as_object() {
+            for required in schema["required"].as_array().into_iter().flatten() {
+                let required = required.as_str().unwrap();
+                if !object.contains_key(required) {
+                    mismatches.push(format!("{}.{} is missing", at, required));
+                }
+            }
+            for (key, field) in object {
+                let at = format!("{}.{}", at, key);
+                let field_schema = match schema["properties"].get(key) {
+                    Some(field_schema) => field_schema,
+                    None if schema["additionalProperties"].is_object() => {
+                        &schema["additionalProperties"]
+                    }
+                    None if schema["properties"].is_object() => {
+                        mismatches.push(format!("{} is not in the schema", at));
+                        continue;
+                    }
+                    None => continue,
+                };
+                mismatches.extend(schema_mismatches(spec, field_schema, field, &at));
+            }
+        }
+        mismatches
+    }
+
+    #[test]
+    fn test_responses_match_schemas() {
+        use alex_api_rs::aliases::AliasTable;
+        use alex_api_rs::data::{Facility, Item, MarketData};
+        use alex_api_rs::plan;
+        use alex_api_rs::scrape::ScrapeSummary;
+        use std::collections::HashMap;
+
+        let mut ingot = Recipe::new();
+        ingot.output_item = "Iron Ingot".to_string();
+        ingot.output_item_count = 1.0;
+        ingot.facility = "Arc Smelter".to_string();
+        ingot.facilities = vec!["Arc Smelter".to_string()];
+        ingot.time = 1.0;
+        ingot.materials = HashMap::from([("Iron Ore".to_string(), 1.0)]);
+        ingot.technology = Some("Basic Assembling Processes".to_string());
+        ingot.market_data = Some(MarketData {
+            last_update_attempt: 1,
+            last_updated: 1,
+            price: Some(10.0),
+            quantity: None,
+            total_trade_count: None,
+            name: Some("Iron Ingot".to_string()),
+        });
+        let mut smelter = Facility::new("Arc Smelter");
+        smelter.work_power = Some(360_000.0);
+        let request = ComputedRecipeRequest {
+            name: "Iron Ingot".to_string(),
+            rate: 1.0,
+            requirements: HashMap::new(),
+            facilities: HashMap::new(),
+            preferred_facilities: vec![],
+            researched: None,
+        };
+        let steps = dsp::plan_recipes(
+            request,
+            dsp::group_recipes(vec![ingot.clone()]),
+            vec![smelter.clone()],
+            &[],
+            AliasTable::default(),
+        )
+        .unwrap();
+        let mut item = Item::new("Iron Ingot");
+        item.localized_names = HashMap::from([("zh".to_string(), "铁块".to_string())]);
+
+        let spec = spec();
+        let responses = [
+            ("Recipe", json!(ingot)),
+            ("ComputedRecipe", json!(steps[0])),
+            ("Plan", json!(plan::build_plan(steps).unwrap())),
+            ("Item", json!(item)),
+            ("Facility", json!(smelter)),
+            (
+                "ValidationReport",
+                json!(validate::Validator::new().validate(&[ingot])),
+            ),
+            ("ScrapeSummary", json!(ScrapeSummary::default())),
+        ];
+        for (name, value) in responses {
+            let schema = json!({ "$ref": format!("#/components/schemas/{}", name) });
+            let mismatches = schema_mismatches(&spec, &schema, &value, name);
+            assert!(mismatches.is_empty(), "{:?}", mismatches);
+        }
+    }
+
+    #[test]
+    fn test_spec_versions() {
+        let spec = spec();
+        for (path, operations) in spec["paths"].as_object().unwrap() {
+            for operation in operations.as_object().unwrap().values() {
+                assert_eq!(
+                    operation["deprecated"].as_bool().unwrap_or(false),
+                    !path.starts_with(V2),
+                    "{} is not deprecated as its version is",
+                    path
+                );
+            }
+        }
+        let plan = &spec["paths"]["/v2/dsp/computedRecipes"]["post"];
+        assert_eq!(plan["operationId"], "dsp_plan");
+        assert_eq!(
+            plan["responses"]["200"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/Plan"
+        );
+        assert_eq!(
+            spec["paths"]["/v2/dsp/items"]["get"]["operationId"],
+            "v2_dsp_items"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deprecation_headers() {
+        for (path, successor) in [
+            (
+                "/dsp/computedRecipes",
+                Some("</v2/dsp/computedRecipes>; rel=\"successor-version\""),
+            ),
+            (
+                "/v1/dsp/computedRecipes",
+                Some("</v2/dsp/computedRecipes>; rel=\"successor-version\""),
+            ),
+            ("/v2/dsp/computedRecipes", None),
+        ] {
+            // rejected before reaching the database
+            let request = Request::post(path)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    r#"{"name": "Iron Ingot", "rate": 0, "requirements": {}}"#,
+                ))
+                .unwrap();
+            let response = app().oneshot(request).await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "{}",
+                path
+            );
+            let headers = response.headers();
+            assert_eq!(headers.contains_key("deprecation"), successor.is_some());
+            assert_eq!(
+                headers.get(header::LINK).map(|link| link.to_str().unwrap()),
+                successor
+            );
+        }
+    }
+
+    #[test]
+    fn test_spec_references() {
+        let spec = spec();
+        let schemas = spec["components"]["schemas"].as_object().unwrap();
+        let mut pending = vec![&spec];
+        let mut missing = BTreeSet::new();
+        while let Some(value) = pending.pop() {
+            match value {
+                serde_json::Value::Object(object) => {
+                    if let Some(reference) = object.get("$ref").and_then(|r| r.as_str()) {
+                        let name = reference.trim_start_matches("#/components/schemas/");
+                        if !schemas.contains_key(name) {
+                            missing.insert(name.to_string());
+                        }
+                    }
+                    pending.extend(object.values());
+                }
+                serde_json::Value::Array(values) => pending.extend(values),
+                _ => {}
+            }
+        }
+        assert!(
+            missing.is_empty(),
+            "schemas missing from the spec: {:?}",
+            missing
+        );
+        for schema in [
+            "ComputedRecipeRequest",
+            "ComputedRecipe",
+            "Recipe",
+            "MarketData",
+            "Plan",
+        ] {
+            assert!(
+                schemas.contains_key(schema),
+                "{} is not in the spec",
+                schema
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_spec() {
+        let request = Request::get(openapi::SPEC_PATH)
+            .body(Body::empty())
+            .unwrap();
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["openapi"].as_str().unwrap().starts_with("3."));
+
+        let request = Request::get(format!("{}/", openapi::DOCS_PATH))
+            .body(Body::empty())
+            .unwrap();
+        let response = app().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,207 @@
+use alex_api_rs::aliases::{AliasSource, ItemMatch};
+use alex_api_rs::data::{
+    Facility, Footprint, Item, ItemAlias, MarketData, OverrideAction, Recipe, RecipeOverride,
+    RecipePatch, RecipeVersion, Technology,
+};
+use alex_api_rs::diff::{RecipeChange, RecipeDiff};
+use alex_api_rs::dsp::{
+    ComputedRecipe, ComputedRecipeRequest, ImportOutcome, ItemDetail, RefreshOutcome,
+    RefreshRequest,
+};
+use alex_api_rs::error::{ErrorBody, ErrorResponse};
+use alex_api_rs::jobs::{JobEvent, JobState, JobStatus};
+use alex_api_rs::plan::{Plan, PlanInput, PlanNode, PlanSummary};
+use alex_api_rs::query::{RecipePage, RecipeSort, SortOrder};
+use alex_api_rs::scrape::report::{PageReport, ScrapeReport};
+use alex_api_rs::scrape::{PageStatus, ScrapeSummary};
+use alex_api_rs::transfer::DatasetFormat;
+use alex_api_rs::validate::{IssueKind, Severity, ValidationIssue, ValidationReport};
+use std::collections::BTreeSet;
+use utoipa::openapi::path::PathItem;
+use utoipa::openapi::{
+    ContentBuilder, Deprecated, OpenApi as OpenApiDocument, Ref, ResponseBuilder,
+};
+use utoipa::{Modify, OpenApi};
+
+/// Path the specification is served at.
+pub const SPEC_PATH: &str = "/openapi.json";
+
+/// Path of the Swagger UI reading the specification.
+pub const DOCS_PATH: &str = "/docs";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        description = "Dyson Sphere Program recipes scraped from the wiki, and production \
+                       planning on top of them"
+    ),
+    paths(
+        crate::dsp_recipes,
+        crate::dsp_query_recipes,
+        crate::dsp_computed_recipes,
+        crate::dsp_plan,
+        crate::dsp_reload_recipes,
+        crate::dsp_reload_recipes_get,
+        crate::dsp_jobs,
+        crate::dsp_job,
+        crate::dsp_job_events,
+        crate::dsp_cancel_job,
+        crate::dsp_promote_job,
+        crate::dsp_discard_job,
+        crate::dsp_scrape_reports,
+        crate::dsp_scrape_report,
+        crate::dsp_validate_recipes,
+        crate::dsp_validate_recipe_payload,
+        crate::dsp_export_recipes,
+        crate::dsp_import_recipes,
+        crate::dsp_recipe_versions,
+        crate::dsp_items,
+        crate::dsp_search_items,
+        crate::dsp_item,
+        crate::dsp_image,
+        crate::dsp_facilities,
+        crate::dsp_technologies,
+        crate::dsp_item_aliases,
+        crate::dsp_save_item_alias,
+        crate::dsp_delete_item_alias,
+        crate::dsp_recipe_overrides,
+        crate::dsp_recipe_override,
+        crate::dsp_save_recipe_override,
+        crate::dsp_delete_recipe_override,
+    ),
+    components(schemas(
+        AliasSource,
+        ComputedRecipe,
+        ComputedRecipeRequest,
+        DatasetFormat,
+        ErrorBody,
+        ErrorResponse,
+        Facility,
+        Footprint,
+        ImportOutcome,
+        IssueKind,
+        Item,
+        ItemAlias,
+        ItemDetail,
+        ItemMatch,
+        JobEvent,
+        JobState,
+        JobStatus,
+        MarketData,
+        OverrideAction,
+        PageReport,
+        PageStatus,
+        Plan,
+        PlanInput,
+        PlanNode,
+        PlanSummary,
+        Recipe,
+        RecipeChange,
+        RecipeDiff,
+        RecipeOverride,
+        RecipePage,
+        RecipePatch,
+        RecipeSort,
+        RecipeVersion,
+        RefreshOutcome,
+        RefreshRequest,
+        ScrapeReport,
+        ScrapeSummary,
+        Severity,
+        SortOrder,
+        Technology,
+        ValidationIssue,
+        ValidationReport,
+    )),
+    modifiers(&ErrorResponses),
+    tags(
+        (name = "recipes", description = "Recipes, production planning and dataset versions"),
+        (name = "jobs", description = "Refreshing the recipes from the wiki"),
+        (name = "items", description = "Items, facilities, technologies and their names"),
+        (
+            name = "overrides",
+            description = "Manual corrections applied on top of the scraped recipes"
+        ),
+    )
+)]
+pub struct ApiDoc;
+
+/// The specification served at `SPEC_PATH`.
+pub fn spec() -> OpenApiDocument {
+    let mut spec = ApiDoc::openapi();
+    // the package has no license, which would be listed as one without a name
+    spec.info.license = None;
+    version_paths(&mut spec);
+    spec
+}
+
+/// The handlers document the paths served without a version, which are also served under
+/// `V1` and `V2`. Only the `V2` paths are current, and the ones documented by a handler of
+/// their own replace the shared handler.
+fn version_paths(spec: &mut OpenApiDocument) {
+    let paths = std::mem::take(&mut spec.paths.paths);
+    let v2_paths: BTreeSet<String> = paths
+        .keys()
+        .filter(|path| path.starts_with(crate::V2))
+        .cloned()
+        .collect();
+    for (path, item) in paths {
+        if v2_paths.contains(&path) {
+            spec.paths.paths.insert(path, item);
+            continue;
+        }
+        let v2_path = format!("{}{}", crate::V2, path);
+        if !v2_paths.contains(&v2_path) {
+            spec.paths
+                .paths
+                .insert(v2_path, versioned(&item, "v2", false));
+        }
+        spec.paths.paths.insert(
+            format!("{}{}", crate::V1, path),
+            versioned(&item, "v1", true),
+        );
+        spec.paths.paths.insert(path, versioned(&item, "", true));
+    }
+}
+
+/// Copy of `item` whose operation ids start with `prefix`, as they must be unique.
+fn versioned(item: &PathItem, prefix: &str, deprecated: bool) -> PathItem {
+    let mut item = item.clone();
+    for operation in item.operations.values_mut() {
+        if !prefix.is_empty() {
+            operation.operation_id = operation
+                .operation_id
+                .as_ref()
+                .map(|id| format!("{}_{}", prefix, id));
+        }
+        if deprecated {
+            operation.deprecated = Some(Deprecated::True);
+        }
+    }
+    item
+}
+
+/// Every operation can fail with an `ErrorResponse`, whose status depends on the error code.
+struct ErrorResponses;
+
+impl Modify for ErrorResponses {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        let response = ResponseBuilder::new()
+            .description("Error, with the status of its code")
+            .content(
+                "application/json",
+                ContentBuilder::new()
+                    .schema(Ref::from_schema_name("ErrorResponse"))
+                    .build(),
+            )
+            .build();
+        for path in openapi.paths.paths.values_mut() {
+            for operation in path.operations.values_mut() {
+                operation
+                    .responses
+                    .responses
+                    .insert("default".to_string(), response.clone().into());
+            }
+        }
+    }
+}
@@ -1,17 +1,26 @@
-use crate::data::Recipe;
+use crate::aliases::AliasTable;
+use crate::data::{Facility, Recipe};
 
-use super::dsp::{ComputedRecipe, RecipeRequirements};
+use super::dsp::{ComputedRecipe, FacilityChoices, RecipeRequirements};
 use std::collections::HashMap;
 
 #[derive(Debug)]
 pub struct Optimizer {
     recipe_map: HashMap<String, Vec<Recipe>>,
+    facility_choices: FacilityChoices,
+    preferred_facilities: Vec<String>,
+    facilities: HashMap<String, Facility>,
+    aliases: AliasTable,
 }
 
 impl Optimizer {
     pub fn new() -> Self {
         Self {
             recipe_map: HashMap::new(),
+            facility_choices: HashMap::new(),
+            preferred_facilities: vec![],
+            facilities: HashMap::new(),
+            aliases: AliasTable::default(),
         }
     }
 
@@ -20,9 +29,57 @@ impl Optimizer {
         self.recipe_map = recipes;
     }
 
+    /// `choices` picks the facility per item. Items without a choice use the first of
+    /// `preferred` that can craft them, and the recipe's default facility otherwise.
+    #[tracing::instrument(skip(self))]
+    pub fn set_facility_choices(&mut self, choices: FacilityChoices, preferred: Vec<String>) {
+        self.facility_choices = choices
+            .into_iter()
+            .map(|(item_name, facility)| (item_name.to_lowercase(), facility))
+            .collect();
+        self.preferred_facilities = preferred;
+    }
+
+    /// Facility stats used for crafting speeds and power. Facilities without stats craft at
+    /// speed 1.0 and have no known power usage.
+    #[tracing::instrument(skip(self, facilities))]
+    pub fn set_facilities(&mut self, facilities: Vec<Facility>) {
+        self.facilities = facilities
+            .into_iter()
+            .map(|facility| (facility.name.to_lowercase(), facility))
+            .collect();
+    }
+
+    /// Names recipes can be looked up by besides the item name.
+    #[tracing::instrument(skip(self, aliases))]
+    pub fn set_aliases(&mut self, aliases: AliasTable) {
+        self.aliases = aliases;
+    }
+
+    fn crafting_speed(&self, facility: &str) -> f64 {
+        self.facilities
+            .get(&facility.to_lowercase())
+            .and_then(|facility| facility.crafting_speed)
+            .filter(|speed| *speed > 0.0)
+            .unwrap_or(1.0)
+    }
+
+    fn choose_facility(&self, recipe: &Recipe) -> String {
+        self.facility_choices
+            .get(&recipe.output_item.to_lowercase())
+            .and_then(|facility| recipe.find_facility(facility))
+            .or_else(|| {
+                self.preferred_facilities
+                    .iter()
+                    .find_map(|facility| recipe.find_facility(facility))
+            })
+            .unwrap_or(&recipe.facility)
+            .to_string()
+    }
+
     #[tracing::instrument(skip(self))]
     fn get_recipe(&self, item_name: String, recipe_idx: i64) -> Option<Recipe> {
-        let name = item_name.to_lowercase();
+        let name = self.aliases.canonical(&item_name).to_lowercase();
         let recipes = self.recipe_map.get(&name)?;
 
         if recipes.len() > recipe_idx as usize {
@@ -67,16 +124,21 @@ impl Optimizer {
 
         let recipe = recipe.unwrap();
 
+        let facility = self.choose_facility(&recipe);
+        let facility_speed = self.crafting_speed(&facility);
+
         let mut consumed_mats = HashMap::new();
         let mut number_of_facilities_needed = 0_f64;
         if recipe.output_item_count > 0.0 {
-            number_of_facilities_needed = recipe.time * crafting_speed / recipe.output_item_count;
+            number_of_facilities_needed =
+                recipe.time * crafting_speed / (recipe.output_item_count * facility_speed);
         }
         for (material_name, material_count) in recipe.materials.iter() {
             let mut new_material_count = 0_f64;
 
             if recipe.time > 0.0 {
-                new_material_count = material_count * number_of_facilities_needed / recipe.time;
+                new_material_count =
+                    material_count * number_of_facilities_needed * facility_speed / recipe.time;
             }
 
             consumed_mats.insert(material_name.clone(), new_material_count);
@@ -84,7 +146,12 @@ impl Optimizer {
 
         let computed_recipe: ComputedRecipe = ComputedRecipe {
             output_item: recipe.output_item.clone(),
-            facility: recipe.facility.clone(),
+            power_usage: self
+                .facilities
+                .get(&facility.to_lowercase())
+                .and_then(|facility| facility.work_power)
+                .map(|work_power| work_power * number_of_facilities_needed),
+            facility,
             num_facilities_needed: number_of_facilities_needed,
             items_consumed_per_sec: consumed_mats,
             seconds_spent_per_craft: recipe.time,
@@ -159,6 +226,10 @@ impl Optimizer {
                     u_recipe.used_for, recipe.used_for, recipe.crafting_per_sec
                 );
                 u_recipe.num_facilities_needed += recipe.num_facilities_needed;
+                u_recipe.power_usage = match (u_recipe.power_usage, recipe.power_usage) {
+                    (Some(a), Some(b)) => Some(a + b),
+                    (a, b) => a.or(b),
+                };
                 u_recipe.depth = max(u_recipe.depth, recipe.depth);
             } else {
                 let mut recipe = recipe.clone();
@@ -178,6 +249,12 @@ impl Optimizer {
     }
 }
 
+impl Default for Optimizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[tracing::instrument]
 fn max(a: Option<i64>, b: Option<i64>) -> Option<i64> {
     match (a, b) {
@@ -209,6 +286,7 @@ mod tests {
                 output_item: "Iron Ingot".to_string(),
                 facility: "Smelter".to_string(),
                 num_facilities_needed: 1.0,
+                power_usage: None,
                 items_consumed_per_sec: {
                     let mut m = std::collections::HashMap::new();
                     m.insert("Iron Ore".to_string(), 1.0);
@@ -224,6 +302,7 @@ mod tests {
                 output_item: "Iron Ingot".to_string(),
                 facility: "Smelter".to_string(),
                 num_facilities_needed: 1.0,
+                power_usage: None,
                 items_consumed_per_sec: {
                     let mut m = std::collections::HashMap::new();
                     m.insert("Iron Ore".to_string(), 1.0);
@@ -253,6 +332,7 @@ mod tests {
                 output_item: "Iron Ingot A".to_string(),
                 facility: "Smelter".to_string(),
                 num_facilities_needed: 1.0,
+                power_usage: None,
                 items_consumed_per_sec: {
                     let mut m = std::collections::HashMap::new();
                     m.insert("Iron Ore".to_string(), 1.0);
@@ -268,6 +348,7 @@ mod tests {
                 output_item: "Iron Ingot B".to_string(),
                 facility: "Smelter".to_string(),
                 num_facilities_needed: 1.0,
+                power_usage: None,
                 items_consumed_per_sec: {
                     let mut m = std::collections::HashMap::new();
                     m.insert("Iron Ore".to_string(), 1.0);
@@ -283,6 +364,7 @@ mod tests {
                 output_item: "Iron Ingot C".to_string(),
                 facility: "Smelter".to_string(),
                 num_facilities_needed: 1.0,
+                power_usage: None,
                 items_consumed_per_sec: {
                     let mut m = std::collections::HashMap::new();
                     m.insert("Iron Ore".to_string(), 1.0);
@@ -311,6 +393,7 @@ mod tests {
                 output_item: "Iron Ingot".to_string(),
                 output_item_count: 1.0,
                 facility: "Smelter".to_string(),
+                facilities: vec![],
                 time: 1.0,
                 materials: {
                     let mut m = std::collections::HashMap::new();
@@ -321,6 +404,7 @@ mod tests {
                 min_output_item_count: None,
                 max_output_item_count: None,
                 market_data: None,
+                technology: None,
             }],
         );
         recipe_map.insert(
@@ -329,12 +413,14 @@ mod tests {
                 output_item: "Iron Ore".to_string(),
                 output_item_count: 1.0,
                 facility: "Miner".to_string(),
+                facilities: vec![],
                 time: 1.0,
                 materials: std::collections::HashMap::new(),
                 image: None,
                 min_output_item_count: None,
                 max_output_item_count: None,
                 market_data: None,
+                technology: None,
             }],
         );
 
@@ -373,4 +459,80 @@ mod tests {
         assert_eq!(recipes[1].used_for, "Iron Ingot".to_string());
         assert_eq!(recipes[1].depth, Some(1));
     }
+
+    #[test]
+    fn test_choose_facility() {
+        let mut recipe = Recipe::new();
+        recipe.output_item = "Magnetic Coil".to_string();
+        recipe.facility = "Assembling Machine Mk.I".to_string();
+        recipe.facilities = vec![
+            "Assembling Machine Mk.I".to_string(),
+            "Assembling Machine Mk.II".to_string(),
+            "Assembling Machine Mk.III".to_string(),
+        ];
+
+        let mut optimizer = super::Optimizer::new();
+        assert_eq!(
+            optimizer.choose_facility(&recipe),
+            "Assembling Machine Mk.I"
+        );
+
+        optimizer.set_facility_choices(
+            std::collections::HashMap::new(),
+            vec![
+                "Plane Smelter".to_string(),
+                "assembling machine mk.iii".to_string(),
+            ],
+        );
+        assert_eq!(
+            optimizer.choose_facility(&recipe),
+            "Assembling Machine Mk.III"
+        );
+
+        optimizer.set_facility_choices(
+            [(
+                "magnetic coil".to_string(),
+                "Assembling Machine Mk.II".to_string(),
+            )]
+            .into(),
+            vec!["Assembling Machine Mk.III".to_string()],
+        );
+        assert_eq!(
+            optimizer.choose_facility(&recipe),
+            "Assembling Machine Mk.II"
+        );
+    }
+
+    #[test]
+    fn test_facility_speed_and_power() {
+        let mut recipe = Recipe::new();
+        recipe.output_item = "Gear".to_string();
+        recipe.output_item_count = 1.0;
+        recipe.facility = "Assembling Machine Mk.I".to_string();
+        recipe.time = 1.0;
+        recipe.materials.insert("Iron Ingot".to_string(), 1.0);
+
+        let mut facility = crate::data::Facility::new("Assembling Machine Mk.I");
+        facility.crafting_speed = Some(0.75);
+        facility.work_power = Some(270e3);
+
+        let mut optimizer = super::Optimizer::new();
+        optimizer.set_recipes([("gear".to_string(), vec![recipe])].into());
+        optimizer.set_facilities(vec![facility]);
+        let recipes = optimizer.get_optimal_recipe(
+            "gear".to_string(),
+            3.0,
+            "".to_string(),
+            &mut std::collections::HashMap::new(),
+            0,
+            std::collections::HashMap::new(),
+        );
+
+        assert_eq!(recipes[0].num_facilities_needed, 4.0);
+        assert_eq!(recipes[0].power_usage, Some(4.0 * 270e3));
+        assert_eq!(
+            recipes[0].items_consumed_per_sec.get("Iron Ingot"),
+            Some(&3.0)
+        );
+    }
 }
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+
+use crate::data::{OverrideAction, Recipe, RecipeOverride, RecipePatch};
+
+/// Applies manual overrides on top of a recipe map keyed by lowercase item name. Overrides are
+/// applied in id order so the result does not depend on the order they were stored in.
+#[tracing::instrument(skip(recipe_map, overrides))]
+pub fn apply_overrides(
+    recipe_map: &mut HashMap<String, Vec<Recipe>>,
+    overrides: &[RecipeOverride],
+) {
+    let mut overrides: Vec<&RecipeOverride> = overrides.iter().collect();
+    overrides.sort_by(|a, b| a.id.cmp(&b.id));
+
+    for recipe_override in overrides {
+        let key = recipe_override.output_item.to_lowercase();
+        match &recipe_override.action {
+            OverrideAction::Patch { patch } => {
+                if let Some(recipes) = recipe_map.get_mut(&key) {
+                    recipes
+                        .iter_mut()
+                        .filter(|recipe| is_targeted(recipe, recipe_override))
+                        .for_each(|recipe| apply_patch(recipe, patch));
+                } else {
+                    tracing::warn!(
+                        "Override {} patches unknown item {}",
+                        recipe_override.id,
+                        recipe_override.output_item
+                    );
+                }
+            }
+            OverrideAction::Replace { recipe } => {
+                if let Some(recipes) = recipe_map.get_mut(&key) {
+                    recipes.retain(|r| !is_targeted(r, recipe_override));
+                    if recipes.is_empty() {
+                        recipe_map.remove(&key);
+                    }
+                }
+                insert_recipe(recipe_map, recipe.clone());
+            }
+            OverrideAction::Add { recipe } => {
+                insert_recipe(recipe_map, recipe.clone());
+            }
+        }
+    }
+}
+
+fn is_targeted(recipe: &Recipe, recipe_override: &RecipeOverride) -> bool {
+    match &recipe_override.facility {
+        Some(facility) => recipe.find_facility(facility).is_some(),
+        None => true,
+    }
+}
+
+fn insert_recipe(recipe_map: &mut HashMap<String, Vec<Recipe>>, recipe: Recipe) {
+    recipe_map
+        .entry(recipe.output_item.to_lowercase())
+        .or_default()
+        .push(recipe);
+}
+
+fn apply_patch(recipe: &mut Recipe, patch: &RecipePatch) {
+    if let Some(output_item_count) = patch.output_item_count {
+        recipe.output_item_count = output_item_count;
+    }
+    if patch.min_output_item_count.is_some() {
+        recipe.min_output_item_count = patch.min_output_item_count;
+    }
+    if patch.max_output_item_count.is_some() {
+        recipe.max_output_item_count = patch.max_output_item_count;
+    }
+    if let Some(facilities) = &patch.facilities {
+        recipe.facilities = facilities.clone();
+    }
+    if let Some(facility) = &patch.facility {
+        recipe.facility = facility.clone();
+        if !recipe.facilities.is_empty() && !recipe.facilities.contains(facility) {
+            recipe.facilities.insert(0, facility.clone());
+        }
+    }
+    if let Some(time) = patch.time {
+        recipe.time = time;
+    }
+    if let Some(materials) = &patch.materials {
+        recipe.materials = materials.clone();
+    }
+    if patch.image.is_some() {
+        recipe.image = patch.image.clone();
+    }
+    if patch.technology.is_some() {
+        recipe.technology = patch.technology.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipe(output_item: &str, facility: &str, time: f64) -> Recipe {
+        let mut recipe = Recipe::new();
+        recipe.output_item = output_item.to_string();
+        recipe.output_item_count = 1.0;
+        recipe.facility = facility.to_string();
+        recipe.time = time;
+        recipe
+    }
+
+    fn recipe_map(recipes: Vec<Recipe>) -> HashMap<String, Vec<Recipe>> {
+        let mut map: HashMap<String, Vec<Recipe>> = HashMap::new();
+        for recipe in recipes {
+            insert_recipe(&mut map, recipe);
+        }
+        map
+    }
+
+    fn recipe_override(id: &str, output_item: &str, action: OverrideAction) -> RecipeOverride {
+        RecipeOverride {
+            id: id.to_string(),
+            output_item: output_item.to_string(),
+            facility: None,
+            action,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn test_patch_override() {
+        let mut map = recipe_map(vec![
+            recipe("Iron Ingot", "Arc Smelter", 1.0),
+            recipe("Iron Ingot", "Plane Smelter", 1.0),
+        ]);
+        let mut patch_override = recipe_override(
+            "a",
+            "iron ingot",
+            OverrideAction::Patch {
+                patch: RecipePatch {
+                    time: Some(2.0),
+                    ..Default::default()
+                },
+            },
+        );
+        patch_override.facility = Some("Plane Smelter".to_string());
+
+        apply_overrides(&mut map, &[patch_override]);
+
+        let recipes = &map["iron ingot"];
+        assert_eq!(recipes[0].time, 1.0);
+        assert_eq!(recipes[1].time, 2.0);
+    }
+
+    #[test]
+    fn test_replace_override() {
+        let mut map = recipe_map(vec![
+            recipe("Iron Ingot", "Arc Smelter", 1.0),
+            recipe("Iron Ingot", "Plane Smelter", 1.0),
+        ]);
+        let replace_override = recipe_override(
+            "a",
+            "Iron Ingot",
+            OverrideAction::Replace {
+                recipe: recipe("Iron Ingot", "Negentropy Smelter", 0.5),
+            },
+        );
+
+        apply_overrides(&mut map, &[replace_override]);
+
+        let recipes = &map["iron ingot"];
+        assert_eq!(recipes.len(), 1);
+        assert_eq!(recipes[0].facility, "Negentropy Smelter");
+    }
+
+    #[test]
+    fn test_add_override() {
+        let mut map = recipe_map(vec![recipe("Iron Ingot", "Arc Smelter", 1.0)]);
+        let add_override = recipe_override(
+            "a",
+            "Modded Ingot",
+            OverrideAction::Add {
+                recipe: recipe("Modded Ingot", "Arc Smelter", 3.0),
+            },
+        );
+
+        apply_overrides(&mut map, &[add_override]);
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["modded ingot"][0].time, 3.0);
+    }
+
+    #[test]
+    fn test_override_serde_shape() {
+        let json = serde_json::json!({
+            "id": "fix-iron",
+            "output_item": "Iron Ingot",
+            "facility": null,
+            "action": "patch",
+            "patch": { "time": 2.0 },
+            "reason": "wiki lags behind patch"
+        });
+
+        let recipe_override: RecipeOverride = serde_json::from_value(json.clone()).unwrap();
+        assert!(matches!(
+            recipe_override.action,
+            OverrideAction::Patch { ref patch } if patch.time == Some(2.0)
+        ));
+        assert_eq!(
+            serde_json::to_value(&recipe_override).unwrap()["action"],
+            "patch"
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+use crate::dsp::ComputedRecipe;
+
+/// A production plan as returned by `/v2/dsp/computedRecipes`: the chain of steps as a tree,
+/// and the totals of the whole chain.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Plan {
+    /// Item produced, with the name the recipes use.
+    pub item: String,
+    /// Items produced per second.
+    pub rate: f64,
+    pub root: PlanNode,
+    pub summary: PlanSummary,
+}
+
+/// A step of a plan, with the steps producing its inputs as children.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PlanNode {
+    pub item: String,
+    /// Items produced per second for the parent step, or for the plan at the root.
+    pub rate: f64,
+    pub facility: String,
+    pub num_facilities: f64,
+    /// Power drawn by the facilities while working, in watts. Unknown without facility stats.
+    pub power_usage: Option<f64>,
+    pub seconds_per_craft: f64,
+    pub image: Option<String>,
+    pub inputs: Vec<PlanInput>,
+    pub children: Vec<PlanNode>,
+}
+
+/// An item consumed by a step.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PlanInput {
+    pub item: String,
+    /// Items consumed per second.
+    pub rate: f64,
+    /// Whether the plan has no step producing the item, like ores mined by hand.
+    pub raw: bool,
+}
+
+/// Totals of a plan.
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct PlanSummary {
+    /// Facilities needed, by facility name.
+    pub facilities: HashMap<String, f64>,
+    /// Power drawn by the facilities with known stats, in watts. `None` when none are known.
+    pub power_usage: Option<f64>,
+    /// Raw inputs consumed per second, by item name.
+    pub raw_resources: HashMap<String, f64>,
+    /// Items produced per second, by item name, including the ones consumed by other steps.
+    pub production: HashMap<String, f64>,
+}
+
+/// Builds the plan of the steps returned by the optimizer, which lists every step after its
+/// parent, with its depth in the chain. `None` without steps.
+pub fn build_plan(steps: Vec<ComputedRecipe>) -> Option<Plan> {
+    let mut steps = steps.into_iter().peekable();
+    let root = build_node(steps.next()?, &mut steps);
+
+    let mut summary = PlanSummary::default();
+    summarize(&root, &mut summary);
+    Some(Plan {
+        item: root.item.clone(),
+        rate: root.rate,
+        root,
+        summary,
+    })
+}
+
+fn build_node(
+    step: ComputedRecipe,
+    steps: &mut std::iter::Peekable<impl Iterator<Item = ComputedRecipe>>,
+) -> PlanNode {
+    let depth = step.depth.unwrap_or(0);
+    let mut children = vec![];
+    while let Some(child) = steps.next_if(|child| child.depth.unwrap_or(0) > depth) {
+        children.push(build_node(child, steps));
+    }
+    children.sort_by(|a, b| a.item.cmp(&b.item));
+
+    let mut inputs: Vec<PlanInput> = step
+        .items_consumed_per_sec
+        .into_iter()
+        .map(|(item, rate)| PlanInput {
+            raw: !children.iter().any(|child| child.item == item),
+            item,
+            rate,
+        })
+        .collect();
+    inputs.sort_by(|a, b| a.item.cmp(&b.item));
+
+    PlanNode {
+        item: step.output_item,
+        rate: step.crafting_per_sec,
+        facility: step.facility,
+        num_facilities: step.num_facilities_needed,
+        power_usage: step.power_usage,
+        seconds_per_craft: step.seconds_spent_per_craft,
+        image: step.image,
+        inputs,
+        children,
+    }
+}
+
+fn summarize(node: &PlanNode, summary: &mut PlanSummary) {
+    *summary
+        .facilities
+        .entry(node.facility.clone())
+        .or_insert(0.0) += node.num_facilities;
+    if let Some(power_usage) = node.power_usage {
+        summary.power_usage = Some(summary.power_usage.unwrap_or(0.0) + power_usage);
+    }
+    *summary.production.entry(node.item.clone()).or_insert(0.0) += node.rate;
+    for input in node.inputs.iter().filter(|input| input.raw) {
+        *summary
+            .raw_resources
+            .entry(input.item.clone())
+            .or_insert(0.0) += input.rate;
+    }
+    for child in node.children.iter() {
+        summarize(child, summary);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aliases::AliasTable;
+    use crate::data::{Facility, Recipe};
+    use crate::dsp::{group_recipes, plan_recipes, ComputedRecipeRequest};
+
+    fn recipe(output_item: &str, facility: &str, materials: &[(&str, f64)]) -> Recipe {
+        let mut recipe = Recipe::new();
+        recipe.output_item = output_item.to_string();
+        recipe.output_item_count = 1.0;
+        recipe.facility = facility.to_string();
+        recipe.time = 1.0;
+        recipe.materials = materials
+            .iter()
+            .map(|(item, count)| (item.to_string(), *count))
+            .collect();
+        recipe
+    }
+
+    #[test]
+    fn test_build_plan() {
+        let recipes = group_recipes(vec![
+            recipe(
+                "Magnetic Coil",
+                "Assembling Machine Mk.I",
+                &[("Magnet", 2.0), ("Copper Ingot", 1.0)],
+            ),
+            recipe("Magnet", "Arc Smelter", &[("Iron Ore", 1.0)]),
+            recipe("Copper Ingot", "Arc Smelter", &[("Copper Ore", 1.0)]),
+        ]);
+        let mut smelter = Facility::new("Arc Smelter");
+        smelter.work_power = Some(360_000.0);
+        let request = ComputedRecipeRequest {
+            name: "magnetic coil".to_string(),
+            rate: 1.0,
+            requirements: HashMap::new(),
+            facilities: HashMap::new(),
+            preferred_facilities: vec![],
+            researched: None,
+        };
+        let steps =
+            plan_recipes(request, recipes, vec![smelter], &[], AliasTable::default()).unwrap();
+
+        let plan = build_plan(steps).unwrap();
+        assert_eq!(plan.item, "Magnetic Coil");
+        assert_eq!(plan.rate, 1.0);
+        let children: Vec<(&str, f64)> = plan
+            .root
+            .children
+            .iter()
+            .map(|child| (child.item.as_str(), child.rate))
+            .collect();
+        assert_eq!(children, [("Copper Ingot", 1.0), ("Magnet", 2.0)]);
+        assert!(plan.root.inputs.iter().all(|input| !input.raw));
+        let magnet = &plan.root.children[1];
+        assert!(magnet.children.is_empty());
+        assert_eq!(magnet.inputs[0].item, "Iron Ore");
+        assert!(magnet.inputs[0].raw);
+
+        assert_eq!(plan.summary.facilities["Arc Smelter"], 3.0);
+        assert_eq!(plan.summary.facilities["Assembling Machine Mk.I"], 1.0);
+        assert_eq!(plan.summary.power_usage, Some(1_080_000.0));
+        assert_eq!(
+            plan.summary.raw_resources,
+            HashMap::from([
+                ("Iron Ore".to_string(), 2.0),
+                ("Copper Ore".to_string(), 1.0)
+            ])
+        );
+        assert_eq!(plan.summary.production["Magnet"], 2.0);
+
+        assert!(build_plan(vec![]).is_none());
+    }
+}
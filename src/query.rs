@@ -0,0 +1,426 @@
+use mongodb::bson::{doc, Bson, Document};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::data::Recipe;
+
+const DEFAULT_PER_PAGE: usize = 50;
+const MAX_PER_PAGE: usize = 500;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RecipeSort {
+    #[default]
+    OutputItem,
+    Facility,
+    Time,
+    OutputItemCount,
+    /// Best name match first. Only meaningful together with `name`.
+    Relevance,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Filters for `/dsp/recipes/query`. Every filter is optional and they are combined with AND.
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RecipeQuery {
+    /// Case-insensitive facility name.
+    pub facility: Option<String>,
+    /// Case-insensitive name of a material the recipe consumes.
+    pub material: Option<String>,
+    /// Case-insensitive substring of the output item, or a fuzzy match when `fuzzy` is set.
+    pub name: Option<String>,
+    #[serde(default)]
+    pub fuzzy: bool,
+    pub min_time: Option<f64>,
+    pub max_time: Option<f64>,
+    #[serde(default)]
+    pub sort: RecipeSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    /// 1-based page number.
+    pub page: Option<usize>,
+    pub per_page: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RecipePage {
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+    pub recipes: Vec<Recipe>,
+}
+
+impl RecipeQuery {
+    pub fn page(&self) -> usize {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    pub fn per_page(&self) -> usize {
+        self.per_page
+            .unwrap_or(DEFAULT_PER_PAGE)
+            .clamp(1, MAX_PER_PAGE)
+    }
+
+    pub fn matches(&self, recipe: &Recipe) -> bool {
+        if let Some(facility) = &self.facility {
+            if recipe.find_facility(facility).is_none() {
+                return false;
+            }
+        }
+
+        if let Some(material) = &self.material {
+            if !recipe
+                .materials
+                .keys()
+                .any(|name| name.eq_ignore_ascii_case(material))
+            {
+                return false;
+            }
+        }
+
+        if let Some(name) = &self.name {
+            if self.name_score(name, &recipe.output_item).is_none() {
+                return false;
+            }
+        }
+
+        if let Some(min_time) = self.min_time {
+            if recipe.time < min_time {
+                return false;
+            }
+        }
+
+        if let Some(max_time) = self.max_time {
+            if recipe.time > max_time {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// A Mongo filter that narrows the candidates using the indexed fields. It is a superset of
+    /// what `matches` accepts, so results must still be passed through `apply`. The filter is
+    /// meant to run with a case-insensitive collation.
+    pub fn mongo_filter(&self) -> Document {
+        let mut filter = doc! {};
+
+        if let Some(facility) = &self.facility {
+            filter.insert(
+                "$or",
+                vec![
+                    doc! { "facility": facility.clone() },
+                    doc! { "facilities": facility.clone() },
+                ],
+            );
+        }
+
+        // fuzzy matching can't be expressed as an index lookup
+        if let (Some(name), false) = (&self.name, self.fuzzy) {
+            filter.insert(
+                "output_item",
+                doc! { "$regex": regex::escape(name), "$options": "i" },
+            );
+        }
+
+        let mut time = doc! {};
+        if let Some(min_time) = self.min_time {
+            time.insert("$gte", Bson::Double(min_time));
+        }
+        if let Some(max_time) = self.max_time {
+            time.insert("$lte", Bson::Double(max_time));
+        }
+        if !time.is_empty() {
+            filter.insert("time", time);
+        }
+
+        filter
+    }
+
+    /// Filters, sorts and paginates `recipes`.
+    pub fn apply(&self, recipes: Vec<Recipe>) -> RecipePage {
+        let mut recipes: Vec<Recipe> = recipes.into_iter().filter(|r| self.matches(r)).collect();
+
+        recipes.sort_by(|a, b| {
+            let ordering = match self.sort {
+                RecipeSort::OutputItem => a.output_item.cmp(&b.output_item),
+                RecipeSort::Facility => a.facility.cmp(&b.facility),
+                RecipeSort::Time => a.time.partial_cmp(&b.time).unwrap_or(Ordering::Equal),
+                RecipeSort::OutputItemCount => a
+                    .output_item_count
+                    .partial_cmp(&b.output_item_count)
+                    .unwrap_or(Ordering::Equal),
+                RecipeSort::Relevance => match &self.name {
+                    Some(name) => self
+                        .name_score(name, &a.output_item)
+                        .cmp(&self.name_score(name, &b.output_item)),
+                    None => Ordering::Equal,
+                },
+            }
+            .then_with(|| a.output_item.cmp(&b.output_item))
+            .then_with(|| a.facility.cmp(&b.facility));
+
+            match self.order {
+                SortOrder::Asc => ordering,
+                SortOrder::Desc => ordering.reverse(),
+            }
+        });
+
+        let total = recipes.len();
+        let page = self.page();
+        let per_page = self.per_page();
+        let recipes = recipes
+            .into_iter()
+            .skip((page - 1).saturating_mul(per_page))
+            .take(per_page)
+            .collect();
+
+        RecipePage {
+            total,
+            page,
+            per_page,
+            recipes,
+        }
+    }
+
+    fn name_score(&self, query: &str, name: &str) -> Option<usize> {
+        if self.fuzzy {
+            fuzzy_score(query, name)
+        } else if name.to_lowercase().contains(&query.to_lowercase()) {
+            Some(0)
+        } else {
+            None
+        }
+    }
+}
+
+/// Scores how well `query` matches `name`, lower is better. Substrings score 0. Otherwise each
+/// query word must be within a small edit distance of some word of the name, which tolerates
+/// typos like "magentic coil".
+pub fn fuzzy_score(query: &str, name: &str) -> Option<usize> {
+    let query = query.trim().to_lowercase();
+    let name = name.to_lowercase();
+
+    if query.is_empty() {
+        return Some(0);
+    }
+    if name.contains(&query) {
+        return Some(0);
+    }
+
+    let name_words: Vec<&str> = name.split(|c: char| !c.is_alphanumeric()).collect();
+    let mut score = 0;
+    for query_word in query.split(|c: char| !c.is_alphanumeric()) {
+        if query_word.is_empty() {
+            continue;
+        }
+        let allowed = (query_word.chars().count() / 4).max(1);
+        let best = name_words
+            .iter()
+            .filter(|word| !word.is_empty())
+            .map(|word| {
+                if word.starts_with(query_word) {
+                    0
+                } else {
+                    levenshtein(query_word, word)
+                }
+            })
+            .min()?;
+        if best > allowed {
+            return None;
+        }
+        score += best;
+    }
+
+    Some(score + 1)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, a_char) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let cost = usize::from(a_char != *b_char);
+            current[j + 1] = (previous[j] + cost)
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipe(output_item: &str, facility: &str, time: f64, materials: &[&str]) -> Recipe {
+        let mut recipe = Recipe::new();
+        recipe.output_item = output_item.to_string();
+        recipe.output_item_count = 1.0;
+        recipe.facility = facility.to_string();
+        recipe.time = time;
+        for material in materials {
+            recipe.materials.insert(material.to_string(), 1.0);
+        }
+        recipe
+    }
+
+    fn recipes() -> Vec<Recipe> {
+        vec![
+            recipe("Magnetic Coil", "Assembling Machine Mk.I", 1.0, &["Magnet"]),
+            recipe("Iron Ingot", "Arc Smelter", 1.0, &["Iron Ore"]),
+            recipe("Magnet", "Arc Smelter", 1.5, &["Iron Ore"]),
+            recipe("Gear", "Assembling Machine Mk.I", 1.0, &["Iron Ingot"]),
+        ]
+    }
+
+    fn names(page: &RecipePage) -> Vec<&str> {
+        page.recipes
+            .iter()
+            .map(|r| r.output_item.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_filter_by_facility_and_material() {
+        let query = RecipeQuery {
+            facility: Some("arc smelter".to_string()),
+            material: Some("IRON ORE".to_string()),
+            ..Default::default()
+        };
+
+        let page = query.apply(recipes());
+        assert_eq!(page.total, 2);
+        assert_eq!(names(&page), vec!["Iron Ingot", "Magnet"]);
+    }
+
+    #[test]
+    fn test_filter_by_name_and_time() {
+        let query = RecipeQuery {
+            name: Some("magnet".to_string()),
+            max_time: Some(1.0),
+            ..Default::default()
+        };
+
+        let page = query.apply(recipes());
+        assert_eq!(names(&page), vec!["Magnetic Coil"]);
+    }
+
+    #[test]
+    fn test_fuzzy_name() {
+        let query = RecipeQuery {
+            name: Some("magentic coil".to_string()),
+            fuzzy: true,
+            ..Default::default()
+        };
+
+        let page = query.apply(recipes());
+        assert_eq!(names(&page), vec!["Magnetic Coil"]);
+    }
+
+    #[test]
+    fn test_sort_and_paginate() {
+        let query = RecipeQuery {
+            sort: RecipeSort::Time,
+            order: SortOrder::Desc,
+            page: Some(2),
+            per_page: Some(2),
+            ..Default::default()
+        };
+
+        let page = query.apply(recipes());
+        assert_eq!(page.total, 4);
+        assert_eq!(page.page, 2);
+        assert_eq!(names(&page), vec!["Iron Ingot", "Gear"]);
+    }
+
+    #[test]
+    fn test_page_beyond_usize_is_empty() {
+        let query = RecipeQuery {
+            page: Some(usize::MAX),
+            per_page: Some(2),
+            ..Default::default()
+        };
+
+        let page = query.apply(recipes());
+        assert_eq!(page.total, 4);
+        assert!(page.recipes.is_empty());
+    }
+
+    #[test]
+    fn test_filter_by_eligible_facility() {
+        let mut iron_ingot = recipe("Iron Ingot", "Arc Smelter", 1.0, &["Iron Ore"]);
+        iron_ingot.facilities = vec!["Arc Smelter".to_string(), "Plane Smelter".to_string()];
+        let query = RecipeQuery {
+            facility: Some("plane smelter".to_string()),
+            ..Default::default()
+        };
+
+        assert!(query.matches(&iron_ingot));
+        assert!(!query.matches(&recipe("Magnet", "Arc Smelter", 1.5, &["Iron Ore"])));
+    }
+
+    #[test]
+    fn test_mongo_filter() {
+        let query = RecipeQuery {
+            facility: Some("Arc Smelter".to_string()),
+            name: Some("Mk.I".to_string()),
+            min_time: Some(1.0),
+            ..Default::default()
+        };
+
+        let filter = query.mongo_filter();
+        let facility_filter = filter.get_array("$or").unwrap();
+        assert_eq!(
+            facility_filter[0]
+                .as_document()
+                .unwrap()
+                .get_str("facility"),
+            Ok("Arc Smelter")
+        );
+        assert_eq!(
+            facility_filter[1]
+                .as_document()
+                .unwrap()
+                .get_str("facilities"),
+            Ok("Arc Smelter")
+        );
+        assert_eq!(
+            filter
+                .get_document("output_item")
+                .unwrap()
+                .get_str("$regex")
+                .unwrap(),
+            "Mk\\.I"
+        );
+        assert_eq!(
+            filter
+                .get_document("time")
+                .unwrap()
+                .get_f64("$gte")
+                .unwrap(),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("magnet", "magnet"), 0);
+        assert_eq!(levenshtein("magent", "magnet"), 2);
+        assert_eq!(levenshtein("", "abc"), 3);
+    }
+}
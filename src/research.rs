@@ -0,0 +1,196 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::data::{Recipe, Technology};
+use crate::dsp::ComputedRecipe;
+
+/// Technologies researched so far, matched case-insensitively.
+#[derive(Debug, Clone, Default)]
+pub struct Research {
+    researched: HashSet<String>,
+}
+
+impl Research {
+    pub fn new(technologies: &[String]) -> Self {
+        Self {
+            researched: technologies
+                .iter()
+                .map(|technology| technology.to_lowercase())
+                .collect(),
+        }
+    }
+
+    pub fn add(&mut self, technology: &str) {
+        self.researched.insert(technology.to_lowercase());
+    }
+
+    pub fn is_researched(&self, technology: &str) -> bool {
+        self.researched.contains(&technology.to_lowercase())
+    }
+
+    /// Recipes without a technology are available from the start.
+    pub fn is_unlocked(&self, recipe: &Recipe) -> bool {
+        match &recipe.technology {
+            Some(technology) => self.is_researched(technology),
+            None => true,
+        }
+    }
+
+    /// Removes the recipes that aren't unlocked from a recipe map keyed by lowercase item name.
+    /// Returns the removed recipes of the items left without any recipe, keyed the same way.
+    pub fn lock_recipes(
+        &self,
+        recipe_map: &mut HashMap<String, Vec<Recipe>>,
+    ) -> HashMap<String, Vec<Recipe>> {
+        let mut locked = HashMap::new();
+        recipe_map.retain(|item_name, recipes| {
+            let (unlocked, removed): (Vec<Recipe>, Vec<Recipe>) = recipes
+                .drain(..)
+                .partition(|recipe| self.is_unlocked(recipe));
+            *recipes = unlocked;
+            if recipes.is_empty() {
+                locked.insert(item_name.clone(), removed);
+                return false;
+            }
+            true
+        });
+        locked
+    }
+}
+
+/// Technologies to research so that every item of the plan can be crafted: the item itself and
+/// every material it consumes that has only locked recipes. The first locked recipe of each
+/// such item decides the technology.
+pub fn blocking_technologies(
+    item_name: &str,
+    plan: &[ComputedRecipe],
+    locked: &HashMap<String, Vec<Recipe>>,
+) -> Vec<String> {
+    let mut technologies: Vec<String> = std::iter::once(item_name)
+        .chain(
+            plan.iter()
+                .flat_map(|recipe| recipe.items_consumed_per_sec.keys())
+                .map(|material| material.as_str()),
+        )
+        .filter_map(|name| locked.get(&name.to_lowercase()))
+        .filter_map(|recipes| recipes.iter().find_map(|recipe| recipe.technology.clone()))
+        .collect();
+    technologies.sort();
+    technologies.dedup();
+    technologies
+}
+
+/// `technologies` together with every prerequisite that hasn't been researched, sorted by name.
+/// Technologies missing from `catalogue` are kept without prerequisites.
+pub fn with_prerequisites(
+    technologies: Vec<String>,
+    catalogue: &[Technology],
+    research: &Research,
+) -> Vec<String> {
+    let by_name: HashMap<String, &Technology> = catalogue
+        .iter()
+        .map(|technology| (technology.name.to_lowercase(), technology))
+        .collect();
+
+    let mut seen = HashSet::new();
+    let mut missing = vec![];
+    let mut pending = technologies;
+    while let Some(name) = pending.pop() {
+        if research.is_researched(&name) || !seen.insert(name.to_lowercase()) {
+            continue;
+        }
+        if let Some(technology) = by_name.get(&name.to_lowercase()) {
+            pending.extend(technology.prerequisites.iter().cloned());
+        }
+        missing.push(name);
+    }
+    missing.sort();
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipe(output_item: &str, technology: Option<&str>) -> Recipe {
+        let mut recipe = Recipe::new();
+        recipe.output_item = output_item.to_string();
+        recipe.technology = technology.map(|technology| technology.to_string());
+        recipe
+    }
+
+    fn technology(name: &str, prerequisites: &[&str]) -> Technology {
+        let mut technology = Technology::new(name);
+        technology.prerequisites = prerequisites.iter().map(|p| p.to_string()).collect();
+        technology
+    }
+
+    #[test]
+    fn test_lock_recipes() {
+        let mut recipe_map = HashMap::from([
+            (
+                "graphene".to_string(),
+                vec![
+                    recipe("Graphene", Some("Basic Chemical Engineering")),
+                    recipe("Graphene", Some("Fire Ice Utilization")),
+                ],
+            ),
+            ("iron ingot".to_string(), vec![recipe("Iron Ingot", None)]),
+        ]);
+
+        let research = Research::new(&["fire ice utilization".to_string()]);
+        let locked = research.lock_recipes(&mut recipe_map.clone());
+        assert!(locked.is_empty());
+
+        let locked = Research::default().lock_recipes(&mut recipe_map);
+        assert_eq!(recipe_map.len(), 1);
+        assert_eq!(locked["graphene"].len(), 2);
+    }
+
+    #[test]
+    fn test_blocking_technologies() {
+        let locked = HashMap::from([(
+            "magnetic coil".to_string(),
+            vec![recipe("Magnetic Coil", Some("Electromagnetism"))],
+        )]);
+        let plan = vec![ComputedRecipe {
+            output_item: "Electric Motor".to_string(),
+            facility: "Assembling Machine Mk.I".to_string(),
+            num_facilities_needed: 1.0,
+            power_usage: None,
+            items_consumed_per_sec: HashMap::from([
+                ("Magnetic Coil".to_string(), 1.0),
+                ("Iron Ingot".to_string(), 2.0),
+            ]),
+            seconds_spent_per_craft: 2.0,
+            crafting_per_sec: 0.5,
+            used_for: "".to_string(),
+            depth: Some(0),
+            image: None,
+        }];
+
+        assert_eq!(
+            blocking_technologies("Electric Motor", &plan, &locked),
+            vec!["Electromagnetism"]
+        );
+        assert!(blocking_technologies("Electric Motor", &plan, &HashMap::new()).is_empty());
+    }
+
+    #[test]
+    fn test_with_prerequisites() {
+        let catalogue = vec![
+            technology("Electromagnetism", &[]),
+            technology("Basic Assembling Processes", &["Electromagnetism"]),
+            technology("Electromagnetic Matrix", &["Basic Assembling Processes"]),
+        ];
+
+        let missing = with_prerequisites(
+            vec!["Electromagnetic Matrix".to_string()],
+            &catalogue,
+            &Research::new(&["electromagnetism".to_string()]),
+        );
+        assert_eq!(
+            missing,
+            vec!["Basic Assembling Processes", "Electromagnetic Matrix"]
+        );
+    }
+}
@@ -1,11 +1,37 @@
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
 use std::io::Write;
-use std::thread;
-use std::time::Duration;
-use std::{collections::HashMap, fs::File};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
 
-use crate::data::{Materials, Recipe};
+use crate::data::{Facility, Item, Recipe, Technology};
 use crate::timekeeper;
 
+pub mod cache;
+pub mod images;
+pub mod parse;
+pub mod rate_limit;
+pub mod report;
+pub mod retry;
+pub mod robots;
+pub mod source;
+pub mod wikitext;
+
+use cache::{CachedPage, PageCache, PARSER_VERSION};
+use images::ImageStore;
+use rate_limit::RateLimiter;
+use report::PageReport;
+use retry::RequestGate;
+pub use retry::{RetryPolicy, RetryRequest};
+use robots::RobotsPolicy;
+use source::{FetchedPage, HttpSource, MediaWikiSource, PageSource};
+
+pub const DEFAULT_BASE_URL: &str = "https://dsp-wiki.com";
+
 #[derive(Debug, Clone)]
 pub struct MissingProductionTableError {
     pub url: String,
@@ -13,289 +39,909 @@ pub struct MissingProductionTableError {
 
 #[derive(Debug)]
 pub enum RetryRequestError {
+    /// The page was fetched but has nothing to parse. Never retried.
     MissingProductionTableError(MissingProductionTableError),
+    /// The server answered with a 4xx or 5xx status.
+    HttpStatusError {
+        url: String,
+        status: reqwest::StatusCode,
+        retry_after: Option<Duration>,
+    },
+    TimeoutError {
+        url: String,
+    },
+    /// Connection or body errors.
     ReqwestError(reqwest::Error),
+    IoError(std::io::Error),
+    /// The MediaWiki api answered with an error, e.g. `missingtitle`. Never retried.
+    ApiError {
+        url: String,
+        code: String,
+        info: String,
+    },
+    /// The wiki's robots.txt disallows the page. Never retried.
+    DisallowedByRobots {
+        url: String,
+    },
 }
 
-#[derive(Debug)]
-pub struct RetryRequest<'a> {
-    pub url: &'a str,
-}
+impl RetryRequestError {
+    /// Whether retrying the same request may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            RetryRequestError::HttpStatusError { status, .. } => {
+                retry::is_transient_status(*status)
+            }
+            RetryRequestError::TimeoutError { .. } | RetryRequestError::ReqwestError(_) => true,
+            RetryRequestError::MissingProductionTableError(_)
+            | RetryRequestError::IoError(_)
+            | RetryRequestError::ApiError { .. }
+            | RetryRequestError::DisallowedByRobots { .. } => false,
+        }
+    }
 
-impl<'a> RetryRequest<'a> {
-    pub fn new(url: &'a str) -> Self {
-        Self { url }
+    /// Name of the variant, as recorded in scrape reports.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RetryRequestError::MissingProductionTableError(_) => "MissingProductionTableError",
+            RetryRequestError::HttpStatusError { .. } => "HttpStatusError",
+            RetryRequestError::TimeoutError { .. } => "TimeoutError",
+            RetryRequestError::ReqwestError(_) => "ReqwestError",
+            RetryRequestError::IoError(_) => "IoError",
+            RetryRequestError::ApiError { .. } => "ApiError",
+            RetryRequestError::DisallowedByRobots { .. } => "DisallowedByRobots",
+        }
     }
 
-    #[tracing::instrument(skip(self))]
-    pub async fn fetch(&self) -> Result<String, RetryRequestError> {
-        let mut retries = 0;
-        loop {
-            let response = reqwest::get(self.url).await;
-            match response {
-                Ok(response) => {
-                    let text_response = response.text().await.unwrap();
-                    let document = scraper::Html::parse_document(&text_response);
-                    let table_selector =
-                        scraper::Selector::parse("table.pc_table:nth-of-type(1)").unwrap();
-                    let table_elems: Vec<_> = document.select(&table_selector).collect();
-
-                    if let Some(_table) = table_elems.first() {
-                        return Ok(text_response);
-                    } else {
-                        println!("Unable to find production chain table for url {}", self.url);
-                        retries += 1;
-                        thread::sleep(Duration::from_millis(5000));
-                        if retries > 3 {
-                            return Err(RetryRequestError::MissingProductionTableError(
-                                MissingProductionTableError {
-                                    url: self.url.to_string(),
-                                },
-                            ));
-                        }
-                    }
-                }
-                Err(err) => {
-                    println!("Error fetching url: {}", err);
-                    retries += 1;
-                    thread::sleep(Duration::from_millis(5000));
-                    if retries > 3 {
-                        return Err(RetryRequestError::ReqwestError(err));
-                    }
-                }
+    /// Delay requested by the server through a `Retry-After` header.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            RetryRequestError::HttpStatusError { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for RetryRequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RetryRequestError::MissingProductionTableError(err) => {
+                write!(f, "missing production table for url {}", err.url)
+            }
+            RetryRequestError::HttpStatusError { url, status, .. } => {
+                write!(f, "{} returned {}", url, status)
+            }
+            RetryRequestError::TimeoutError { url } => write!(f, "{} timed out", url),
+            RetryRequestError::ReqwestError(err) => write!(f, "request failed: {}", err),
+            RetryRequestError::IoError(err) => write!(f, "read failed: {}", err),
+            RetryRequestError::ApiError { url, code, info } => {
+                write!(f, "api error {} for {}: {}", code, url, info)
+            }
+            RetryRequestError::DisallowedByRobots { url } => {
+                write!(f, "{} is disallowed by robots.txt", url)
             }
         }
     }
 }
 
+/// How pages are read from the wiki.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    /// Rendered html pages, parsed with CSS selectors.
+    Html,
+    /// Page wikitext from the MediaWiki `api.php`, parsed from the templates.
+    MediaWikiApi,
+}
+
+impl std::str::FromStr for SourceKind {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "html" => Ok(SourceKind::Html),
+            "api" | "mediawiki" => Ok(SourceKind::MediaWikiApi),
+            _ => Err(format!("unknown scrape source {}", value)),
+        }
+    }
+}
+
+/// Which wiki a scrape run reads and how hard it may hit it.
+#[derive(Debug, Clone)]
+pub struct ScrapeConfig {
+    pub source: SourceKind,
+    /// Address of the wiki, e.g. a local mirror. Page, image and `api.php` urls start with it.
+    pub base_url: String,
+    /// Sent with every request. Should name the scraper and a way to contact its operator.
+    pub user_agent: String,
+    pub connect_timeout: Duration,
+    /// Skip pages disallowed by the wiki's robots.txt and wait out its crawl delay.
+    pub respect_robots: bool,
+    /// Number of pages fetched at the same time.
+    pub concurrency: usize,
+    /// Sustained request rate. 0 disables the rate limit.
+    pub requests_per_second: f64,
+    /// Number of requests that may be sent back to back before the rate limit applies.
+    pub burst: u32,
+    pub retry: RetryPolicy,
+    /// Where fetched pages are cached between runs. `None` disables the cache.
+    pub cache_dir: Option<PathBuf>,
+    /// Where a copy of the scraped recipes is written after each full run.
+    pub recipes_file: Option<PathBuf>,
+    /// Where recipe and item icons are mirrored. `None` keeps the wiki urls.
+    pub image_dir: Option<PathBuf>,
+    /// Share of failed pages above which a run is not promoted.
+    pub max_failed_ratio: f64,
+}
+
+impl Default for ScrapeConfig {
+    fn default() -> Self {
+        Self {
+            source: SourceKind::Html,
+            base_url: DEFAULT_BASE_URL.to_string(),
+            user_agent: concat!("alex-api-rs/", env!("CARGO_PKG_VERSION")).to_string(),
+            connect_timeout: Duration::from_secs(10),
+            respect_robots: true,
+            concurrency: 4,
+            requests_per_second: 2.0,
+            burst: 4,
+            retry: RetryPolicy::default(),
+            cache_dir: None,
+            recipes_file: None,
+            image_dir: None,
+            max_failed_ratio: 0.1,
+        }
+    }
+}
+
+impl ScrapeConfig {
+    /// Reads the configuration from these variables, falling back to the defaults for unset or
+    /// unparsable values:
+    ///
+    /// - `SCRAPE_SOURCE`: `html` or `api`
+    /// - `SCRAPE_BASE_URL`
+    /// - `SCRAPE_USER_AGENT`
+    /// - `SCRAPE_CONNECT_TIMEOUT_SECS` and `SCRAPE_TIMEOUT_SECS`, positive numbers of seconds
+    /// - `SCRAPE_RESPECT_ROBOTS`
+    /// - `SCRAPE_CONCURRENCY`
+    /// - `SCRAPE_REQUESTS_PER_SECOND` and `SCRAPE_BURST`
+    /// - `SCRAPE_MAX_RETRIES`
+    /// - `SCRAPE_MAX_FAILED_RATIO`
+    /// - `SCRAPE_CACHE_DIR`: `.cache/dsp-wiki` when unset, an empty string disables the cache
+    /// - `SCRAPE_IMAGE_DIR`: mirroring is off unless it is set to a non-empty path
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            source: env_or("SCRAPE_SOURCE", defaults.source),
+            base_url: env_or("SCRAPE_BASE_URL", defaults.base_url)
+                .trim_end_matches('/')
+                .to_string(),
+            user_agent: env_or("SCRAPE_USER_AGENT", defaults.user_agent),
+            connect_timeout: env_secs("SCRAPE_CONNECT_TIMEOUT_SECS", defaults.connect_timeout),
+            respect_robots: env_or("SCRAPE_RESPECT_ROBOTS", defaults.respect_robots),
+            concurrency: env_or("SCRAPE_CONCURRENCY", defaults.concurrency).max(1),
+            requests_per_second: env_or("SCRAPE_REQUESTS_PER_SECOND", defaults.requests_per_second),
+            burst: env_or("SCRAPE_BURST", defaults.burst),
+            retry: RetryPolicy {
+                max_retries: env_or("SCRAPE_MAX_RETRIES", defaults.retry.max_retries),
+                timeout: env_secs("SCRAPE_TIMEOUT_SECS", defaults.retry.timeout),
+                ..defaults.retry
+            },
+            cache_dir: match std::env::var("SCRAPE_CACHE_DIR") {
+                Ok(dir) if dir.is_empty() => None,
+                Ok(dir) => Some(PathBuf::from(dir)),
+                Err(_) => Some(PathBuf::from(".cache/dsp-wiki")),
+            },
+            recipes_file: Some(PathBuf::from("recipes.json")),
+            image_dir: std::env::var("SCRAPE_IMAGE_DIR")
+                .ok()
+                .filter(|dir| !dir.is_empty())
+                .map(PathBuf::from),
+            max_failed_ratio: env_or("SCRAPE_MAX_FAILED_RATIO", defaults.max_failed_ratio),
+        }
+    }
+
+    /// Client sending the configured User-Agent, with the connect timeout applied. Fails for a
+    /// User-Agent that isn't a valid header value.
+    pub fn http_client(&self) -> Result<reqwest::Client, RetryRequestError> {
+        reqwest::Client::builder()
+            .user_agent(self.user_agent.clone())
+            .connect_timeout(self.connect_timeout)
+            .build()
+            .map_err(RetryRequestError::ReqwestError)
+    }
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    std::env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// A duration in seconds. Negative, zero and non-finite values fall back to `default`.
+fn env_secs(key: &str, default: Duration) -> Duration {
+    std::env::var(key)
+        .ok()
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|secs| secs.is_finite() && *secs > 0.0)
+        .map(Duration::from_secs_f64)
+        .unwrap_or(default)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PageStatus {
+    /// New page, or its content changed since the cached copy.
+    Changed,
+    Unchanged,
+    Failed,
+}
+
 #[derive(Debug)]
-pub struct Scraper {}
+pub struct ScrapedPage {
+    pub url: String,
+    pub status: PageStatus,
+    pub recipes: Vec<Recipe>,
+    pub item: Option<Item>,
+    pub facility: Option<Facility>,
+    pub technology: Option<Technology>,
+    pub error: Option<RetryRequestError>,
+    /// Requests sent for the page, retries included.
+    pub attempts: u32,
+    pub duration: Duration,
+}
 
-impl Scraper {
-    pub fn new() -> Self {
-        Self {}
+impl ScrapedPage {
+    pub fn report(&self) -> PageReport {
+        PageReport {
+            url: self.url.clone(),
+            status: self.status,
+            error_kind: self.error.as_ref().map(|err| err.kind().to_string()),
+            error: self.error.as_ref().map(|err| err.to_string()),
+            attempts: self.attempts,
+            duration_ms: self.duration.as_millis() as u64,
+        }
     }
 
-    #[tracing::instrument(skip(self))]
-    pub async fn scrape_dsp_data(&self, mut urls: Vec<String>) -> Vec<Vec<Recipe>> {
-        let mut timekeeper = timekeeper::TimeKeeper::new();
-        println!("Start {:?}", timekeeper.start());
+    /// Same as [`PageReport::is_failed`]: pages without a production table don't count.
+    pub fn is_failed(&self) -> bool {
+        self.status == PageStatus::Failed
+            && !matches!(
+                self.error,
+                Some(RetryRequestError::MissingProductionTableError(_))
+            )
+    }
 
-        if urls.is_empty() {
-            urls = self.get_urls().await;
-            urls.iter().for_each(|item| println!("{}", item));
+    fn failed(url: &str, err: RetryRequestError) -> Self {
+        tracing::warn!("Error fetching url: {}", err);
+        Self {
+            url: url.to_string(),
+            status: PageStatus::Failed,
+            recipes: vec![],
+            item: None,
+            facility: None,
+            technology: None,
+            error: Some(err),
+            attempts: 0,
+            duration: Duration::ZERO,
         }
+    }
+}
 
-        let mut recipe_lists = vec![];
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct ScrapeSummary {
+    pub pages: usize,
+    pub changed_pages: usize,
+    pub unchanged_pages: usize,
+    pub failed_pages: usize,
+    /// Building pages fetched only for their facility stats.
+    pub facility_pages: usize,
+    /// Technology pages of the scraped recipes and their prerequisites.
+    pub technology_pages: usize,
+}
 
-        for (i, url) in urls.iter().enumerate() {
-            println!(
-                "[{}/{} {}%] {:?}",
-                i + 1,
-                urls.len(),
-                (i + 1) * 100 / urls.len(),
-                timekeeper.tick()
-            );
+impl ScrapeSummary {
+    pub fn from_pages(pages: &[ScrapedPage]) -> Self {
+        let count = |status| pages.iter().filter(|page| page.status == status).count();
+        Self {
+            pages: pages.len(),
+            changed_pages: count(PageStatus::Changed),
+            unchanged_pages: count(PageStatus::Unchanged),
+            failed_pages: pages.iter().filter(|page| page.is_failed()).count(),
+            facility_pages: 0,
+            technology_pages: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ScrapeRun {
+    pub recipe_lists: Vec<Vec<Recipe>>,
+    pub items: Vec<Item>,
+    pub facilities: Vec<Facility>,
+    pub technologies: Vec<Technology>,
+    /// Every page attempted, in scrape order.
+    pub pages: Vec<PageReport>,
+    pub summary: ScrapeSummary,
+}
+
+/// Follows the progress of a scrape run, e.g. to report it from a background job.
+pub trait ScrapeObserver: std::fmt::Debug + Send + Sync {
+    /// More pages will be scraped. Building and technology pages are only known once the item
+    /// pages are done, so the total grows during a run.
+    fn pages_queued(&self, _count: usize) {}
+
+    /// The request for the page is about to be sent.
+    fn page_started(&self, _url: &str) {}
+
+    fn recipe_parsed(&self, _url: &str, _recipe: &Recipe) {}
 
-            let recipe_list = self.scrape_url(url).await;
-            recipe_lists.push(recipe_list);
-            thread::sleep(Duration::from_millis(1000));
+    /// The page is done, successfully or not.
+    fn page_scraped(&self, _page: &ScrapedPage) {}
+
+    /// Checked after every page. Once it returns true no further pages are scraped.
+    fn is_cancelled(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug)]
+pub struct Scraper {
+    source: Box<dyn PageSource>,
+    config: ScrapeConfig,
+    pacing: Pacing,
+    cache: Option<PageCache>,
+    images: Option<ImageStore>,
+    /// Client for requests outside the page source, e.g. images.
+    client: reqwest::Client,
+    observer: Option<Arc<dyn ScrapeObserver>>,
+}
+
+/// The rate limit and robots.txt every request of a scraper, retries included, goes through.
+#[derive(Debug, Clone)]
+struct Pacing {
+    limiter: Arc<RateLimiter>,
+    robots: Option<Arc<RobotsPolicy>>,
+}
+
+#[async_trait]
+impl RequestGate for Pacing {
+    async fn acquire(&self, url: &str) -> Result<(), RetryRequestError> {
+        self.limiter.acquire().await;
+        if let Some(robots) = &self.robots {
+            robots.acquire(url).await?;
         }
+        Ok(())
+    }
+}
 
-        println!("End {:?}", timekeeper.end());
+impl Scraper {
+    /// Scraper configured from the environment. Fails when the configured client can't be
+    /// built.
+    pub fn new() -> Result<Self, RetryRequestError> {
+        let config = ScrapeConfig::from_env();
+        let client = config.http_client()?;
+        let source: Box<dyn PageSource> = match config.source {
+            SourceKind::Html => Box::new(HttpSource::with_client(
+                client.clone(),
+                config.retry.clone(),
+            )),
+            SourceKind::MediaWikiApi => Box::new(MediaWikiSource::with_client(
+                &format!("{}/api.php", config.base_url),
+                client.clone(),
+                config.retry.clone(),
+            )),
+        };
+        let robots = config.respect_robots.then(|| {
+            RobotsPolicy::new(
+                &config.base_url,
+                &config.user_agent,
+                client,
+                config.retry.timeout,
+            )
+        });
+        let scraper = Self::with_config(source, config)?;
+        Ok(match robots {
+            Some(robots) => scraper.with_robots(robots),
+            None => scraper,
+        })
+    }
 
-        // write recipe lists to json file
-        let mut file = File::create("recipes.json").unwrap();
-        let json = serde_json::to_string_pretty(&recipe_lists).unwrap();
-        file.write_all(json.as_bytes()).unwrap();
+    pub fn with_source(source: Box<dyn PageSource>) -> Self {
+        Self::with_config(source, ScrapeConfig::default())
+            .expect("the default config builds a client")
+    }
 
-        recipe_lists
+    pub fn with_config(
+        source: Box<dyn PageSource>,
+        config: ScrapeConfig,
+    ) -> Result<Self, RetryRequestError> {
+        let pacing = Pacing {
+            limiter: Arc::new(RateLimiter::new(config.requests_per_second, config.burst)),
+            robots: None,
+        };
+        let cache = config.cache_dir.clone().map(PageCache::new);
+        let images = config.image_dir.clone().map(ImageStore::new);
+        let client = config.http_client()?;
+        Ok(Self {
+            source,
+            config,
+            pacing,
+            cache,
+            images,
+            client,
+            observer: None,
+        })
     }
 
-    #[tracing::instrument(skip(self))]
-    pub async fn get_urls(&self) -> Vec<String> {
-        let response = reqwest::get("https://dsp-wiki.com/Items")
-            .await
-            .unwrap()
-            .text()
-            .await
-            .unwrap();
+    /// Checks every page against the wiki's robots.txt before fetching it.
+    pub fn with_robots(mut self, robots: RobotsPolicy) -> Self {
+        self.pacing.robots = Some(Arc::new(robots));
+        self
+    }
 
-        let document = scraper::Html::parse_document(&response);
-        let selector = scraper::Selector::parse("div.item_icon_container a[href]").unwrap();
-        let urls = document
-            .select(&selector)
-            .map(|x| format!("https://dsp-wiki.com{}", x.attr("href").unwrap()));
+    pub fn with_observer(mut self, observer: Arc<dyn ScrapeObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
 
-        // uniqify
-        let mut urls: Vec<String> = urls.collect();
-        urls.sort();
-        urls.dedup();
+    pub fn config(&self) -> &ScrapeConfig {
+        &self.config
+    }
 
-        println!("URL Count: {}", urls.len());
-        urls
+    fn is_cancelled(&self) -> bool {
+        self.observer
+            .as_ref()
+            .is_some_and(|observer| observer.is_cancelled())
     }
 
     #[tracing::instrument(skip(self))]
-    pub async fn scrape_url(&self, url: &str) -> Vec<Recipe> {
-        println!("Scraping URL: {}", url);
+    pub async fn scrape_dsp_data(
+        &self,
+        mut urls: Vec<String>,
+    ) -> Result<ScrapeRun, RetryRequestError> {
+        let mut timekeeper = timekeeper::TimeKeeper::new();
+        timekeeper.start();
+
+        // only a full run has the whole dataset to write to the recipes file
+        let full_run = urls.is_empty();
+        if full_run {
+            urls = self.get_urls().await?;
+        }
 
-        let mut recipes: Vec<Recipe> = vec![];
+        let pages = self.scrape_urls(&urls).await;
+        let mut summary = ScrapeSummary::from_pages(&pages);
 
-        let retry_request = RetryRequest::new(url);
-        let response = retry_request.fetch().await;
+        // building pages of facilities that weren't part of the scraped urls
+        let facility_urls = missing_facility_urls(&self.config.base_url, &pages);
+        let facility_pages = self.scrape_urls(&facility_urls).await;
+        summary.facility_pages = facility_pages.len();
 
-        if response.is_err() {
-            println!("Error fetching url: {:?}", response);
-            return recipes;
+        // technology pages unlocking the scraped recipes, then their prerequisites until the
+        // tree is complete
+        let mut scraped_urls: Vec<String> =
+            urls.iter().chain(facility_urls.iter()).cloned().collect();
+        let mut technology_names: Vec<String> = pages
+            .iter()
+            .chain(facility_pages.iter())
+            .flat_map(|page| page.recipes.iter())
+            .filter_map(|recipe| recipe.technology.clone())
+            .collect();
+        let mut technology_pages = vec![];
+        loop {
+            let technology_urls =
+                missing_urls(&self.config.base_url, &technology_names, &scraped_urls);
+            if technology_urls.is_empty() {
+                break;
+            }
+            let new_pages = self.scrape_urls(&technology_urls).await;
+            scraped_urls.extend(technology_urls);
+            technology_names = new_pages
+                .iter()
+                .filter_map(|page| page.technology.as_ref())
+                .flat_map(|technology| technology.prerequisites.clone())
+                .collect();
+            technology_pages.extend(new_pages);
         }
+        summary.technology_pages = technology_pages.len();
+        let page_reports: Vec<PageReport> = pages
+            .iter()
+            .chain(facility_pages.iter())
+            .chain(technology_pages.iter())
+            .map(|page| page.report())
+            .collect();
 
-        let response = response.unwrap();
+        let mut recipe_lists: Vec<Vec<Recipe>> = vec![];
+        let mut items: Vec<Item> = vec![];
+        let mut facilities: Vec<Facility> = vec![];
+        let mut technologies: Vec<Technology> = vec![];
+        for page in pages {
+            recipe_lists.push(page.recipes);
+            items.extend(page.item);
+            facilities.extend(page.facility);
+            technologies.extend(page.technology);
+        }
+        for page in facility_pages {
+            facilities.extend(page.facility);
+            technologies.extend(page.technology);
+        }
+        technologies.extend(
+            technology_pages
+                .into_iter()
+                .filter_map(|page| page.technology),
+        );
+        link_unlocks(&mut technologies, recipe_lists.iter().flatten());
+        if let Some(images) = &self.images {
+            self.mirror_images(images, &mut recipe_lists, &mut items)
+                .await;
+        }
 
-        let document = scraper::Html::parse_document(&response);
-        let table_selector = scraper::Selector::parse("table.pc_table:nth-of-type(1)").unwrap();
-        let table_elems: Vec<_> = document.select(&table_selector).collect();
-        let row_selector = scraper::Selector::parse("tr:nth-of-type(n+1)").unwrap();
-        if let Some(table) = table_elems.first() {
-            let rows: Vec<_> = table.select(&row_selector).collect();
-            for row in rows {
-                let mut recipe = Recipe::new();
+        tracing::info!(
+            "Scraped {} pages in {} ms, changed: {}, unchanged: {}, failed: {}",
+            summary.pages,
+            timekeeper.end().time_since_start,
+            summary.changed_pages,
+            summary.unchanged_pages,
+            summary.failed_pages
+        );
 
-                // ------------------------------ MATERIALS ------------------------------
-                let mut materials: Materials = HashMap::new();
-                let material_selector = scraper::Selector::parse("div.tt_recipe_item").unwrap();
-                let material_elems: Vec<_> = row.select(&material_selector).collect();
+        // write recipe lists to json file
+        if let Some(path) = self.config.recipes_file.as_ref().filter(|_| full_run) {
+            if let Err(err) = self.write_recipes_file(path, &recipe_lists) {
+                tracing::warn!("Unable to write {}: {}", path.display(), err);
+            }
+        }
 
-                for material_elem in material_elems {
-                    let name_selector = scraper::Selector::parse("a").unwrap();
-                    let name = &material_elem
-                        .select(&name_selector)
-                        .map(|x| x.attr("title"))
-                        .next()
-                        .unwrap_or_default();
+        Ok(ScrapeRun {
+            recipe_lists,
+            items,
+            facilities,
+            technologies,
+            pages: page_reports,
+            summary,
+        })
+    }
 
-                    if name.is_none() {
-                        continue;
-                    }
+    /// Scrapes the urls with up to `concurrency` requests in flight, throttled by the rate
+    /// limiter. The pages are returned in the same order as `urls`, stopping early when the run
+    /// is cancelled.
+    #[tracing::instrument(skip(self, urls))]
+    pub async fn scrape_urls(&self, urls: &[String]) -> Vec<ScrapedPage> {
+        if self.is_cancelled() {
+            return vec![];
+        }
+        if let Some(observer) = &self.observer {
+            observer.pages_queued(urls.len());
+        }
 
-                    let count_selector = scraper::Selector::parse("div").unwrap();
-                    let count_text = material_elem
-                        .select(&count_selector)
-                        .map(|x| x.text())
-                        .next();
+        let mut results = stream::iter(urls.to_vec())
+            .map(|url| async move { self.scrape_page(&url).await })
+            .buffered(self.config.concurrency.max(1));
 
-                    if count_text.is_none() {
-                        continue;
-                    }
+        let mut pages = Vec::with_capacity(urls.len());
+        while let Some(page) = results.next().await {
+            tracing::debug!("[{}/{}] {}", pages.len() + 1, urls.len(), page.url);
+            if let Some(observer) = &self.observer {
+                observer.page_scraped(&page);
+            }
+            pages.push(page);
+            if self.is_cancelled() {
+                break;
+            }
+        }
 
-                    let count = count_text.unwrap().next().unwrap_or("0");
-                    materials.insert(name.unwrap().to_string(), count.parse::<f64>().unwrap());
-                }
+        pages
+    }
 
-                recipe.materials = materials;
+    fn write_recipes_file(
+        &self,
+        path: &PathBuf,
+        recipe_lists: &Vec<Vec<Recipe>>,
+    ) -> std::io::Result<()> {
+        let mut file = File::create(path)?;
+        let json = serde_json::to_string_pretty(recipe_lists)?;
+        file.write_all(json.as_bytes())
+    }
 
-                // ------------------------------ TIME ------------------------------
-                let time_selector = scraper::Selector::parse("div.tt_rec_arrow");
-                let time_text = row.select(&time_selector.unwrap()).map(|x| x.text()).next();
+    #[tracing::instrument(skip(self))]
+    pub async fn get_urls(&self) -> Result<Vec<String>, RetryRequestError> {
+        let url = format!("{}/Items", self.config.base_url);
+        self.pacing.acquire(&self.source.request_url(&url)).await?;
+        let response =
+            retry::gate_retries(Arc::new(self.pacing.clone()), self.source.fetch(&url)).await?;
+        let urls = self
+            .source
+            .parse_item_urls(&response, &self.config.base_url);
 
-                if time_text.is_none() {
-                    continue;
-                }
+        tracing::info!("Found {} item pages", urls.len());
+        Ok(urls)
+    }
+
+    /// Downloads the icons of the recipes and items into the image store and points their
+    /// `image` fields at the api. Icons that can't be downloaded keep their wiki url.
+    #[tracing::instrument(skip_all)]
+    async fn mirror_images(
+        &self,
+        images: &ImageStore,
+        recipe_lists: &mut [Vec<Recipe>],
+        items: &mut [Item],
+    ) {
+        let mut index = images.index().await;
+        let mut urls: Vec<String> = recipe_lists
+            .iter()
+            .flatten()
+            .filter_map(|recipe| recipe.image.clone())
+            .chain(items.iter().filter_map(|item| item.image.clone()))
+            .filter(|url| url.starts_with("http") && !index.contains_key(url))
+            .collect();
+        urls.sort();
+        urls.dedup();
 
-                let time = time_text.unwrap().next().unwrap_or("0");
-                let re = regex::Regex::new(r"\d+\.*\d*").unwrap();
-                let captures = re.captures(time);
-                if let Some(captures) = captures {
-                    let time_as_float = captures[0].parse::<f64>().unwrap();
-                    recipe.time = time_as_float;
+        let mut downloads = stream::iter(urls)
+            .map(|url| async move {
+                let hash = self.download_image(images, &url).await;
+                (url, hash)
+            })
+            .buffer_unordered(self.config.concurrency);
+        while let Some((url, hash)) = downloads.next().await {
+            match hash {
+                Ok(hash) => {
+                    index.insert(url, hash);
                 }
+                Err(err) => tracing::warn!("Unable to mirror image {}: {}", url, err),
+            }
+        }
+        if let Err(err) = images.save_index(&index).await {
+            tracing::warn!("Unable to save the image index: {}", err);
+        }
 
-                // ------------------------------ OUTPUT ITEM NAME, COUNT, & IMAGE ------------------------------
-
-                let output_item_selector = scraper::Selector::parse("div.tt_output_item").unwrap();
-                let output_items: Vec<_> = row.select(&output_item_selector).collect();
-                for output_item in output_items {
-                    // ------------------------------ OUTPUT ITEM NAME ------------------------------
-                    let name_selector = scraper::Selector::parse("a").unwrap();
-                    let name = &output_item
-                        .select(&name_selector)
-                        .map(|x| x.attr("title"))
-                        .next()
-                        .unwrap_or_default();
-
-                    if name.is_none() {
-                        continue;
-                    }
+        let fields = recipe_lists
+            .iter_mut()
+            .flatten()
+            .map(|recipe| &mut recipe.image)
+            .chain(items.iter_mut().map(|item| &mut item.image));
+        for image in fields {
+            if let Some(hash) = image.as_ref().and_then(|url| index.get(url)) {
+                *image = Some(images::image_url(hash));
+            }
+        }
+    }
 
-                    // ------------------------------ OUTPUT ITEM COUNT ------------------------------
-                    let count_selector = scraper::Selector::parse("div").unwrap();
-                    let count_text = output_item.select(&count_selector).map(|x| x.text()).next();
+    async fn download_image(
+        &self,
+        images: &ImageStore,
+        url: &str,
+    ) -> Result<String, RetryRequestError> {
+        self.pacing.acquire(url).await?;
+        let response = self
+            .client
+            .get(url)
+            .timeout(self.config.retry.timeout)
+            .send()
+            .await
+            .map_err(RetryRequestError::ReqwestError)?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(RetryRequestError::HttpStatusError {
+                url: url.to_string(),
+                status,
+                retry_after: None,
+            });
+        }
+        let content = response
+            .bytes()
+            .await
+            .map_err(RetryRequestError::ReqwestError)?;
+        images
+            .put(&content)
+            .await
+            .map_err(RetryRequestError::IoError)
+    }
 
-                    if count_text.is_none() {
-                        continue;
-                    }
+    #[tracing::instrument(skip(self))]
+    pub async fn scrape_url(&self, url: &str) -> Vec<Recipe> {
+        self.scrape_page(url).await.recipes
+    }
 
-                    let count = count_text.unwrap().next().unwrap_or("0");
-                    let re = regex::Regex::new(r"\d+\.*\d*").unwrap();
-                    let captures = re.captures(count);
-                    if let Some(captures) = captures {
-                        let count_as_float = captures[0].parse::<f64>().unwrap();
-                        recipe.output_item_count = count_as_float;
-                    }
+    /// Scrapes a single page. With a cache, the request is conditional and pages whose content
+    /// didn't change are not parsed again; their recipes come from the cache.
+    #[tracing::instrument(skip(self))]
+    pub async fn scrape_page(&self, url: &str) -> ScrapedPage {
+        // checked against the url the source requests, e.g. `api.php`
+        if let Err(err) = self.pacing.acquire(&self.source.request_url(url)).await {
+            return ScrapedPage::failed(url, err);
+        }
+        if let Some(observer) = &self.observer {
+            observer.page_started(url);
+        }
+        let started = Instant::now();
+        let fetch = retry::gate_retries(Arc::new(self.pacing.clone()), self.fetch_and_parse(url));
+        let (page, attempts) = retry::count_attempts(fetch).await;
+        ScrapedPage {
+            attempts,
+            duration: started.elapsed(),
+            ..page
+        }
+    }
 
-                    recipe.output_item = name.unwrap().to_string();
+    async fn fetch_and_parse(&self, url: &str) -> ScrapedPage {
+        let cached = match &self.cache {
+            Some(cache) => cache.get(url).await,
+            None => None,
+        };
+        let validators = cached
+            .as_ref()
+            .map(|page| page.validators.clone())
+            .unwrap_or_default();
 
-                    // ------------------------------ OUTPUT ITEM IMAGE ------------------------------
-                    let image_selector = scraper::Selector::parse("img").unwrap();
-                    let image = output_item
-                        .select(&image_selector)
-                        .map(|x| format!("https://dsp-wiki.com{}", x.attr("src").unwrap_or("/")))
-                        .next()
-                        .unwrap_or_default();
+        let fetched = self.source.fetch_page(url, &validators).await;
+        let (body, validators) = match (fetched, &cached) {
+            (Ok(FetchedPage::Modified { body, validators }), _) => (body, validators),
+            (Ok(FetchedPage::NotModified), Some(page)) => {
+                (page.body.clone(), page.validators.clone())
+            }
+            // a source answering 304 to an unconditional request; ask for the full page
+            (Ok(FetchedPage::NotModified), None) => match self.source.fetch(url).await {
+                Ok(body) => (body, Default::default()),
+                Err(err) => return ScrapedPage::failed(url, err),
+            },
+            (Err(err), _) => return ScrapedPage::failed(url, err),
+        };
 
-                    recipe.image = Some(image);
-                }
+        let content_hash = cache::content_hash(&body);
+        let unchanged = cached
+            .as_ref()
+            .is_some_and(|page| page.content_hash == content_hash);
+        let parsed = match cached {
+            Some(page) if unchanged && page.parser_version == PARSER_VERSION => page.parsed,
+            _ => self.source.parse_page(&body, url, &self.config.base_url),
+        };
+        if let Some(observer) = &self.observer {
+            for recipe in parsed.recipes.iter() {
+                observer.recipe_parsed(url, recipe);
+            }
+        }
+
+        if let Some(cache) = &self.cache {
+            let page = CachedPage {
+                url: url.to_string(),
+                validators,
+                content_hash,
+                fetched_at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs() as i64,
+                parser_version: PARSER_VERSION,
+                body,
+                parsed: parsed.clone(),
+            };
+            if let Err(err) = cache.put(&page).await {
+                tracing::warn!("Unable to cache {}: {}", url, err);
+            }
+        }
 
-                // ------------------------------ FACILITY ------------------------------
-                let facilities_selector = scraper::Selector::parse("td:nth-of-type(2)").unwrap();
-                let facility_elems: Vec<_> = row.select(&facilities_selector).collect();
+        if parsed.missing_production_table {
+            let err = RetryRequestError::MissingProductionTableError(MissingProductionTableError {
+                url: url.to_string(),
+            });
+            return ScrapedPage {
+                item: parsed.item,
+                facility: parsed.facility,
+                technology: parsed.technology,
+                ..ScrapedPage::failed(url, err)
+            };
+        }
 
-                for facility_elem in facility_elems {
-                    let facility_selector = scraper::Selector::parse("a:nth-of-type(1)").unwrap();
-                    let facility = facility_elem
-                        .select(&facility_selector)
-                        .map(|x| x.attr("title").unwrap())
-                        .next();
+        ScrapedPage {
+            url: url.to_string(),
+            status: if unchanged {
+                PageStatus::Unchanged
+            } else {
+                PageStatus::Changed
+            },
+            recipes: parsed.recipes,
+            item: parsed.item,
+            facility: parsed.facility,
+            technology: parsed.technology,
+            error: None,
+            attempts: 0,
+            duration: Duration::ZERO,
+        }
+    }
+}
 
-                    if let Some(facility) = facility {
-                        recipe.facility = facility.to_string();
-                    }
-                }
+/// Wiki urls of the facilities used by the scraped recipes whose building page wasn't scraped.
+fn missing_facility_urls(base_url: &str, pages: &[ScrapedPage]) -> Vec<String> {
+    let scraped: Vec<String> = pages.iter().map(|page| page.url.clone()).collect();
+    let facilities: Vec<String> = pages
+        .iter()
+        .flat_map(|page| page.recipes.iter())
+        .flat_map(|recipe| recipe.eligible_facilities())
+        .map(|facility| facility.to_string())
+        .collect();
+    missing_urls(base_url, &facilities, &scraped)
+}
 
-                println!("{:?}", recipe);
-                println!("-----------------------------------");
+/// Wiki url of the page titled `target`. Urls are returned unchanged.
+pub fn page_url(base_url: &str, target: &str) -> String {
+    let target = target.trim();
+    if target.starts_with("http://") || target.starts_with("https://") {
+        target.to_string()
+    } else {
+        format!("{}/{}", base_url, target.replace(' ', "_"))
+    }
+}
 
-                recipes.push(recipe);
-            }
-        }
+/// Wiki urls of the pages titled `names` that are not in `scraped`, sorted and deduplicated.
+fn missing_urls(base_url: &str, names: &[String], scraped: &[String]) -> Vec<String> {
+    let mut urls: Vec<String> = names
+        .iter()
+        .filter(|name| !name.is_empty())
+        .map(|name| page_url(base_url, name))
+        .filter(|url| !scraped.contains(url))
+        .collect();
+    urls.sort();
+    urls.dedup();
+    urls
+}
 
-        recipes
+/// Adds the output of every recipe to the unlocks of its technology, for technology pages that
+/// don't list all of them.
+fn link_unlocks<'a>(technologies: &mut [Technology], recipes: impl Iterator<Item = &'a Recipe>) {
+    for recipe in recipes {
+        let Some(name) = &recipe.technology else {
+            continue;
+        };
+        let Some(technology) = technologies
+            .iter_mut()
+            .find(|technology| technology.name.eq_ignore_ascii_case(name))
+        else {
+            continue;
+        };
+        if !technology
+            .unlocks
+            .iter()
+            .any(|unlock| unlock.eq_ignore_ascii_case(&recipe.output_item))
+        {
+            technology.unlocks.push(recipe.output_item.clone());
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::cache::PageCache;
+    use super::images::ImageStore;
+    use super::robots::RobotsPolicy;
+    use super::source::{FixtureSource, MediaWikiSource, PageSource};
+    use super::{
+        env_secs, page_url, PageStatus, RetryPolicy, RetryRequestError, ScrapeConfig,
+        ScrapeSummary, Scraper, DEFAULT_BASE_URL,
+    };
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/dsp-wiki");
+    const API_FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/dsp-wiki-api");
+
+    fn fixture_scraper() -> Scraper {
+        Scraper::with_source(Box::new(FixtureSource::new(FIXTURES)))
+    }
 
     #[tokio::test]
     async fn test_get_urls() {
-        let scraper = super::Scraper::new();
-        let urls = scraper.get_urls().await;
+        let scraper = fixture_scraper();
+        let urls = scraper.get_urls().await.unwrap();
         assert!(!urls.is_empty());
+        assert!(urls.contains(&"https://dsp-wiki.com/Iron_Ingot".to_string()));
     }
 
     #[tokio::test]
     async fn test_scrape_url() {
-        let scraper = super::Scraper::new();
+        let scraper = fixture_scraper();
         let url = "https://dsp-wiki.com/Iron_Ingot";
         let recipes = scraper.scrape_url(url).await;
         assert!(!recipes.is_empty());
@@ -307,4 +953,524 @@ mod tests {
         assert_eq!(recipe.materials.len(), 1);
         assert_eq!(recipe.materials.get("Iron Ore"), Some(&1.0));
     }
+
+    #[tokio::test]
+    async fn test_scrape_url_without_recipes() {
+        let scraper = fixture_scraper();
+        assert!(scraper
+            .scrape_url("https://dsp-wiki.com/Iron_Ore_Vein")
+            .await
+            .is_empty());
+        assert!(scraper
+            .scrape_url("https://dsp-wiki.com/Missing_Page")
+            .await
+            .is_empty());
+    }
+
+    /// Serves the Iron Ingot fixture slowly and records how many requests overlap.
+    #[derive(Debug)]
+    struct SlowSource {
+        fixtures: FixtureSource,
+        in_flight: AtomicUsize,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl PageSource for SlowSource {
+        async fn fetch(&self, url: &str) -> Result<String, RetryRequestError> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            let page = url.rsplit('/').next().unwrap_or_default();
+            self.fixtures
+                .fetch(&format!("https://dsp-wiki.com/{}", page))
+                .await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scrape_urls_concurrently() {
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let source = SlowSource {
+            fixtures: FixtureSource::new(FIXTURES),
+            in_flight: AtomicUsize::new(0),
+            max_in_flight: max_in_flight.clone(),
+        };
+        let config = ScrapeConfig {
+            concurrency: 3,
+            requests_per_second: 0.0,
+            burst: 1,
+            ..Default::default()
+        };
+        let scraper = Scraper::with_config(Box::new(source), config).unwrap();
+
+        let urls: Vec<String> = ["Iron_Ingot", "Magnetic_Coil", "Iron_Ore"]
+            .iter()
+            .cycle()
+            .take(9)
+            .map(|page| format!("https://dsp-wiki.com/{}", page))
+            .collect();
+        let pages = scraper.scrape_urls(&urls).await;
+
+        assert_eq!(pages.len(), 9);
+        assert_eq!(pages[0].recipes[0].output_item, "Iron Ingot");
+        assert_eq!(pages[1].recipes[0].output_item, "Magnetic Coil");
+        assert_eq!(pages[8].recipes[0].output_item, "Iron Ore");
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_scrape_page_with_cache() {
+        let dir = std::env::temp_dir().join(format!("dsp-scrape-cache-{}", std::process::id()));
+        let config = ScrapeConfig {
+            cache_dir: Some(dir.clone()),
+            ..Default::default()
+        };
+        let scraper = Scraper::with_config(Box::new(FixtureSource::new(FIXTURES)), config).unwrap();
+        let url = "https://dsp-wiki.com/Iron_Ingot";
+
+        let first = scraper.scrape_page(url).await;
+        assert_eq!(first.status, PageStatus::Changed);
+        assert_eq!(first.recipes[0].output_item, "Iron Ingot");
+        assert_eq!(
+            first.item.as_ref().and_then(|item| item.stack_size),
+            Some(100.0)
+        );
+
+        // unchanged pages replay the cached recipes instead of parsing the page again
+        let cache = PageCache::new(&dir);
+        let mut cached = cache.get(url).await.unwrap();
+        cached.parsed.recipes[0].facility = "Cached Smelter".to_string();
+        cache.put(&cached).await.unwrap();
+
+        let second = scraper.scrape_page(url).await;
+        assert_eq!(second.status, PageStatus::Unchanged);
+        assert_eq!(second.recipes[0].facility, "Cached Smelter");
+        assert_eq!(second.item.as_ref().unwrap().name, "Iron Ingot");
+
+        let pages = vec![
+            first,
+            second,
+            scraper
+                .scrape_page("https://dsp-wiki.com/Iron_Ore_Vein")
+                .await,
+            scraper.scrape_page("https://dsp-wiki.com/Not_A_Page").await,
+        ];
+        let summary = ScrapeSummary::from_pages(&pages);
+        // the vein has no production table, which is not a failure
+        assert_eq!(summary.pages, 4);
+        assert_eq!(summary.changed_pages, 1);
+        assert_eq!(summary.unchanged_pages, 1);
+        assert_eq!(summary.failed_pages, 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_scrape_facilities() {
+        let scraper = fixture_scraper();
+        let run = scraper
+            .scrape_dsp_data(vec!["https://dsp-wiki.com/Magnetic_Coil".to_string()])
+            .await
+            .unwrap();
+
+        // Mk.I has a fixture, Mk.II and Mk.III fail to load
+        assert_eq!(run.summary.pages, 1);
+        assert_eq!(run.summary.facility_pages, 3);
+        assert_eq!(run.facilities.len(), 1);
+        assert_eq!(run.facilities[0].name, "Assembling Machine Mk.I");
+        assert_eq!(run.facilities[0].crafting_speed, Some(0.75));
+
+        let failed: Vec<&str> = run
+            .pages
+            .iter()
+            .filter(|page| page.is_failed())
+            .map(|page| page.error_kind.as_deref().unwrap())
+            .collect();
+        let summary = &run.summary;
+        assert_eq!(
+            run.pages.len(),
+            summary.pages + summary.facility_pages + summary.technology_pages
+        );
+        assert_eq!(failed, ["IoError", "IoError"]);
+    }
+
+    #[tokio::test]
+    async fn test_scrape_technologies() {
+        let scraper = fixture_scraper();
+        let run = scraper
+            .scrape_dsp_data(vec![
+                "https://dsp-wiki.com/Assembling_Machine_Mk.I".to_string()
+            ])
+            .await
+            .unwrap();
+
+        // Basic Assembling Processes unlocks the recipe and requires Electromagnetism
+        assert_eq!(run.summary.technology_pages, 2);
+        let mut names: Vec<&str> = run
+            .technologies
+            .iter()
+            .map(|technology| technology.name.as_str())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["Basic Assembling Processes", "Electromagnetism"]
+        );
+        assert!(run.items.iter().all(|item| item.name != "Electromagnetism"));
+    }
+
+    /// Starts a local `api.php` serving the recorded responses, answering `missingtitle` for
+    /// pages without one, and returns its url.
+    fn api_stub_server() -> String {
+        use axum::extract::Query;
+        use std::collections::HashMap;
+
+        let app = axum::Router::new().route(
+            "/api.php",
+            axum::routing::get(|Query(params): Query<HashMap<String, String>>| async move {
+                let page = params.get("page").cloned().unwrap_or_default();
+                std::fs::read_to_string(format!("{}/{}.json", API_FIXTURES, page)).unwrap_or_else(
+                    |_| {
+                        serde_json::json!({ "error": {
+                            "code": "missingtitle",
+                            "info": "The page you specified doesn't exist.",
+                        } })
+                        .to_string()
+                    },
+                )
+            }),
+        );
+        format!("{}/api.php", serve(app))
+    }
+
+    #[tokio::test]
+    async fn test_media_wiki_source_matches_html() {
+        let html_scraper = fixture_scraper();
+        let api_scraper = Scraper::with_source(Box::new(MediaWikiSource::new(&api_stub_server())));
+
+        assert_eq!(
+            api_scraper.get_urls().await.unwrap(),
+            html_scraper.get_urls().await.unwrap()
+        );
+
+        // the same recipes, except for the image urls
+        let comparable = |recipes: Vec<super::Recipe>| -> Vec<serde_json::Value> {
+            recipes
+                .into_iter()
+                .map(|recipe| super::Recipe {
+                    image: None,
+                    ..recipe
+                })
+                .map(|recipe| serde_json::to_value(recipe).unwrap())
+                .collect()
+        };
+        for page in ["Iron_Ingot", "Magnetic_Coil", "Assembling_Machine_Mk.I"] {
+            let url = format!("https://dsp-wiki.com/{}", page);
+            let api_recipes = api_scraper.scrape_url(&url).await;
+            assert!(!api_recipes.is_empty());
+            assert_eq!(
+                comparable(api_recipes),
+                comparable(html_scraper.scrape_url(&url).await)
+            );
+        }
+
+        let page = api_scraper
+            .scrape_page("https://dsp-wiki.com/Missing_Page")
+            .await;
+        assert_eq!(page.status, PageStatus::Failed);
+        assert!(matches!(
+            page.error,
+            Some(RetryRequestError::ApiError { ref code, .. }) if code == "missingtitle"
+        ));
+    }
+
+    /// Cancels the run once `limit` pages were scraped.
+    #[derive(Debug, Default)]
+    struct CancellingObserver {
+        queued: AtomicUsize,
+        scraped: AtomicUsize,
+        limit: usize,
+    }
+
+    impl super::ScrapeObserver for CancellingObserver {
+        fn pages_queued(&self, count: usize) {
+            self.queued.fetch_add(count, Ordering::SeqCst);
+        }
+
+        fn page_scraped(&self, _page: &super::ScrapedPage) {
+            self.scraped.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn is_cancelled(&self) -> bool {
+            self.scraped.load(Ordering::SeqCst) >= self.limit
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancel_scrape() {
+        let observer = Arc::new(CancellingObserver {
+            limit: 1,
+            ..Default::default()
+        });
+        let scraper = fixture_scraper().with_observer(observer.clone());
+        let urls = vec![
+            "https://dsp-wiki.com/Iron_Ingot".to_string(),
+            "https://dsp-wiki.com/Magnetic_Coil".to_string(),
+            "https://dsp-wiki.com/Coal".to_string(),
+        ];
+
+        let pages = scraper.scrape_urls(&urls).await;
+        assert_eq!(pages.len(), 1);
+        assert_eq!(observer.queued.load(Ordering::SeqCst), 3);
+        assert!(scraper.scrape_urls(&urls).await.is_empty());
+    }
+
+    #[test]
+    fn test_env_secs() {
+        let default = Duration::from_secs(10);
+        for (value, expected) in [
+            ("2.5", Duration::from_millis(2500)),
+            ("-1", default),
+            ("0", default),
+            ("NaN", default),
+            ("inf", default),
+            ("soon", default),
+        ] {
+            std::env::set_var("TEST_ENV_SECS", value);
+            assert_eq!(env_secs("TEST_ENV_SECS", default), expected, "{}", value);
+        }
+        std::env::remove_var("TEST_ENV_SECS");
+    }
+
+    #[test]
+    fn test_page_url() {
+        assert_eq!(
+            page_url(DEFAULT_BASE_URL, "Super-Magnetic Ring"),
+            "https://dsp-wiki.com/Super-Magnetic_Ring"
+        );
+        assert_eq!(
+            page_url("http://localhost:8080", " https://dsp-wiki.com/Iron_Ingot "),
+            "https://dsp-wiki.com/Iron_Ingot"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_obey_robots() {
+        use axum::http::{header::USER_AGENT, HeaderMap};
+
+        let agents = Arc::new(std::sync::Mutex::new(vec![]));
+        let seen = agents.clone();
+        let app = axum::Router::new().route(
+            "/robots.txt",
+            axum::routing::get(move |headers: HeaderMap| async move {
+                let agent = headers.get(USER_AGENT).unwrap().to_str().unwrap();
+                seen.lock().unwrap().push(agent.to_string());
+                "User-agent: *\nDisallow: /\n\nUser-agent: alex-api-rs\nDisallow: /Coal\n"
+            }),
+        );
+        let base_url = serve(app);
+
+        let config = ScrapeConfig {
+            base_url: base_url.clone(),
+            user_agent: "alex-api-rs/1.0 (+mailto:ops@example.com)".to_string(),
+            ..Default::default()
+        };
+        let robots = RobotsPolicy::new(
+            &config.base_url,
+            &config.user_agent,
+            config.http_client().unwrap(),
+            config.retry.timeout,
+        );
+        let scraper = Scraper::with_config(Box::new(FixtureSource::new(FIXTURES)), config)
+            .unwrap()
+            .with_robots(robots);
+        let urls = vec![
+            format!("{}/Iron_Ingot", base_url),
+            format!("{}/Coal", base_url),
+        ];
+
+        let pages = scraper.scrape_urls(&urls).await;
+        assert!(!pages[0].recipes.is_empty());
+        assert!(matches!(
+            pages[1].error,
+            Some(RetryRequestError::DisallowedByRobots { .. })
+        ));
+        // fetched once for the whole run
+        assert_eq!(
+            *agents.lock().unwrap(),
+            ["alex-api-rs/1.0 (+mailto:ops@example.com)"]
+        );
+    }
+
+    /// Serves `app` on a free local port and returns its base url.
+    fn serve(app: axum::Router) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        base_url
+    }
+
+    #[tokio::test]
+    async fn test_robots_checks_requested_url() {
+        let base_url = serve(axum::Router::new().route(
+            "/robots.txt",
+            axum::routing::get(|| async { "User-agent: *\nDisallow: /api.php\n" }),
+        ));
+        let config = ScrapeConfig {
+            base_url: base_url.clone(),
+            ..Default::default()
+        };
+        let robots = RobotsPolicy::new(
+            &config.base_url,
+            &config.user_agent,
+            config.http_client().unwrap(),
+            config.retry.timeout,
+        );
+        let source = MediaWikiSource::new(&format!("{}/api.php", base_url));
+        let scraper = Scraper::with_config(Box::new(source), config)
+            .unwrap()
+            .with_robots(robots);
+
+        // the page path is allowed, but the source requests api.php
+        let page = scraper
+            .scrape_page(&format!("{}/Iron_Ingot", base_url))
+            .await;
+        assert!(matches!(
+            page.error,
+            Some(RetryRequestError::DisallowedByRobots { url }) if url.contains("/api.php?")
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_robots_server_error_disallows() {
+        let base_url = serve(axum::Router::new().route(
+            "/robots.txt",
+            axum::routing::get(|| async { axum::http::StatusCode::SERVICE_UNAVAILABLE }),
+        ));
+        let config = ScrapeConfig {
+            base_url: base_url.clone(),
+            ..Default::default()
+        };
+        let robots = RobotsPolicy::new(
+            &config.base_url,
+            &config.user_agent,
+            config.http_client().unwrap(),
+            config.retry.timeout,
+        );
+        let scraper = Scraper::with_config(Box::new(FixtureSource::new(FIXTURES)), config)
+            .unwrap()
+            .with_robots(robots);
+
+        let page = scraper
+            .scrape_page(&format!("{}/Iron_Ingot", base_url))
+            .await;
+        assert!(matches!(
+            page.error,
+            Some(RetryRequestError::DisallowedByRobots { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_robots_timeout_disallows() {
+        let base_url = serve(axum::Router::new().route(
+            "/robots.txt",
+            axum::routing::get(|| async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                ""
+            }),
+        ));
+        let config = ScrapeConfig {
+            base_url: base_url.clone(),
+            retry: RetryPolicy {
+                timeout: Duration::from_millis(100),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let robots = RobotsPolicy::new(
+            &config.base_url,
+            &config.user_agent,
+            config.http_client().unwrap(),
+            config.retry.timeout,
+        );
+        let scraper = Scraper::with_config(Box::new(FixtureSource::new(FIXTURES)), config)
+            .unwrap()
+            .with_robots(robots);
+
+        let page = scraper
+            .scrape_page(&format!("{}/Iron_Ingot", base_url))
+            .await;
+        assert!(matches!(
+            page.error,
+            Some(RetryRequestError::DisallowedByRobots { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_mirror_images() {
+        use crate::data::{Item, Recipe};
+
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = axum::Router::new().route(
+            "/images/:name",
+            axum::routing::get(
+                move |axum::extract::Path(name): axum::extract::Path<String>| async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    match name.as_str() {
+                        "missing.png" => Err(axum::http::StatusCode::NOT_FOUND),
+                        _ => Ok(b"\x89PNG\r\n\x1a\nicon".to_vec()),
+                    }
+                },
+            ),
+        );
+        let base_url = serve(app);
+
+        let dir = std::env::temp_dir().join(format!("dsp-mirror-{}", std::process::id()));
+        let config = ScrapeConfig {
+            image_dir: Some(dir.clone()),
+            ..Default::default()
+        };
+        let scraper = Scraper::with_config(Box::new(FixtureSource::new(FIXTURES)), config).unwrap();
+        let images = ImageStore::new(&dir);
+        let image = |name: &str| Some(format!("{}/images/{}", base_url, name));
+        let mut recipe = Recipe::new();
+        recipe.image = image("Iron_Ingot.png");
+        let mut missing = Recipe::new();
+        missing.image = image("missing.png");
+        let mut item = Item::new("Iron Ingot");
+        // same icon under another url
+        item.image = image("Iron_Ingot_64.png");
+
+        let mut recipe_lists = vec![vec![recipe.clone(), missing]];
+        let mut items = vec![item.clone()];
+        scraper
+            .mirror_images(&images, &mut recipe_lists, &mut items)
+            .await;
+        let mirrored = recipe_lists[0][0].image.clone().unwrap();
+        assert!(mirrored.starts_with("/dsp/images/"));
+        assert_eq!(items[0].image.as_ref(), Some(&mirrored));
+        assert_eq!(recipe_lists[0][1].image, image("missing.png"));
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        // one image file plus the index
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        // known urls are not downloaded again
+        let mut recipe_lists = vec![vec![recipe]];
+        let mut items = vec![item];
+        scraper
+            .mirror_images(&images, &mut recipe_lists, &mut items)
+            .await;
+        assert_eq!(recipe_lists[0][0].image.as_ref(), Some(&mirrored));
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
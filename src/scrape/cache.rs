@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::parse::ParsedPage;
+
+/// Bump whenever the parsers change their output, so that cached pages are parsed
+/// again instead of replaying recipes produced by the old parser.
+pub const PARSER_VERSION: u32 = 6;
+
+/// Distinguishes temporary files of concurrent writes within this process.
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// HTTP validators used to ask the server whether a page changed since it was cached.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// A page as it was last fetched, together with what it parsed into.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedPage {
+    pub url: String,
+    #[serde(default)]
+    pub validators: Validators,
+    pub content_hash: String,
+    pub fetched_at: i64,
+    pub parser_version: u32,
+    pub body: String,
+    #[serde(flatten)]
+    pub parsed: ParsedPage,
+}
+
+/// On-disk page cache, one json file per url named after the hash of the url.
+#[derive(Debug, Clone)]
+pub struct PageCache {
+    dir: PathBuf,
+}
+
+impl PageCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path_for(&self, url: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", content_hash(url)))
+    }
+
+    /// Returns the cached page, treating unreadable entries as missing.
+    pub async fn get(&self, url: &str) -> Option<CachedPage> {
+        let contents = tokio::fs::read(self.path_for(url)).await.ok()?;
+        match serde_json::from_slice::<CachedPage>(&contents) {
+            Ok(page) if page.url == url => Some(page),
+            Ok(_) => None,
+            Err(err) => {
+                tracing::warn!("Ignoring corrupt cache entry for {}: {}", url, err);
+                None
+            }
+        }
+    }
+
+    /// Stores the page, replacing any previous entry. The file is written next to its final
+    /// location first so that readers never see a partial entry.
+    pub async fn put(&self, page: &CachedPage) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let path = self.path_for(&page.url);
+        // The same url can be stored by concurrent scrapes, so every write gets its own
+        // temporary file. Whichever rename lands last wins.
+        let n = TMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let tmp = path.with_extension(format!("json.{}-{}.tmp", std::process::id(), n));
+        tokio::fs::write(&tmp, serde_json::to_vec(page)?).await?;
+        if let Err(err) = tokio::fs::rename(&tmp, &path).await {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Hex encoded SHA-256 of `content`.
+pub fn content_hash(content: &str) -> String {
+    hex::encode(Sha256::digest(content.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cache_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("dsp-page-cache-{}", std::process::id()));
+        let cache = PageCache::new(&dir);
+        let url = "https://dsp-wiki.com/Iron_Ingot";
+        assert!(cache.get(url).await.is_none());
+
+        let page = CachedPage {
+            url: url.to_string(),
+            validators: Validators {
+                etag: Some("\"abc\"".to_string()),
+                last_modified: None,
+            },
+            content_hash: content_hash("<html></html>"),
+            fetched_at: 0,
+            parser_version: PARSER_VERSION,
+            body: "<html></html>".to_string(),
+            parsed: ParsedPage {
+                missing_production_table: true,
+                ..Default::default()
+            },
+        };
+        cache.put(&page).await.unwrap();
+
+        let cached = cache.get(url).await.unwrap();
+        assert_eq!(cached.validators, page.validators);
+        assert_eq!(cached.content_hash, page.content_hash);
+        assert!(cache
+            .get("https://dsp-wiki.com/Copper_Ingot")
+            .await
+            .is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_puts() {
+        let dir = std::env::temp_dir().join(format!("dsp-page-cache-race-{}", std::process::id()));
+        let cache = PageCache::new(&dir);
+        let url = "https://dsp-wiki.com/Iron_Ingot";
+        let pages: Vec<_> = (0..16)
+            .map(|fetched_at| CachedPage {
+                url: url.to_string(),
+                validators: Validators::default(),
+                content_hash: content_hash("<html></html>"),
+                fetched_at,
+                parser_version: PARSER_VERSION,
+                body: "<html></html>".to_string(),
+                parsed: ParsedPage::default(),
+            })
+            .collect();
+
+        let puts = pages.iter().map(|page| cache.put(page));
+        futures::future::try_join_all(puts).await.unwrap();
+        assert!(cache.get(url).await.is_some());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Distinguishes temporary files of concurrent writes within this process.
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Path the api serves mirrored images under, followed by their hash.
+pub const IMAGES_PATH: &str = "/dsp/images";
+
+/// On-disk image store. Images are named after the SHA-256 of their content, so the same icon
+/// linked from several urls is stored once. `index.json` remembers which url resolved to which
+/// image so that later runs don't download it again.
+#[derive(Debug, Clone)]
+pub struct ImageStore {
+    dir: PathBuf,
+}
+
+impl ImageStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path_for(&self, hash: &str) -> Option<PathBuf> {
+        let valid = hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit());
+        valid.then(|| self.dir.join(hash.to_lowercase()))
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.dir.join("index.json")
+    }
+
+    /// Stores `content` unless an identical image exists and returns its hash.
+    pub async fn put(&self, content: &[u8]) -> std::io::Result<String> {
+        let hash = hex::encode(Sha256::digest(content));
+        let path = self.dir.join(&hash);
+        if tokio::fs::try_exists(&path).await? {
+            return Ok(hash);
+        }
+        tokio::fs::create_dir_all(&self.dir).await?;
+        // Urls serving identical bytes are downloaded concurrently, so every write gets its own
+        // temporary file. Whichever rename lands last replaces the same content.
+        let n = TMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let tmp = path.with_extension(format!("{}-{}.tmp", std::process::id(), n));
+        tokio::fs::write(&tmp, content).await?;
+        if let Err(err) = tokio::fs::rename(&tmp, &path).await {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+                return Err(err);
+            }
+        }
+        Ok(hash)
+    }
+
+    /// The image and its content type. Unknown and malformed hashes are missing.
+    pub async fn get(&self, hash: &str) -> Option<(&'static str, Vec<u8>)> {
+        let content = tokio::fs::read(self.path_for(hash)?).await.ok()?;
+        Some((content_type(&content), content))
+    }
+
+    /// Hashes of the images already downloaded, by url.
+    pub async fn index(&self) -> HashMap<String, String> {
+        let Ok(contents) = tokio::fs::read(self.index_path()).await else {
+            return HashMap::new();
+        };
+        serde_json::from_slice(&contents).unwrap_or_else(|err| {
+            tracing::warn!("Ignoring corrupt image index: {}", err);
+            HashMap::new()
+        })
+    }
+
+    pub async fn save_index(&self, index: &HashMap<String, String>) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let path = self.index_path();
+        let tmp = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(index)?).await?;
+        tokio::fs::rename(tmp, path).await
+    }
+}
+
+/// Url the api serves the image with `hash` at.
+pub fn image_url(hash: &str) -> String {
+    format!("{}/{}", IMAGES_PATH, hash)
+}
+
+/// Content type sniffed from the first bytes. Wiki icons are png; anything unknown is served
+/// as binary.
+pub fn content_type(content: &[u8]) -> &'static str {
+    match content {
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [0xFF, 0xD8, 0xFF, ..] => "image/jpeg",
+        [b'G', b'I', b'F', b'8', ..] => "image/gif",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+        _ if content.starts_with(b"<svg") || content.starts_with(b"<?xml") => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_image_store() {
+        let dir = std::env::temp_dir().join(format!("dsp-images-{}", std::process::id()));
+        let store = ImageStore::new(&dir);
+        let png = b"\x89PNG\r\n\x1a\nicon";
+
+        let hash = store.put(png).await.unwrap();
+        assert_eq!(store.put(png).await.unwrap(), hash);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        assert_eq!(store.get(&hash).await, Some(("image/png", png.to_vec())));
+        assert_eq!(store.get("../index.json").await, None);
+
+        let index = HashMap::from([("https://dsp-wiki.com/icon.png".to_string(), hash)]);
+        store.save_index(&index).await.unwrap();
+        assert_eq!(store.index().await, index);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_puts() {
+        let dir = std::env::temp_dir().join(format!("dsp-images-race-{}", std::process::id()));
+        let store = ImageStore::new(&dir);
+        let png = b"\x89PNG\r\n\x1a\nsame";
+
+        let puts = (0..16).map(|_| store.put(png));
+        let hashes = futures::future::try_join_all(puts).await.unwrap();
+        assert!(hashes.iter().all(|hash| hash == &hashes[0]));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
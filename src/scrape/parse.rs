@@ -0,0 +1,618 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::data::{Facility, Footprint, Item, Materials, Recipe, Technology};
+
+static NUMBER_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\d+\.*\d*").unwrap());
+static FOOTPRINT_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(\d+\.?\d*)\s*[×xX*]\s*(\d+\.?\d*)").unwrap());
+static QUANTITY_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(-?[\d,]*\.?\d+)\s*([kMGT]?)").unwrap());
+
+/// Everything parsed from a single wiki page.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ParsedPage {
+    pub recipes: Vec<Recipe>,
+    #[serde(default)]
+    pub item: Option<Item>,
+    #[serde(default)]
+    pub facility: Option<Facility>,
+    #[serde(default)]
+    pub technology: Option<Technology>,
+    #[serde(default)]
+    pub missing_production_table: bool,
+}
+
+/// The wiki lists recipes available from the start as unlocked by the game itself.
+pub const STARTING_TECHNOLOGY: &str = "Dyson Sphere Program";
+
+/// Parses the recipes, item infobox, building stats and technology of a page. Technology pages
+/// have an infobox too but describe no item.
+pub fn parse_page(html: &str, url: &str, base_url: &str) -> ParsedPage {
+    let missing_production_table = !has_production_table(html);
+    let technology = parse_technology(html, url);
+    ParsedPage {
+        recipes: if missing_production_table {
+            vec![]
+        } else {
+            parse_recipes(html, base_url)
+        },
+        item: if technology.is_none() {
+            parse_item(html, url, base_url)
+        } else {
+            None
+        },
+        facility: parse_facility(html, url),
+        technology,
+        missing_production_table,
+    }
+}
+
+/// Whether the page has a production chain table. Item pages without one (e.g. raw resources
+/// without a recipe) yield no recipes.
+pub fn has_production_table(html: &str) -> bool {
+    let document = scraper::Html::parse_document(html);
+    let table_selector = scraper::Selector::parse("table.pc_table:nth-of-type(1)").unwrap();
+    document.select(&table_selector).next().is_some()
+}
+
+/// Extracts the item page urls listed on the wiki's Items page.
+pub fn parse_item_urls(html: &str, base_url: &str) -> Vec<String> {
+    let document = scraper::Html::parse_document(html);
+    let selector = scraper::Selector::parse("div.item_icon_container a[href]").unwrap();
+    let urls = document
+        .select(&selector)
+        .filter_map(|x| x.attr("href"))
+        .map(|href| format!("{}{}", base_url, href));
+
+    // uniqify
+    let mut urls: Vec<String> = urls.collect();
+    urls.sort();
+    urls.dedup();
+    urls
+}
+
+/// Parses the recipes in the first production chain table of an item page. `base_url` is
+/// prepended to the relative image urls.
+pub fn parse_recipes(html: &str, base_url: &str) -> Vec<Recipe> {
+    let mut recipes: Vec<Recipe> = vec![];
+
+    let document = scraper::Html::parse_document(html);
+    let table_selector = scraper::Selector::parse("table.pc_table:nth-of-type(1)").unwrap();
+    let table_elems: Vec<_> = document.select(&table_selector).collect();
+    let row_selector = scraper::Selector::parse("tr:nth-of-type(n+1)").unwrap();
+    if let Some(table) = table_elems.first() {
+        let rows: Vec<_> = table.select(&row_selector).collect();
+        for row in rows {
+            let mut recipe = Recipe::new();
+
+            // ------------------------------ MATERIALS ------------------------------
+            let mut materials: Materials = HashMap::new();
+            let material_selector = scraper::Selector::parse("div.tt_recipe_item").unwrap();
+            let material_elems: Vec<_> = row.select(&material_selector).collect();
+
+            for material_elem in material_elems {
+                let name_selector = scraper::Selector::parse("a").unwrap();
+                let name = &material_elem
+                    .select(&name_selector)
+                    .map(|x| x.attr("title"))
+                    .next()
+                    .unwrap_or_default();
+
+                if name.is_none() {
+                    continue;
+                }
+
+                let count_selector = scraper::Selector::parse("div").unwrap();
+                let count_text = material_elem
+                    .select(&count_selector)
+                    .map(|x| x.text())
+                    .next();
+
+                if count_text.is_none() {
+                    continue;
+                }
+
+                let count = count_text.unwrap().next().unwrap_or("0");
+                if let Ok(count) = count.trim().parse::<f64>() {
+                    materials.insert(name.unwrap().to_string(), count);
+                }
+            }
+
+            recipe.materials = materials;
+
+            // ------------------------------ TIME ------------------------------
+            let time_selector = scraper::Selector::parse("div.tt_rec_arrow");
+            let time_text = row.select(&time_selector.unwrap()).map(|x| x.text()).next();
+
+            if time_text.is_none() {
+                continue;
+            }
+
+            let time = time_text.unwrap().next().unwrap_or("0");
+            let captures = NUMBER_RE.captures(time);
+            if let Some(captures) = captures {
+                if let Ok(time_as_float) = captures[0].parse::<f64>() {
+                    recipe.time = time_as_float;
+                }
+            }
+
+            // ------------------------------ OUTPUT ITEM NAME, COUNT, & IMAGE ------------------------------
+
+            let output_item_selector = scraper::Selector::parse("div.tt_output_item").unwrap();
+            let output_items: Vec<_> = row.select(&output_item_selector).collect();
+            for output_item in output_items {
+                // ------------------------------ OUTPUT ITEM NAME ------------------------------
+                let name_selector = scraper::Selector::parse("a").unwrap();
+                let name = &output_item
+                    .select(&name_selector)
+                    .map(|x| x.attr("title"))
+                    .next()
+                    .unwrap_or_default();
+
+                if name.is_none() {
+                    continue;
+                }
+
+                // ------------------------------ OUTPUT ITEM COUNT ------------------------------
+                let count_selector = scraper::Selector::parse("div").unwrap();
+                let count_text = output_item.select(&count_selector).map(|x| x.text()).next();
+
+                if count_text.is_none() {
+                    continue;
+                }
+
+                let count = count_text.unwrap().next().unwrap_or("0");
+                let captures = NUMBER_RE.captures(count);
+                if let Some(captures) = captures {
+                    if let Ok(count_as_float) = captures[0].parse::<f64>() {
+                        recipe.output_item_count = count_as_float;
+                    }
+                }
+
+                recipe.output_item = name.unwrap().to_string();
+
+                // ------------------------------ OUTPUT ITEM IMAGE ------------------------------
+                let image_selector = scraper::Selector::parse("img").unwrap();
+                let image = output_item
+                    .select(&image_selector)
+                    .map(|x| format!("{}{}", base_url, x.attr("src").unwrap_or("/")))
+                    .next()
+                    .unwrap_or_default();
+
+                recipe.image = Some(image);
+            }
+
+            // ------------------------------ FACILITY ------------------------------
+            let facilities_selector = scraper::Selector::parse("td:nth-of-type(2)").unwrap();
+            let facility_elems: Vec<_> = row.select(&facilities_selector).collect();
+
+            for facility_elem in facility_elems {
+                let facility_selector = scraper::Selector::parse("a[title]").unwrap();
+                for facility in facility_elem
+                    .select(&facility_selector)
+                    .filter_map(|x| x.attr("title"))
+                {
+                    if !recipe.facilities.iter().any(|f| f == facility) {
+                        recipe.facilities.push(facility.to_string());
+                    }
+                }
+            }
+            if let Some(facility) = recipe.facilities.first() {
+                recipe.facility = facility.clone();
+            }
+
+            // ------------------------------ TECHNOLOGY ------------------------------
+            let technology_selector =
+                scraper::Selector::parse("td:nth-of-type(4) a[title]").unwrap();
+            recipe.technology = row
+                .select(&technology_selector)
+                .filter_map(|x| x.attr("title"))
+                .find(|technology| *technology != STARTING_TECHNOLOGY)
+                .map(|technology| technology.to_string());
+
+            recipes.push(recipe);
+        }
+    }
+
+    recipes
+}
+
+/// Parses the infobox of an item page. Returns `None` for pages without one.
+pub fn parse_item(html: &str, url: &str, base_url: &str) -> Option<Item> {
+    let document = scraper::Html::parse_document(html);
+    let infobox = find_infobox(&document)?;
+
+    let mut item = Item::new(&infobox_title(&document, &infobox)?);
+    item.url = Some(url.to_string());
+
+    let image_selector = scraper::Selector::parse("img").unwrap();
+    item.image = infobox
+        .select(&image_selector)
+        .filter_map(|x| x.attr("src"))
+        .map(|src| format!("{}{}", base_url, src))
+        .next();
+
+    let description_selector = scraper::Selector::parse(".infobox-description").unwrap();
+    item.description = infobox
+        .select(&description_selector)
+        .map(|x| element_text(&x))
+        .find(|description| !description.is_empty());
+
+    read_item_rows(&mut item, &infobox_rows(&infobox));
+    item.localized_names = localized_names(&document);
+
+    Some(item)
+}
+
+/// Reads the page titles of the interlanguage links, whose `title` is the localized title
+/// followed by the name of the language.
+fn localized_names(document: &scraper::Html) -> HashMap<String, String> {
+    let selector = scraper::Selector::parse("li.interlanguage-link a[hreflang][title]").unwrap();
+    document
+        .select(&selector)
+        .filter_map(|link| {
+            let language = language_code(link.attr("hreflang")?);
+            let title = link.attr("title")?;
+            let name = title
+                .rsplit_once(" – ")
+                .map_or(title, |(name, _)| name)
+                .trim();
+            (!name.is_empty()).then(|| (language, name.to_string()))
+        })
+        .collect()
+}
+
+/// Primary subtag of a language tag, e.g. `zh` for `zh-Hans`.
+pub fn language_code(tag: &str) -> String {
+    tag.split('-').next().unwrap_or(tag).to_lowercase()
+}
+
+/// Fills in the item fields found in `(lowercased label, value)` infobox rows.
+pub fn read_item_rows(item: &mut Item, rows: &[(String, String)]) {
+    for (label, value) in rows {
+        match label.as_str() {
+            "type" | "category" => item.category = Some(value.clone()),
+            "stack size" | "stack" => item.stack_size = parse_quantity(value),
+            "fuel value" | "fuel" | "energy" => item.fuel_value = parse_quantity(value),
+            _ => {}
+        }
+    }
+}
+
+/// Parses the building stats in the infobox of a facility page. Returns `None` unless the page
+/// lists at least one of them, so that it can run on every item page.
+pub fn parse_facility(html: &str, url: &str) -> Option<Facility> {
+    let document = scraper::Html::parse_document(html);
+    let infobox = find_infobox(&document)?;
+
+    let name = infobox_title(&document, &infobox)?;
+    facility_from_rows(&name, url, &infobox_rows(&infobox))
+}
+
+/// Building stats from `(lowercased label, value)` infobox rows. Returns `None` unless the rows
+/// list at least one of them.
+pub fn facility_from_rows(name: &str, url: &str, rows: &[(String, String)]) -> Option<Facility> {
+    let mut facility = Facility::new(name);
+    facility.url = Some(url.to_string());
+
+    let mut has_stats = false;
+    for (label, value) in rows {
+        match label.as_str() {
+            "production speed" | "crafting speed" | "speed" => {
+                facility.crafting_speed = parse_quantity(value).map(|speed| {
+                    if value.contains('%') {
+                        speed / 100.0
+                    } else {
+                        speed
+                    }
+                });
+            }
+            "work consumption" | "work power" | "power usage" => {
+                facility.work_power = parse_quantity(value);
+            }
+            "idle consumption" | "idle power" => facility.idle_power = parse_quantity(value),
+            "footprint" | "size" => facility.footprint = parse_footprint(value),
+            "sorter slots" | "sorter ports" => {
+                facility.sorter_slots = parse_quantity(value).map(|slots| slots as u32);
+            }
+            _ => continue,
+        }
+        has_stats = true;
+    }
+
+    has_stats.then_some(facility)
+}
+
+/// Parses the research cost, prerequisites and unlocks in the infobox of a technology page.
+/// Returns `None` for pages without any of them.
+pub fn parse_technology(html: &str, url: &str) -> Option<Technology> {
+    let document = scraper::Html::parse_document(html);
+    let infobox = find_infobox(&document)?;
+
+    let mut technology = Technology::new(&infobox_title(&document, &infobox)?);
+    technology.url = Some(url.to_string());
+
+    let mut is_technology = false;
+    for (label, value) in infobox_cells(&infobox) {
+        match label.as_str() {
+            "research cost" | "cost" => technology.cost = parse_item_counts(&value),
+            "prerequisites" | "prerequisite" | "requires" => {
+                technology.prerequisites = link_titles(&value)
+                    .into_iter()
+                    .filter(|name| name != STARTING_TECHNOLOGY)
+                    .collect();
+            }
+            "unlocks" => technology.unlocks = link_titles(&value),
+            _ => continue,
+        }
+        is_technology = true;
+    }
+
+    is_technology.then_some(technology)
+}
+
+fn find_infobox(document: &scraper::Html) -> Option<scraper::ElementRef<'_>> {
+    let infobox_selector = scraper::Selector::parse("table.infobox").unwrap();
+    document.select(&infobox_selector).next()
+}
+
+/// The infobox title, falling back to the page heading.
+fn infobox_title(document: &scraper::Html, infobox: &scraper::ElementRef) -> Option<String> {
+    let title_selector = scraper::Selector::parse("th.infobox-title, h1#firstHeading").unwrap();
+    infobox
+        .select(&title_selector)
+        .chain(document.select(&title_selector))
+        .map(|x| element_text(&x))
+        .find(|name| !name.is_empty())
+}
+
+/// `(lowercased label, value)` of every infobox row with a `th` label and a `td` value.
+fn infobox_rows(infobox: &scraper::ElementRef) -> Vec<(String, String)> {
+    infobox_cells(infobox)
+        .into_iter()
+        .map(|(label, value)| (label, element_text(&value)))
+        .collect()
+}
+
+/// Like `infobox_rows`, but keeps the value cell for values made of links.
+fn infobox_cells<'a>(infobox: &scraper::ElementRef<'a>) -> Vec<(String, scraper::ElementRef<'a>)> {
+    let row_selector = scraper::Selector::parse("tr").unwrap();
+    let label_selector = scraper::Selector::parse("th").unwrap();
+    let value_selector = scraper::Selector::parse("td").unwrap();
+    infobox
+        .select(&row_selector)
+        .filter_map(|row| {
+            let label = row.select(&label_selector).next()?;
+            let value = row.select(&value_selector).next()?;
+            Some((element_text(&label).to_lowercase(), value))
+        })
+        .collect()
+}
+
+/// Titles of the links in `element`, in order and without duplicates.
+fn link_titles(element: &scraper::ElementRef) -> Vec<String> {
+    let link_selector = scraper::Selector::parse("a[title]").unwrap();
+    let mut titles: Vec<String> = vec![];
+    for title in element
+        .select(&link_selector)
+        .filter_map(|x| x.attr("title"))
+    {
+        if !titles.iter().any(|t| t == title) {
+            titles.push(title.to_string());
+        }
+    }
+    titles
+}
+
+/// Item counts listed as `tt_recipe_item` icons, e.g. the research cost of a technology.
+fn parse_item_counts(element: &scraper::ElementRef) -> Materials {
+    let item_selector = scraper::Selector::parse("div.tt_recipe_item").unwrap();
+    let name_selector = scraper::Selector::parse("a[title]").unwrap();
+    let count_selector = scraper::Selector::parse("div").unwrap();
+    element
+        .select(&item_selector)
+        .filter_map(|item| {
+            let name = item.select(&name_selector).next()?.attr("title")?;
+            let count = item.select(&count_selector).next()?;
+            Some((name.to_string(), parse_quantity(&element_text(&count))?))
+        })
+        .collect()
+}
+
+/// Parses footprints written as `3 × 3` or `3x3`.
+fn parse_footprint(text: &str) -> Option<Footprint> {
+    let captures = FOOTPRINT_RE.captures(text)?;
+    Some(Footprint {
+        width: captures[1].parse().ok()?,
+        length: captures[2].parse().ok()?,
+    })
+}
+
+/// Parses a number with an optional SI prefix on its unit, e.g. `2.7 MJ` or `360 kW`, into the
+/// value in the base unit. Thousands separators are ignored.
+pub fn parse_quantity(text: &str) -> Option<f64> {
+    let captures = QUANTITY_RE.captures(text)?;
+    let value = captures[1].replace(',', "").parse::<f64>().ok()?;
+    let multiplier = match &captures[2] {
+        "k" => 1e3,
+        "M" => 1e6,
+        "G" => 1e9,
+        "T" => 1e12,
+        _ => 1.0,
+    };
+    Some(value * multiplier)
+}
+
+fn element_text(element: &scraper::ElementRef) -> String {
+    element
+        .text()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        let path = format!(
+            "{}/tests/fixtures/dsp-wiki/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn test_parse_item_urls() {
+        let urls = parse_item_urls(&fixture("Items.html"), "https://dsp-wiki.com");
+        assert_eq!(
+            urls,
+            vec![
+                "https://dsp-wiki.com/Arc_Smelter",
+                "https://dsp-wiki.com/Iron_Ingot",
+                "https://dsp-wiki.com/Iron_Ore",
+                "https://dsp-wiki.com/Magnetic_Coil",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_recipes() {
+        let html = fixture("Magnetic_Coil.html");
+        assert!(has_production_table(&html));
+
+        let recipes = parse_recipes(&html, "https://dsp-wiki.com");
+        assert_eq!(recipes.len(), 1);
+        let recipe = &recipes[0];
+        assert_eq!(recipe.output_item, "Magnetic Coil");
+        assert_eq!(recipe.output_item_count, 2.0);
+        assert_eq!(recipe.facility, "Assembling Machine Mk.I");
+        assert_eq!(
+            recipe.facilities,
+            vec![
+                "Assembling Machine Mk.I",
+                "Assembling Machine Mk.II",
+                "Assembling Machine Mk.III"
+            ]
+        );
+        assert_eq!(recipe.time, 1.0);
+        assert_eq!(recipe.materials.get("Magnet"), Some(&2.0));
+        assert_eq!(recipe.materials.get("Copper Ingot"), Some(&1.0));
+        assert_eq!(
+            recipe.image.as_deref(),
+            Some("https://dsp-wiki.com/images/thumb/e/e5/Icon_Magnetic_Coil.png/45px-Icon_Magnetic_Coil.png")
+        );
+    }
+
+    #[test]
+    fn test_missing_production_table() {
+        let html = fixture("Iron_Ore_Vein.html");
+        assert!(!has_production_table(&html));
+        assert!(parse_recipes(&html, "https://dsp-wiki.com").is_empty());
+    }
+
+    #[test]
+    fn test_parse_item() {
+        let item = parse_item(
+            &fixture("Coal.html"),
+            "https://dsp-wiki.com/Coal",
+            "https://dsp-wiki.com",
+        )
+        .unwrap();
+        assert_eq!(item.name, "Coal");
+        assert_eq!(item.url.as_deref(), Some("https://dsp-wiki.com/Coal"));
+        assert_eq!(item.category.as_deref(), Some("Natural Resource"));
+        assert_eq!(item.stack_size, Some(100.0));
+        assert_eq!(item.fuel_value, Some(2.7e6));
+        assert_eq!(
+            item.description.as_deref(),
+            Some("Coal can be burnt as fuel or refined into graphite.")
+        );
+        assert_eq!(
+            item.image.as_deref(),
+            Some("https://dsp-wiki.com/images/thumb/a/a8/Icon_Coal.png/80px-Icon_Coal.png")
+        );
+        assert_eq!(
+            item.localized_names,
+            HashMap::from([
+                ("zh".to_string(), "煤矿".to_string()),
+                ("ja".to_string(), "石炭".to_string()),
+            ])
+        );
+
+        assert!(parse_item(
+            &fixture("Iron_Ore_Vein.html"),
+            "https://dsp-wiki.com/Iron_Ore_Vein",
+            "https://dsp-wiki.com"
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_parse_quantity() {
+        assert_eq!(parse_quantity("2.7 MJ"), Some(2.7e6));
+        assert_eq!(parse_quantity("360 kW"), Some(360e3));
+        assert_eq!(parse_quantity("1,000"), Some(1000.0));
+        assert_eq!(parse_quantity("0.5"), Some(0.5));
+        assert_eq!(parse_quantity("none"), None);
+    }
+
+    #[test]
+    fn test_parse_facility() {
+        let facility = parse_facility(
+            &fixture("Assembling_Machine_Mk.I.html"),
+            "https://dsp-wiki.com/Assembling_Machine_Mk.I",
+        )
+        .unwrap();
+        assert_eq!(facility.name, "Assembling Machine Mk.I");
+        assert_eq!(facility.crafting_speed, Some(0.75));
+        assert_eq!(facility.work_power, Some(270e3));
+        assert_eq!(facility.idle_power, Some(12e3));
+        assert_eq!(
+            facility.footprint,
+            Some(Footprint {
+                width: 3.0,
+                length: 3.0
+            })
+        );
+        assert_eq!(facility.sorter_slots, Some(12));
+
+        // item pages have an infobox but no building stats
+        assert!(parse_facility(&fixture("Coal.html"), "https://dsp-wiki.com/Coal").is_none());
+    }
+
+    #[test]
+    fn test_parse_technology() {
+        let html = fixture("Basic_Assembling_Processes.html");
+        let technology =
+            parse_technology(&html, "https://dsp-wiki.com/Basic_Assembling_Processes").unwrap();
+        assert_eq!(technology.name, "Basic Assembling Processes");
+        assert_eq!(technology.cost.get("Electromagnetic Matrix"), Some(&20.0));
+        assert_eq!(technology.prerequisites, vec!["Electromagnetism"]);
+        assert_eq!(technology.unlocks, vec!["Assembling Machine Mk.I"]);
+
+        let page = parse_page(
+            &fixture("Electromagnetism.html"),
+            "https://dsp-wiki.com/Electromagnetism",
+            "https://dsp-wiki.com",
+        );
+        let technology = page.technology.unwrap();
+        assert!(technology.prerequisites.is_empty());
+        assert_eq!(technology.cost.len(), 2);
+        assert!(page.item.is_none());
+
+        assert!(parse_technology(&fixture("Coal.html"), "https://dsp-wiki.com/Coal").is_none());
+
+        let recipes = parse_recipes(&fixture("Magnetic_Coil.html"), "https://dsp-wiki.com");
+        assert_eq!(recipes[0].technology.as_deref(), Some("Electromagnetism"));
+        let recipes = parse_recipes(&fixture("Iron_Ingot.html"), "https://dsp-wiki.com");
+        assert_eq!(recipes[0].technology, None);
+    }
+}
@@ -0,0 +1,157 @@
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use utoipa::ToSchema;
+
+use super::PageStatus;
+use crate::error::{AppError, AppResult};
+
+/// What happened to a single page of a run.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct PageReport {
+    pub url: String,
+    pub status: PageStatus,
+    /// `RetryRequestError` variant of a failed page.
+    pub error_kind: Option<String>,
+    pub error: Option<String>,
+    /// Requests sent for the page, retries included. 0 when it came from a local source.
+    pub attempts: u32,
+    pub duration_ms: u64,
+}
+
+impl PageReport {
+    /// Fetched fine, but without a production table to parse, like raw resources.
+    pub fn is_without_recipes(&self) -> bool {
+        self.error_kind.as_deref() == Some("MissingProductionTableError")
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.status == PageStatus::Failed && !self.is_without_recipes()
+    }
+}
+
+/// Record of a refresh run, stored whether or not it was promoted.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ScrapeReport {
+    pub id: String,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub dry_run: bool,
+    pub attempted: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub without_recipes: usize,
+    pub promoted: bool,
+    /// Why the run was not promoted.
+    pub error: Option<String>,
+    #[serde(default)]
+    pub pages: Vec<PageReport>,
+}
+
+impl ScrapeReport {
+    pub fn new(dry_run: bool) -> Self {
+        Self {
+            id: hex::encode(rand::random::<[u8; 8]>()),
+            started_at: now(),
+            finished_at: None,
+            dry_run,
+            attempted: 0,
+            succeeded: 0,
+            failed: 0,
+            without_recipes: 0,
+            promoted: false,
+            error: None,
+            pages: vec![],
+        }
+    }
+
+    pub fn set_pages(&mut self, pages: Vec<PageReport>) {
+        self.attempted = pages.len();
+        self.failed = pages.iter().filter(|page| page.is_failed()).count();
+        self.without_recipes = pages
+            .iter()
+            .filter(|page| page.is_without_recipes())
+            .count();
+        self.succeeded = self.attempted - self.failed;
+        self.pages = pages;
+    }
+
+    pub fn failed_pages(&self) -> Vec<PageReport> {
+        self.pages
+            .iter()
+            .filter(|page| page.is_failed())
+            .cloned()
+            .collect()
+    }
+
+    pub fn finish<T>(&mut self, result: &AppResult<T>) {
+        self.finished_at = Some(now());
+        match result {
+            Ok(_) => self.promoted = !self.dry_run,
+            Err(err) => self.error = Some(err.to_string()),
+        }
+    }
+}
+
+/// Fails when more than `max_failed_ratio` of the attempted pages failed.
+pub fn check_failures(failed: usize, attempted: usize, max_failed_ratio: f64) -> AppResult<()> {
+    if attempted == 0 || failed as f64 / attempted as f64 <= max_failed_ratio {
+        return Ok(());
+    }
+    Err(AppError::ScrapeFailed(format!(
+        "{} of {} pages failed, more than the {}% allowed",
+        failed,
+        attempted,
+        max_failed_ratio * 100.0
+    )))
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(url: &str, status: PageStatus, error_kind: Option<&str>) -> PageReport {
+        PageReport {
+            url: url.to_string(),
+            status,
+            error_kind: error_kind.map(str::to_string),
+            error: None,
+            attempts: 1,
+            duration_ms: 10,
+        }
+    }
+
+    #[test]
+    fn test_report_counts() {
+        let mut report = ScrapeReport::new(false);
+        report.set_pages(vec![
+            page("Iron_Ingot", PageStatus::Changed, None),
+            page("Coal", PageStatus::Unchanged, None),
+            page(
+                "Iron_Ore_Vein",
+                PageStatus::Failed,
+                Some("MissingProductionTableError"),
+            ),
+            page("Magnet", PageStatus::Failed, Some("TimeoutError")),
+        ]);
+        assert_eq!(
+            (report.attempted, report.succeeded, report.failed),
+            (4, 3, 1)
+        );
+        assert_eq!(report.without_recipes, 1);
+        assert_eq!(report.failed_pages()[0].url, "Magnet");
+
+        assert!(check_failures(report.failed, report.attempted, 0.25).is_ok());
+        assert!(check_failures(report.failed, report.attempted, 0.1).is_err());
+        assert!(check_failures(0, 0, 0.0).is_ok());
+
+        report.finish(&Ok(()));
+        assert!(report.promoted && report.finished_at.is_some());
+    }
+}
@@ -0,0 +1,482 @@
+use async_trait::async_trait;
+use rand::Rng;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER};
+use reqwest::StatusCode;
+use std::cell::Cell;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use super::cache::Validators;
+use super::source::FetchedPage;
+use super::RetryRequestError;
+
+tokio::task_local! {
+    static ATTEMPTS: Cell<u32>;
+    static GATE: Arc<dyn RequestGate>;
+}
+
+/// Waits until a request to `url` may be sent, e.g. for a rate limit, or refuses it.
+#[async_trait]
+pub trait RequestGate: std::fmt::Debug + Send + Sync {
+    async fn acquire(&self, url: &str) -> Result<(), RetryRequestError>;
+}
+
+/// Runs `future` with every retry its requests make passing through `gate` first. The first
+/// attempt is left to the caller, which usually checks it before choosing to fetch at all.
+pub async fn gate_retries<F: Future>(gate: Arc<dyn RequestGate>, future: F) -> F::Output {
+    GATE.scope(gate, future).await
+}
+
+/// Runs `future` and counts the request attempts it makes, retries included.
+pub async fn count_attempts<F: Future>(future: F) -> (F::Output, u32) {
+    ATTEMPTS
+        .scope(Cell::new(0), async {
+            let output = future.await;
+            (output, ATTEMPTS.with(Cell::get))
+        })
+        .await
+}
+
+/// How often and how patiently a request is retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt. 0 disables retrying.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for every following one.
+    pub base_delay: Duration,
+    /// Upper bound for a single delay, including delays requested through `Retry-After`.
+    pub max_delay: Duration,
+    /// Randomize each delay between half and the full backoff so that concurrent scrapers
+    /// don't retry in lockstep.
+    pub jitter: bool,
+    /// Per attempt timeout.
+    pub timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (starting at 0). A `Retry-After` sent by the server
+    /// replaces the computed backoff.
+    pub fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(self.max_delay);
+        }
+
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        if self.jitter && !backoff.is_zero() {
+            let half = backoff / 2;
+            half + rand::thread_rng().gen_range(Duration::ZERO..=backoff - half)
+        } else {
+            backoff
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RetryRequest<'a> {
+    pub url: &'a str,
+    client: reqwest::Client,
+    policy: RetryPolicy,
+    validators: Validators,
+}
+
+impl<'a> RetryRequest<'a> {
+    pub fn new(url: &'a str) -> Self {
+        Self {
+            url,
+            client: reqwest::Client::new(),
+            policy: RetryPolicy::default(),
+            validators: Validators::default(),
+        }
+    }
+
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn with_policy(mut self, policy: RetryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Makes the request conditional: the server may answer 304 Not Modified.
+    pub fn with_validators(mut self, validators: Validators) -> Self {
+        self.validators = validators;
+        self
+    }
+
+    /// Fetches the page body. A 304 Not Modified answer has no body to return, so it is an
+    /// error here; conditional requests go through `fetch_page`.
+    pub async fn fetch(&self) -> Result<String, RetryRequestError> {
+        match self.fetch_page().await? {
+            FetchedPage::Modified { body, .. } => Ok(body),
+            FetchedPage::NotModified => Err(RetryRequestError::HttpStatusError {
+                url: self.url.to_string(),
+                status: StatusCode::NOT_MODIFIED,
+                retry_after: None,
+            }),
+        }
+    }
+
+    /// Fetches the url, retrying transient failures (connection errors, timeouts, 408, 429 and
+    /// 5xx responses) according to the policy. Other failures are returned right away.
+    #[tracing::instrument(skip(self), fields(url = self.url))]
+    pub async fn fetch_page(&self) -> Result<FetchedPage, RetryRequestError> {
+        let mut retries = 0;
+        loop {
+            let err = match self.attempt().await {
+                Ok(body) => return Ok(body),
+                Err(err) => err,
+            };
+            if !err.is_transient() || retries >= self.policy.max_retries {
+                return Err(err);
+            }
+
+            let delay = self.policy.delay(retries, err.retry_after());
+            tracing::warn!("Error fetching url: {}, retrying in {:?}", err, delay);
+            tokio::time::sleep(delay).await;
+            if let Ok(gate) = GATE.try_with(Arc::clone) {
+                gate.acquire(self.url).await?;
+            }
+            retries += 1;
+        }
+    }
+
+    async fn attempt(&self) -> Result<FetchedPage, RetryRequestError> {
+        let _ = ATTEMPTS.try_with(|attempts| attempts.set(attempts.get() + 1));
+        let mut request = self.client.get(self.url).timeout(self.policy.timeout);
+        if let Some(etag) = &self.validators.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &self.validators.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|err| self.request_error(err))?;
+
+        let status = response.status();
+        if status == StatusCode::NOT_MODIFIED {
+            return Ok(FetchedPage::NotModified);
+        }
+        if status.is_client_error() || status.is_server_error() {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after);
+            return Err(RetryRequestError::HttpStatusError {
+                url: self.url.to_string(),
+                status,
+                retry_after,
+            });
+        }
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let validators = Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        let body = response
+            .text()
+            .await
+            .map_err(|err| self.request_error(err))?;
+        Ok(FetchedPage::Modified { body, validators })
+    }
+
+    fn request_error(&self, err: reqwest::Error) -> RetryRequestError {
+        if err.is_timeout() {
+            RetryRequestError::TimeoutError {
+                url: self.url.to_string(),
+            }
+        } else {
+            RetryRequestError::ReqwestError(err)
+        }
+    }
+}
+
+/// Whether a response with this status is worth retrying.
+pub fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+}
+
+/// Parses a `Retry-After` header, given either in seconds or as an HTTP date.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap;
+    use axum::routing::get;
+    use axum::Router;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Starts a local server that answers `/page` with the given responses in order, repeating
+    /// the last one, and returns its url together with the number of requests it received.
+    fn stub_server(
+        responses: Vec<(StatusCode, Option<&'static str>)>,
+    ) -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = Router::new().route(
+            "/page",
+            get(move || {
+                let hit = counter.fetch_add(1, Ordering::SeqCst);
+                let (status, retry_after) = responses[hit.min(responses.len() - 1)];
+                let mut headers = HeaderMap::new();
+                if let Some(retry_after) = retry_after {
+                    headers.insert(RETRY_AFTER, retry_after.parse().unwrap());
+                }
+                async move { (status, headers, format!("response {}", hit)) }
+            }),
+        );
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        (format!("http://{}/page", addr), hits)
+    }
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(50),
+            jitter: false,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn test_backoff_delays() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            jitter: false,
+            ..Default::default()
+        };
+        assert_eq!(policy.delay(0, None), Duration::from_millis(100));
+        assert_eq!(policy.delay(2, None), Duration::from_millis(400));
+        assert_eq!(policy.delay(5, None), Duration::from_millis(500));
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_millis(300))),
+            Duration::from_millis(300)
+        );
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_secs(60))),
+            Duration::from_millis(500)
+        );
+
+        let policy = RetryPolicy {
+            jitter: true,
+            ..policy
+        };
+        for retry in 0..4 {
+            let delay = policy.delay(retry, None);
+            assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(500));
+        }
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("7"), Some(Duration::from_secs(7)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn test_retries_server_errors() {
+        let (url, hits) = stub_server(vec![
+            (StatusCode::SERVICE_UNAVAILABLE, None),
+            (StatusCode::TOO_MANY_REQUESTS, Some("0")),
+            (StatusCode::OK, None),
+        ]);
+
+        let body = RetryRequest::new(&url)
+            .with_policy(fast_policy())
+            .fetch()
+            .await
+            .unwrap();
+        assert_eq!(body, "response 2");
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_client_errors() {
+        let (url, hits) = stub_server(vec![(StatusCode::NOT_FOUND, None)]);
+
+        let err = RetryRequest::new(&url)
+            .with_policy(fast_policy())
+            .fetch()
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            RetryRequestError::HttpStatusError {
+                status: StatusCode::NOT_FOUND,
+                ..
+            }
+        ));
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    /// Lets `allowed` requests through and refuses the rest.
+    #[derive(Debug)]
+    struct CountingGate {
+        acquired: AtomicUsize,
+        allowed: usize,
+    }
+
+    #[async_trait]
+    impl RequestGate for CountingGate {
+        async fn acquire(&self, url: &str) -> Result<(), RetryRequestError> {
+            if self.acquired.fetch_add(1, Ordering::SeqCst) < self.allowed {
+                Ok(())
+            } else {
+                Err(RetryRequestError::DisallowedByRobots {
+                    url: url.to_string(),
+                })
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_pass_the_gate() {
+        let (url, hits) = stub_server(vec![
+            (StatusCode::SERVICE_UNAVAILABLE, None),
+            (StatusCode::SERVICE_UNAVAILABLE, None),
+            (StatusCode::OK, None),
+        ]);
+        let gate = Arc::new(CountingGate {
+            acquired: AtomicUsize::new(0),
+            allowed: usize::MAX,
+        });
+
+        let request = RetryRequest::new(&url).with_policy(fast_policy());
+        let body = gate_retries(gate.clone(), request.fetch()).await.unwrap();
+        assert_eq!(body, "response 2");
+        assert_eq!(gate.acquired.load(Ordering::SeqCst), 2);
+
+        // a retry the gate refuses is not sent
+        let (url, hits_refused) = stub_server(vec![(StatusCode::SERVICE_UNAVAILABLE, None)]);
+        let gate = Arc::new(CountingGate {
+            acquired: AtomicUsize::new(0),
+            allowed: 1,
+        });
+        let request = RetryRequest::new(&url).with_policy(fast_policy());
+        let err = gate_retries(gate, request.fetch()).await.unwrap_err();
+        assert!(matches!(err, RetryRequestError::DisallowedByRobots { .. }));
+        assert_eq!(hits_refused.load(Ordering::SeqCst), 2);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_conditional_request() {
+        let app = Router::new().route(
+            "/page",
+            get(|headers: HeaderMap| async move {
+                let mut response_headers = HeaderMap::new();
+                response_headers.insert(ETAG, "\"v1\"".parse().unwrap());
+                if headers.get(IF_NONE_MATCH).map(|v| v.as_bytes()) == Some(b"\"v1\"") {
+                    (StatusCode::NOT_MODIFIED, response_headers, String::new())
+                } else {
+                    (StatusCode::OK, response_headers, "page".to_string())
+                }
+            }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/page", listener.local_addr().unwrap());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        let validators = match RetryRequest::new(&url).fetch_page().await.unwrap() {
+            FetchedPage::Modified { body, validators } => {
+                assert_eq!(body, "page");
+                validators
+            }
+            FetchedPage::NotModified => panic!("expected the full page"),
+        };
+        assert_eq!(validators.etag.as_deref(), Some("\"v1\""));
+
+        let page = RetryRequest::new(&url)
+            .with_validators(validators)
+            .fetch_page()
+            .await
+            .unwrap();
+        assert!(matches!(page, FetchedPage::NotModified));
+
+        // without the page there is no body to return
+        let err = RetryRequest::new(&url)
+            .with_validators(Validators {
+                etag: Some("\"v1\"".to_string()),
+                last_modified: None,
+            })
+            .fetch()
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            RetryRequestError::HttpStatusError {
+                status: StatusCode::NOT_MODIFIED,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_retries() {
+        let (url, hits) = stub_server(vec![(StatusCode::BAD_GATEWAY, None)]);
+
+        let request = RetryRequest::new(&url).with_policy(fast_policy());
+        let (result, attempts) = count_attempts(request.fetch()).await;
+        assert!(result.unwrap_err().is_transient());
+        assert_eq!(hits.load(Ordering::SeqCst), 4);
+        assert_eq!(attempts, 4);
+    }
+}
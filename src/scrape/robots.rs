@@ -0,0 +1,285 @@
+use reqwest::StatusCode;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+use super::rate_limit::RateLimiter;
+use super::RetryRequestError;
+
+/// The rules of a robots.txt that apply to one crawler.
+#[derive(Debug, Clone, Default)]
+pub struct Robots {
+    rules: Vec<Rule>,
+    pub crawl_delay: Option<Duration>,
+}
+
+/// User-agent lines followed by the lines that apply to them.
+#[derive(Debug, Default)]
+struct Group {
+    agents: Vec<String>,
+    lines: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+impl Robots {
+    /// Everything allowed, for sites without a robots.txt.
+    pub fn allow_all() -> Self {
+        Self::default()
+    }
+
+    /// Nothing allowed, for sites whose robots.txt is unreachable.
+    pub fn disallow_all() -> Self {
+        Self {
+            rules: vec![Rule {
+                allow: false,
+                pattern: "/".to_string(),
+            }],
+            crawl_delay: None,
+        }
+    }
+
+    /// Parses the groups of `text` for `agent`, the product token of the User-Agent. Groups
+    /// naming the agent win over the `*` group.
+    pub fn parse(text: &str, agent: &str) -> Self {
+        let agent = agent.to_lowercase();
+        let mut groups: Vec<Group> = vec![];
+        let mut in_agents = false;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let (key, value) = (key.trim().to_lowercase(), value.trim().to_string());
+            if key == "user-agent" {
+                if !in_agents {
+                    groups.push(Group::default());
+                }
+                if let Some(group) = groups.last_mut() {
+                    group.agents.push(value.to_lowercase());
+                }
+                in_agents = true;
+            } else {
+                if let Some(group) = groups.last_mut() {
+                    group.lines.push((key, value));
+                }
+                in_agents = false;
+            }
+        }
+
+        let named: Vec<_> = groups
+            .iter()
+            .filter(|group| group.agents.contains(&agent))
+            .collect();
+        let matching = if named.is_empty() {
+            groups
+                .iter()
+                .filter(|group| group.agents.iter().any(|name| name == "*"))
+                .collect()
+        } else {
+            named
+        };
+
+        let mut robots = Robots::default();
+        for (key, value) in matching.into_iter().flat_map(|group| &group.lines) {
+            match key.as_str() {
+                "allow" | "disallow" if !value.is_empty() => robots.rules.push(Rule {
+                    allow: key == "allow",
+                    pattern: value.clone(),
+                }),
+                "crawl-delay" => {
+                    robots.crawl_delay = value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|secs| secs.is_finite() && *secs > 0.0)
+                        .map(Duration::from_secs_f64)
+                }
+                _ => {}
+            }
+        }
+        robots
+    }
+
+    /// Whether `path` (with its query) may be fetched. The longest matching rule decides, and
+    /// allow wins ties.
+    pub fn is_allowed(&self, path: &str) -> bool {
+        self.rules
+            .iter()
+            .filter(|rule| pattern_matches(&rule.pattern, path))
+            .max_by_key(|rule| (rule.pattern.len(), rule.allow))
+            .map(|rule| rule.allow)
+            .unwrap_or(true)
+    }
+}
+
+/// Matches robots.txt patterns: a path prefix where `*` matches any characters and a trailing
+/// `$` anchors the end.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let Some(mut rest) = path.strip_prefix(parts[0]) else {
+        return false;
+    };
+    for (index, part) in parts.iter().enumerate().skip(1) {
+        if anchored && index == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(found) => rest = &rest[found + part.len()..],
+            None => return false,
+        }
+    }
+    !anchored || rest.is_empty()
+}
+
+/// Robots.txt of the wiki, fetched on first use. Requests to other hosts are not checked.
+#[derive(Debug)]
+pub struct RobotsPolicy {
+    base_url: String,
+    agent: String,
+    client: reqwest::Client,
+    timeout: Duration,
+    robots: OnceCell<(Robots, Option<RateLimiter>)>,
+}
+
+impl RobotsPolicy {
+    /// `user_agent` is the full User-Agent; its product token selects the robots.txt group.
+    /// `timeout` bounds the robots.txt request.
+    pub fn new(
+        base_url: &str,
+        user_agent: &str,
+        client: reqwest::Client,
+        timeout: Duration,
+    ) -> Self {
+        let agent = user_agent
+            .split(|c: char| c == '/' || c.is_whitespace())
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            agent,
+            client,
+            timeout,
+            robots: OnceCell::new(),
+        }
+    }
+
+    /// Fails for urls robots.txt disallows, otherwise waits out the crawl delay.
+    pub async fn acquire(&self, url: &str) -> Result<(), RetryRequestError> {
+        let Some(path) = url.strip_prefix(&self.base_url) else {
+            return Ok(());
+        };
+        let (robots, limiter) = self
+            .robots
+            .get_or_init(|| async {
+                let robots = self.fetch().await;
+                let limiter = robots
+                    .crawl_delay
+                    .map(|delay| RateLimiter::new(1.0 / delay.as_secs_f64(), 1));
+                (robots, limiter)
+            })
+            .await;
+        let path = if path.is_empty() { "/" } else { path };
+        if !robots.is_allowed(path) {
+            return Err(RetryRequestError::DisallowedByRobots {
+                url: url.to_string(),
+            });
+        }
+        if let Some(limiter) = limiter {
+            limiter.acquire().await;
+        }
+        Ok(())
+    }
+
+    /// A missing robots.txt allows everything. An unreachable one, a server error, no response
+    /// or a timeout, disallows everything for this run (RFC 9309 section 2.3.1.4).
+    async fn fetch(&self) -> Robots {
+        let url = format!("{}/robots.txt", self.base_url);
+        let response = match self.client.get(&url).timeout(self.timeout).send().await {
+            Ok(response) => response,
+            Err(err) => {
+                tracing::warn!("Unable to fetch {}, disallowing every page: {}", url, err);
+                return Robots::disallow_all();
+            }
+        };
+        if response.status().is_server_error() {
+            tracing::warn!(
+                "{} returned {}, disallowing every page",
+                url,
+                response.status()
+            );
+            return Robots::disallow_all();
+        }
+        if response.status() != StatusCode::OK {
+            return Robots::allow_all();
+        }
+        match response.text().await {
+            Ok(text) => Robots::parse(&text, &self.agent),
+            Err(err) if err.is_timeout() => {
+                tracing::warn!("Timed out reading {}, disallowing every page: {}", url, err);
+                Robots::disallow_all()
+            }
+            Err(err) => {
+                tracing::warn!("Unable to read {}: {}", url, err);
+                Robots::allow_all()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTS: &str = "
+# comments are ignored
+User-agent: *
+Disallow: /Special:
+Disallow: /*?action=
+Crawl-delay: 5
+
+User-agent: alex-api-rs
+User-agent: other-bot
+Disallow: /Special:
+Allow: /Special:FilePath/
+Disallow: /*.php$
+Crawl-delay: 0.5
+";
+
+    #[test]
+    fn test_parse_robots() {
+        let robots = Robots::parse(ROBOTS, "alex-api-rs");
+        assert_eq!(robots.crawl_delay, Some(Duration::from_millis(500)));
+        assert!(robots.is_allowed("/Iron_Ingot"));
+        assert!(robots.is_allowed("/Iron_Ingot?action=edit"));
+        assert!(!robots.is_allowed("/Special:Random"));
+        assert!(robots.is_allowed("/Special:FilePath/Iron_Ingot.png"));
+        assert!(!robots.is_allowed("/api.php"));
+        assert!(robots.is_allowed("/api.php?action=parse"));
+
+        let robots = Robots::parse(ROBOTS, "curl");
+        assert_eq!(robots.crawl_delay, Some(Duration::from_secs(5)));
+        assert!(!robots.is_allowed("/Iron_Ingot?action=edit"));
+
+        assert!(Robots::allow_all().is_allowed("/Special:Random"));
+        assert!(!Robots::disallow_all().is_allowed("/"));
+        assert!(!Robots::disallow_all().is_allowed("/Iron_Ingot"));
+    }
+
+    #[test]
+    fn test_pattern_matches() {
+        assert!(pattern_matches("/", "/Iron_Ingot"));
+        assert!(pattern_matches("/Iron*", "/Iron_Ingot"));
+        assert!(pattern_matches("/*_Ingot$", "/Iron_Ingot"));
+        assert!(!pattern_matches("/*_Ingot$", "/Iron_Ingot/"));
+        assert!(pattern_matches("/Iron_Ingot$", "/Iron_Ingot"));
+        assert!(!pattern_matches("/Copper", "/Iron_Ingot"));
+    }
+}
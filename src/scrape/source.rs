@@ -0,0 +1,267 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+use super::cache::Validators;
+use super::parse::{self, ParsedPage};
+use super::{wikitext, RetryPolicy, RetryRequest, RetryRequestError};
+
+#[derive(Debug, Clone)]
+pub enum FetchedPage {
+    /// The page didn't change since the request's validators were issued.
+    NotModified,
+    Modified {
+        body: String,
+        validators: Validators,
+    },
+}
+
+/// Where the scraper gets wiki pages from.
+#[async_trait]
+pub trait PageSource: std::fmt::Debug + Send + Sync {
+    async fn fetch(&self, url: &str) -> Result<String, RetryRequestError>;
+
+    /// Fetches the page unless it is unchanged according to `validators`. Sources that can't
+    /// tell always return the full page.
+    async fn fetch_page(
+        &self,
+        url: &str,
+        _validators: &Validators,
+    ) -> Result<FetchedPage, RetryRequestError> {
+        Ok(FetchedPage::Modified {
+            body: self.fetch(url).await?,
+            validators: Validators::default(),
+        })
+    }
+
+    /// Url actually requested to fetch the page at `url`, e.g. to check it against robots.txt.
+    fn request_url(&self, url: &str) -> String {
+        url.to_string()
+    }
+
+    /// Parses a page body returned by `fetch`. Sources fetching rendered html share the html
+    /// parser.
+    fn parse_page(&self, body: &str, url: &str, base_url: &str) -> ParsedPage {
+        parse::parse_page(body, url, base_url)
+    }
+
+    /// Parses the item page urls out of the body of the Items page.
+    fn parse_item_urls(&self, body: &str, base_url: &str) -> Vec<String> {
+        parse::parse_item_urls(body, base_url)
+    }
+}
+
+/// Fetches pages from the live wiki.
+#[derive(Debug)]
+pub struct HttpSource {
+    client: reqwest::Client,
+    policy: RetryPolicy,
+}
+
+impl HttpSource {
+    pub fn new() -> Self {
+        Self::with_policy(RetryPolicy::default())
+    }
+
+    pub fn with_policy(policy: RetryPolicy) -> Self {
+        Self::with_client(reqwest::Client::new(), policy)
+    }
+
+    /// Sends the requests through `client`, e.g. one with a custom User-Agent.
+    pub fn with_client(client: reqwest::Client, policy: RetryPolicy) -> Self {
+        Self { client, policy }
+    }
+}
+
+impl Default for HttpSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl PageSource for HttpSource {
+    async fn fetch(&self, url: &str) -> Result<String, RetryRequestError> {
+        RetryRequest::new(url)
+            .with_client(self.client.clone())
+            .with_policy(self.policy.clone())
+            .fetch()
+            .await
+    }
+
+    async fn fetch_page(
+        &self,
+        url: &str,
+        validators: &Validators,
+    ) -> Result<FetchedPage, RetryRequestError> {
+        RetryRequest::new(url)
+            .with_client(self.client.clone())
+            .with_policy(self.policy.clone())
+            .with_validators(validators.clone())
+            .fetch_page()
+            .await
+    }
+}
+
+/// Fetches page wikitext through the MediaWiki `api.php` interface instead of the rendered
+/// html, so that scraping doesn't depend on the wiki skin. Page urls keep their usual form;
+/// the page title is their last path segment.
+#[derive(Debug)]
+pub struct MediaWikiSource {
+    api_url: String,
+    client: reqwest::Client,
+    policy: RetryPolicy,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiResponse {
+    parse: Option<ApiParse>,
+    error: Option<ApiError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiParse {
+    wikitext: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    code: String,
+    info: String,
+}
+
+impl MediaWikiSource {
+    /// `api_url` is the address of `api.php`, e.g. `https://dsp-wiki.com/api.php`.
+    pub fn new(api_url: &str) -> Self {
+        Self::with_policy(api_url, RetryPolicy::default())
+    }
+
+    pub fn with_policy(api_url: &str, policy: RetryPolicy) -> Self {
+        Self::with_client(api_url, reqwest::Client::new(), policy)
+    }
+
+    pub fn with_client(api_url: &str, client: reqwest::Client, policy: RetryPolicy) -> Self {
+        Self {
+            api_url: api_url.to_string(),
+            client,
+            policy,
+        }
+    }
+}
+
+#[async_trait]
+impl PageSource for MediaWikiSource {
+    /// `action=parse` request returning the wikitext of the page at `url`.
+    fn request_url(&self, url: &str) -> String {
+        let title = url.trim_end_matches('/').rsplit('/').next().unwrap_or(url);
+        reqwest::Url::parse_with_params(
+            &self.api_url,
+            &[
+                ("action", "parse"),
+                ("page", title),
+                ("prop", "wikitext"),
+                ("redirects", "1"),
+                ("format", "json"),
+                ("formatversion", "2"),
+            ],
+        )
+        .map(|url| url.to_string())
+        .unwrap_or_else(|_| self.api_url.clone())
+    }
+
+    async fn fetch(&self, url: &str) -> Result<String, RetryRequestError> {
+        let body = RetryRequest::new(&self.request_url(url))
+            .with_client(self.client.clone())
+            .with_policy(self.policy.clone())
+            .fetch()
+            .await?;
+        let response: ApiResponse =
+            serde_json::from_str(&body).map_err(|err| RetryRequestError::ApiError {
+                url: url.to_string(),
+                code: "invalidjson".to_string(),
+                info: err.to_string(),
+            })?;
+        match (response.parse, response.error) {
+            (Some(parse), _) => Ok(parse.wikitext),
+            (None, Some(error)) => Err(RetryRequestError::ApiError {
+                url: url.to_string(),
+                code: error.code,
+                info: error.info,
+            }),
+            (None, None) => Err(RetryRequestError::ApiError {
+                url: url.to_string(),
+                code: "noparse".to_string(),
+                info: "response has neither parse nor error".to_string(),
+            }),
+        }
+    }
+
+    fn parse_page(&self, body: &str, url: &str, base_url: &str) -> ParsedPage {
+        wikitext::parse_page(body, url, base_url)
+    }
+
+    fn parse_item_urls(&self, body: &str, base_url: &str) -> Vec<String> {
+        wikitext::parse_item_urls(body, base_url)
+    }
+}
+
+/// Reads pages saved in a directory, one `<page title>.html` file per url, e.g.
+/// `https://dsp-wiki.com/Iron_Ingot` is read from `Iron_Ingot.html`.
+#[derive(Debug)]
+pub struct FixtureSource {
+    dir: PathBuf,
+}
+
+impl FixtureSource {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn path_for(&self, url: &str) -> PathBuf {
+        let path = url
+            .split_once("://")
+            .map(|(_, rest)| rest)
+            .unwrap_or(url)
+            .split_once('/')
+            .map(|(_, path)| path)
+            .unwrap_or_default();
+        let name = path.trim_matches('/').replace('/', "_");
+        self.dir.join(format!("{}.html", name))
+    }
+}
+
+#[async_trait]
+impl PageSource for FixtureSource {
+    async fn fetch(&self, url: &str) -> Result<String, RetryRequestError> {
+        tokio::fs::read_to_string(self.path_for(url))
+            .await
+            .map_err(RetryRequestError::IoError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixture_path() {
+        let source = FixtureSource::new("fixtures");
+        assert_eq!(
+            source.path_for("https://dsp-wiki.com/Iron_Ingot"),
+            PathBuf::from("fixtures/Iron_Ingot.html")
+        );
+        assert_eq!(
+            source.path_for("http://localhost:8080/wiki/Items/"),
+            PathBuf::from("fixtures/wiki_Items.html")
+        );
+    }
+
+    #[test]
+    fn test_media_wiki_request_url() {
+        let source = MediaWikiSource::new("https://dsp-wiki.com/api.php");
+        assert_eq!(
+            source.request_url("https://dsp-wiki.com/Assembling_Machine_Mk.I"),
+            "https://dsp-wiki.com/api.php?action=parse&page=Assembling_Machine_Mk.I&prop=wikitext&redirects=1&format=json&formatversion=2"
+        );
+    }
+}
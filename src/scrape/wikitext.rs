@@ -0,0 +1,443 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::HashMap;
+
+use super::parse::{self, ParsedPage, STARTING_TECHNOLOGY};
+use crate::data::{Item, Materials, Recipe, Technology};
+
+/// `[[Target|label]]` links, capturing the target.
+static LINK_TARGET_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[\[([^\]|#]+)[^\]]*\]\]").unwrap());
+/// `[[Target|label]]` links, capturing the text they display.
+static LINK_TEXT_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\[\[(?:[^\]|]*\|)?([^\]]*)\]\]").unwrap());
+/// Interlanguage links like `[[zh:铁块]]`.
+static LANGUAGE_LINK_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\[\[([a-z]{2,3}(?:-[a-zA-Z]+)?):([^\]|]+)\]\]").unwrap());
+static LIST_SEPARATOR_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r",|<br\s*/?>|\n").unwrap());
+static NUMBER_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\d+\.*\d*").unwrap());
+
+/// A template call such as `{{Name|positional|key = value}}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pub name: String,
+    /// Positional parameters in order, untrimmed.
+    pub positional: Vec<String>,
+    /// Named parameters keyed by lowercase name, values trimmed.
+    pub named: HashMap<String, String>,
+}
+
+impl Template {
+    /// Value of the named parameter, ignoring empty ones.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.named
+            .get(key)
+            .map(|value| value.as_str())
+            .filter(|value| !value.is_empty())
+    }
+
+    /// Templates passed as positional parameters, e.g. the rows of a table template.
+    pub fn nested(&self) -> Vec<Template> {
+        self.positional
+            .iter()
+            .flat_map(|param| parse_templates(param))
+            .collect()
+    }
+
+    fn is(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+    }
+}
+
+/// Parses the top level templates of `text` in order. Scanning stops at unbalanced braces.
+pub fn parse_templates(text: &str) -> Vec<Template> {
+    let bytes = text.as_bytes();
+    let mut templates = vec![];
+    let mut i = 0;
+    while i + 1 < bytes.len() {
+        if &bytes[i..i + 2] != b"{{" {
+            i += 1;
+            continue;
+        }
+        let Some(end) = closing_braces(bytes, i) else {
+            break;
+        };
+        templates.push(parse_template(&text[i + 2..end]));
+        i = end + 2;
+    }
+    templates
+}
+
+/// Index of the `}}` closing the template opened at `start`.
+fn closing_braces(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    while i + 1 < bytes.len() {
+        match &bytes[i..i + 2] {
+            b"{{" => {
+                depth += 1;
+                i += 2;
+            }
+            b"}}" => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+                i += 2;
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+fn parse_template(inner: &str) -> Template {
+    let mut parts = split_params(inner).into_iter();
+    let mut template = Template {
+        name: parts.next().unwrap_or_default().trim().to_string(),
+        positional: vec![],
+        named: HashMap::new(),
+    };
+    for part in parts {
+        match part.split_once('=') {
+            Some((key, value)) if !key.contains("{{") && !key.contains("[[") => {
+                template
+                    .named
+                    .insert(key.trim().to_lowercase(), value.trim().to_string());
+            }
+            _ => template.positional.push(part.to_string()),
+        }
+    }
+    template
+}
+
+/// Splits template parameters on the `|` that are not part of a nested template or link.
+fn split_params(inner: &str) -> Vec<&str> {
+    let bytes = inner.as_bytes();
+    let mut parts = vec![];
+    let (mut braces, mut brackets) = (0, 0);
+    let mut start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let pair = bytes.get(i..i + 2);
+        if pair == Some(b"{{") {
+            braces += 1;
+            i += 2;
+        } else if pair == Some(b"}}") {
+            braces -= 1;
+            i += 2;
+        } else if pair == Some(b"[[") {
+            brackets += 1;
+            i += 2;
+        } else if pair == Some(b"]]") {
+            brackets -= 1;
+            i += 2;
+        } else {
+            if bytes[i] == b'|' && braces == 0 && brackets == 0 {
+                parts.push(&inner[start..i]);
+                start = i + 1;
+            }
+            i += 1;
+        }
+    }
+    parts.push(&inner[start..]);
+    parts
+}
+
+/// Targets of the `[[...]]` links in `text`, skipping files and categories. Plain text without
+/// links is read as a list separated by commas or line breaks.
+pub fn names(text: &str) -> Vec<String> {
+    let mut names: Vec<String> = if text.contains("[[") {
+        LINK_TARGET_RE
+            .captures_iter(text)
+            .map(|captures| captures[1].trim().to_string())
+            .filter(|name| !name.contains(':'))
+            .collect()
+    } else {
+        LIST_SEPARATOR_RE
+            .split(text)
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect()
+    };
+    let mut seen = vec![];
+    names.retain(|name| {
+        let keep = !seen.contains(name);
+        seen.push(name.clone());
+        keep
+    });
+    names
+}
+
+/// Url of a wiki file, e.g. an item icon.
+fn file_url(base_url: &str, file: &str) -> String {
+    format!(
+        "{}/Special:FilePath/{}",
+        base_url,
+        file.trim().replace(' ', "_")
+    )
+}
+
+/// Parses the wikitext of a page into the same values `parse::parse_page` reads from the
+/// rendered html. Images point to `Special:FilePath`, which redirects to the file itself.
+pub fn parse_page(wikitext: &str, url: &str, base_url: &str) -> ParsedPage {
+    let templates = parse_templates(wikitext);
+    let production_chain = templates
+        .iter()
+        .find(|template| template.is("ProductionChain"));
+    let infobox = templates.iter().find(|template| template.is("Infobox"));
+    let technology = infobox.and_then(|infobox| technology_from_infobox(infobox, url));
+
+    ParsedPage {
+        recipes: production_chain
+            .map(|chain| parse_recipes(chain, base_url))
+            .unwrap_or_default(),
+        item: match infobox {
+            Some(infobox) if technology.is_none() => {
+                item_from_infobox(infobox, url, base_url).map(|mut item| {
+                    item.localized_names = localized_names(wikitext);
+                    item
+                })
+            }
+            _ => None,
+        },
+        facility: infobox.and_then(|infobox| {
+            let name = infobox.get("title")?;
+            parse::facility_from_rows(name, url, &infobox_rows(infobox))
+        }),
+        technology,
+        missing_production_table: production_chain.is_none(),
+    }
+}
+
+/// Extracts the item page urls from the `ItemIcon` templates of the Items page.
+pub fn parse_item_urls(wikitext: &str, base_url: &str) -> Vec<String> {
+    let mut urls: Vec<String> = parse_templates(wikitext)
+        .iter()
+        .filter(|template| template.is("ItemIcon"))
+        .filter_map(|template| template.positional.first())
+        .map(|name| format!("{}/{}", base_url, name.trim().replace(' ', "_")))
+        .collect();
+    urls.sort();
+    urls.dedup();
+    urls
+}
+
+/// Parses the `ProductionChainRow` templates of a production chain. Rows list their materials
+/// as `in1`/`in1qty`, `in2`/`in2qty`, ... and their outputs as `out1`/`out1qty`, ...
+pub fn parse_recipes(production_chain: &Template, base_url: &str) -> Vec<Recipe> {
+    let number = |text: &str| -> Option<f64> { NUMBER_RE.find(text)?.as_str().parse().ok() };
+
+    let mut recipes = vec![];
+    for row in production_chain.nested() {
+        if !row.is("ProductionChainRow") {
+            continue;
+        }
+        let Some(time) = row.get("time").and_then(number) else {
+            continue;
+        };
+
+        let mut recipe = Recipe::new();
+        recipe.time = time;
+        for n in 1.. {
+            let Some(name) = row.get(&format!("in{}", n)) else {
+                break;
+            };
+            if let Some(count) = row.get(&format!("in{}qty", n)).and_then(number) {
+                recipe.materials.insert(name.to_string(), count);
+            }
+        }
+        // like the html tables, a row with several outputs is read as a recipe for the last
+        for n in 1.. {
+            let Some(name) = row.get(&format!("out{}", n)) else {
+                break;
+            };
+            recipe.output_item = name.to_string();
+            recipe.output_item_count = row
+                .get(&format!("out{}qty", n))
+                .and_then(number)
+                .unwrap_or_default();
+            recipe.image = Some(file_url(
+                base_url,
+                &format!("Icon_{}.png", name.replace(' ', "_")),
+            ));
+        }
+
+        recipe.facilities = row.get("building").map(names).unwrap_or_default();
+        if let Some(facility) = recipe.facilities.first() {
+            recipe.facility = facility.clone();
+        }
+        recipe.technology = row
+            .get("technology")
+            .map(names)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|technology| technology != STARTING_TECHNOLOGY);
+
+        recipes.push(recipe);
+    }
+    recipes
+}
+
+/// Named infobox parameters as `(label, value)` rows with the markup reduced to text.
+fn infobox_rows(infobox: &Template) -> Vec<(String, String)> {
+    infobox
+        .named
+        .iter()
+        .map(|(label, value)| {
+            (
+                label.clone(),
+                LINK_TEXT_RE.replace_all(value, "$1").to_string(),
+            )
+        })
+        .collect()
+}
+
+fn item_from_infobox(infobox: &Template, url: &str, base_url: &str) -> Option<Item> {
+    let mut item = Item::new(infobox.get("title")?);
+    item.url = Some(url.to_string());
+    item.image = infobox.get("image").map(|image| file_url(base_url, image));
+    item.description = infobox.get("description").map(str::to_string);
+    parse::read_item_rows(&mut item, &infobox_rows(infobox));
+    Some(item)
+}
+
+/// Reads interlanguage links like `[[zh:铁块]]`. Namespaces such as `File:` are capitalized,
+/// language prefixes are not.
+fn localized_names(wikitext: &str) -> HashMap<String, String> {
+    LANGUAGE_LINK_RE
+        .captures_iter(wikitext)
+        .map(|captures| {
+            (
+                parse::language_code(&captures[1]),
+                captures[2].trim().to_string(),
+            )
+        })
+        .filter(|(_, name)| !name.is_empty())
+        .collect()
+}
+
+/// Reads `research cost` as `ItemCount` templates, and `prerequisites` and `unlocks` as links.
+fn technology_from_infobox(infobox: &Template, url: &str) -> Option<Technology> {
+    let mut technology = Technology::new(infobox.get("title")?);
+    technology.url = Some(url.to_string());
+
+    let mut is_technology = false;
+    for (label, value) in infobox.named.iter() {
+        match label.as_str() {
+            "research cost" | "cost" => technology.cost = item_counts(value),
+            "prerequisites" | "prerequisite" | "requires" => {
+                technology.prerequisites = names(value)
+                    .into_iter()
+                    .filter(|name| name != STARTING_TECHNOLOGY)
+                    .collect();
+            }
+            "unlocks" => technology.unlocks = names(value),
+            _ => continue,
+        }
+        is_technology = true;
+    }
+
+    is_technology.then_some(technology)
+}
+
+/// `{{ItemCount|name|count}}` templates as materials.
+fn item_counts(text: &str) -> Materials {
+    parse_templates(text)
+        .iter()
+        .filter(|template| template.is("ItemCount"))
+        .filter_map(|template| {
+            let name = template.positional.first()?.trim().to_string();
+            let count = parse::parse_quantity(template.positional.get(1)?)?;
+            Some((name, count))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> String {
+        let path = format!(
+            "{}/tests/fixtures/dsp-wiki-api/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        let response: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        response["parse"]["wikitext"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_parse_templates() {
+        let templates =
+            parse_templates("text {{Outer|a|{{Inner|x=1}}|key = [[Link|label]] }} {{Other}}");
+        assert_eq!(templates.len(), 2);
+        assert_eq!(templates[0].name, "Outer");
+        assert_eq!(templates[0].positional[0], "a");
+        assert_eq!(templates[0].get("key"), Some("[[Link|label]]"));
+        assert_eq!(templates[0].nested()[0].get("x"), Some("1"));
+        assert_eq!(templates[1].name, "Other");
+        assert!(parse_templates("{{Unclosed|a").is_empty());
+    }
+
+    #[test]
+    fn test_parse_item_urls() {
+        let urls = parse_item_urls(&fixture("Items.json"), "https://dsp-wiki.com");
+        assert_eq!(
+            urls,
+            vec![
+                "https://dsp-wiki.com/Arc_Smelter",
+                "https://dsp-wiki.com/Iron_Ingot",
+                "https://dsp-wiki.com/Iron_Ore",
+                "https://dsp-wiki.com/Magnetic_Coil",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_page() {
+        let page = parse_page(
+            &fixture("Assembling_Machine_Mk.I.json"),
+            "https://dsp-wiki.com/Assembling_Machine_Mk.I",
+            "https://dsp-wiki.com",
+        );
+        assert!(!page.missing_production_table);
+        assert_eq!(page.recipes.len(), 1);
+        let recipe = &page.recipes[0];
+        assert_eq!(recipe.output_item, "Assembling Machine Mk.I");
+        assert_eq!(recipe.time, 2.0);
+        assert_eq!(recipe.materials.get("Gear"), Some(&8.0));
+        assert_eq!(recipe.facilities.len(), 3);
+        assert_eq!(
+            recipe.technology.as_deref(),
+            Some("Basic Assembling Processes")
+        );
+        assert_eq!(page.facility.unwrap().crafting_speed, Some(0.75));
+        let item = page.item.unwrap();
+        assert_eq!(item.stack_size, Some(50.0));
+        assert_eq!(
+            item.localized_names.get("ja").map(String::as_str),
+            Some("組立機 Mk.I")
+        );
+        assert_eq!(item.localized_names.len(), 2);
+
+        let page = parse_page(
+            &fixture("Iron_Ore_Vein.json"),
+            "https://dsp-wiki.com/Iron_Ore_Vein",
+            "https://dsp-wiki.com",
+        );
+        assert!(page.missing_production_table);
+        assert!(page.item.is_none());
+
+        let technology = parse_page(
+            &fixture("Basic_Assembling_Processes.json"),
+            "https://dsp-wiki.com/Basic_Assembling_Processes",
+            "https://dsp-wiki.com",
+        )
+        .technology
+        .unwrap();
+        assert_eq!(technology.cost.get("Electromagnetic Matrix"), Some(&20.0));
+        assert_eq!(technology.prerequisites, vec!["Electromagnetism"]);
+    }
+}
@@ -57,6 +57,12 @@ impl TimeKeeper {
     }
 }
 
+impl Default for TimeKeeper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
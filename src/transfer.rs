@@ -0,0 +1,331 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use utoipa::ToSchema;
+
+use crate::data::Recipe;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DatasetFormat {
+    Json,
+    Csv,
+    Yaml,
+}
+
+impl DatasetFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            DatasetFormat::Json => "application/json",
+            DatasetFormat::Csv => "text/csv",
+            DatasetFormat::Yaml => "application/yaml",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DatasetFormat::Json => "json",
+            DatasetFormat::Csv => "csv",
+            DatasetFormat::Yaml => "yaml",
+        }
+    }
+
+    /// Guesses the format from a file name's extension.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let (_, extension) = path.rsplit_once('.')?;
+        extension.parse().ok()
+    }
+}
+
+impl FromStr for DatasetFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(DatasetFormat::Json),
+            "csv" => Ok(DatasetFormat::Csv),
+            "yaml" | "yml" => Ok(DatasetFormat::Yaml),
+            _ => Err(format!("unsupported dataset format '{}'", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TransferError {
+    JsonError(serde_json::Error),
+    CsvError(csv::Error),
+    YamlError(serde_yaml::Error),
+    InvalidRow { row: usize, message: String },
+}
+
+impl std::fmt::Display for TransferError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransferError::JsonError(err) => write!(f, "invalid json: {}", err),
+            TransferError::CsvError(err) => write!(f, "invalid csv: {}", err),
+            TransferError::YamlError(err) => write!(f, "invalid yaml: {}", err),
+            TransferError::InvalidRow { row, message } => write!(f, "row {}: {}", row, message),
+        }
+    }
+}
+
+impl std::error::Error for TransferError {}
+
+/// One row per material edge. Recipes without materials are written as a single row with empty
+/// material columns. Rows sharing a `recipe_id` belong to the same recipe.
+#[derive(Debug, Serialize, Deserialize)]
+struct RecipeEdgeRow {
+    recipe_id: usize,
+    output_item: String,
+    output_item_count: f64,
+    min_output_item_count: Option<f64>,
+    max_output_item_count: Option<f64>,
+    facility: String,
+    /// Every eligible facility, separated by `|`.
+    #[serde(default)]
+    facilities: String,
+    time: f64,
+    image: Option<String>,
+    #[serde(default)]
+    technology: Option<String>,
+    material: Option<String>,
+    material_count: Option<f64>,
+}
+
+/// Accepts both a flat list of recipes and the list of lists written by the scraper.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RecipeDocument {
+    Flat(Vec<Recipe>),
+    Nested(Vec<Vec<Recipe>>),
+}
+
+impl RecipeDocument {
+    fn into_recipes(self) -> Vec<Recipe> {
+        match self {
+            RecipeDocument::Flat(recipes) => recipes,
+            RecipeDocument::Nested(recipe_lists) => recipe_lists.into_iter().flatten().collect(),
+        }
+    }
+}
+
+#[tracing::instrument(skip(recipes))]
+pub fn export_recipes(recipes: &[Recipe], format: DatasetFormat) -> Result<String, TransferError> {
+    match format {
+        DatasetFormat::Json => {
+            serde_json::to_string_pretty(recipes).map_err(TransferError::JsonError)
+        }
+        DatasetFormat::Yaml => serde_yaml::to_string(recipes).map_err(TransferError::YamlError),
+        DatasetFormat::Csv => export_csv(recipes),
+    }
+}
+
+#[tracing::instrument(skip(input))]
+pub fn import_recipes(input: &str, format: DatasetFormat) -> Result<Vec<Recipe>, TransferError> {
+    match format {
+        DatasetFormat::Json => serde_json::from_str::<RecipeDocument>(input)
+            .map(RecipeDocument::into_recipes)
+            .map_err(TransferError::JsonError),
+        DatasetFormat::Yaml => serde_yaml::from_str::<RecipeDocument>(input)
+            .map(RecipeDocument::into_recipes)
+            .map_err(TransferError::YamlError),
+        DatasetFormat::Csv => import_csv(input),
+    }
+}
+
+fn export_csv(recipes: &[Recipe]) -> Result<String, TransferError> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+
+    for (recipe_id, recipe) in recipes.iter().enumerate() {
+        let mut materials: Vec<_> = recipe.materials.iter().collect();
+        materials.sort_by(|a, b| a.0.cmp(b.0));
+
+        let mut edges: Vec<(Option<String>, Option<f64>)> = materials
+            .into_iter()
+            .map(|(name, count)| (Some(name.clone()), Some(*count)))
+            .collect();
+        if edges.is_empty() {
+            edges.push((None, None));
+        }
+
+        for (material, material_count) in edges {
+            writer
+                .serialize(RecipeEdgeRow {
+                    recipe_id,
+                    output_item: recipe.output_item.clone(),
+                    output_item_count: recipe.output_item_count,
+                    min_output_item_count: recipe.min_output_item_count,
+                    max_output_item_count: recipe.max_output_item_count,
+                    facility: recipe.facility.clone(),
+                    facilities: recipe.facilities.join("|"),
+                    time: recipe.time,
+                    image: recipe.image.clone(),
+                    technology: recipe.technology.clone(),
+                    material,
+                    material_count,
+                })
+                .map_err(TransferError::CsvError)?;
+        }
+    }
+
+    let bytes = writer
+        .into_inner()
+        .map_err(|err| TransferError::CsvError(err.into_error().into()))?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn import_csv(input: &str) -> Result<Vec<Recipe>, TransferError> {
+    let mut reader = csv::Reader::from_reader(input.as_bytes());
+    let mut recipes: BTreeMap<usize, Recipe> = BTreeMap::new();
+
+    for (i, row) in reader.deserialize::<RecipeEdgeRow>().enumerate() {
+        let row = row.map_err(TransferError::CsvError)?;
+        // header is line 1
+        let line = i + 2;
+
+        let recipe = recipes.entry(row.recipe_id).or_insert_with(|| Recipe {
+            output_item: row.output_item.clone(),
+            output_item_count: row.output_item_count,
+            min_output_item_count: row.min_output_item_count,
+            max_output_item_count: row.max_output_item_count,
+            facility: row.facility.clone(),
+            facilities: row
+                .facilities
+                .split('|')
+                .filter(|facility| !facility.is_empty())
+                .map(|facility| facility.to_string())
+                .collect(),
+            time: row.time,
+            materials: Default::default(),
+            image: row.image.clone(),
+            market_data: None,
+            technology: row.technology.clone(),
+        });
+
+        if recipe.output_item != row.output_item || recipe.facility != row.facility {
+            return Err(TransferError::InvalidRow {
+                row: line,
+                message: format!(
+                    "recipe {} mixes '{}' and '{}'",
+                    row.recipe_id, recipe.output_item, row.output_item
+                ),
+            });
+        }
+
+        match (row.material, row.material_count) {
+            (Some(material), Some(count)) => {
+                recipe.materials.insert(material, count);
+            }
+            (None, None) => {}
+            _ => {
+                return Err(TransferError::InvalidRow {
+                    row: line,
+                    message: "material and material_count must both be set or both be empty"
+                        .to_string(),
+                })
+            }
+        }
+    }
+
+    Ok(recipes.into_values().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipes() -> Vec<Recipe> {
+        let mut iron_ingot = Recipe::new();
+        iron_ingot.output_item = "Iron Ingot".to_string();
+        iron_ingot.output_item_count = 1.0;
+        iron_ingot.facility = "Arc Smelter".to_string();
+        iron_ingot.time = 1.0;
+        iron_ingot.materials.insert("Iron Ore".to_string(), 1.0);
+        iron_ingot.image = Some("https://dsp-wiki.com/images/Icon_Iron_Ingot.png".to_string());
+
+        let mut magnetic_coil = Recipe::new();
+        magnetic_coil.output_item = "Magnetic Coil".to_string();
+        magnetic_coil.output_item_count = 2.0;
+        magnetic_coil.facility = "Assembling Machine Mk.I".to_string();
+        magnetic_coil.facilities = vec![
+            "Assembling Machine Mk.I".to_string(),
+            "Assembling Machine Mk.II".to_string(),
+        ];
+        magnetic_coil.time = 1.0;
+        magnetic_coil.technology = Some("Electromagnetism".to_string());
+        magnetic_coil.materials.insert("Magnet".to_string(), 2.0);
+        magnetic_coil
+            .materials
+            .insert("Copper Ingot".to_string(), 1.0);
+
+        let mut water = Recipe::new();
+        water.output_item = "Water".to_string();
+        water.output_item_count = 1.0;
+        water.facility = "Water Pump".to_string();
+        water.time = 1.2;
+
+        vec![iron_ingot, magnetic_coil, water]
+    }
+
+    fn assert_round_trip(format: DatasetFormat) {
+        let exported = export_recipes(&recipes(), format).unwrap();
+        let imported = import_recipes(&exported, format).unwrap();
+
+        assert_eq!(imported.len(), 3);
+        for (imported, original) in imported.iter().zip(recipes().iter()) {
+            assert_eq!(imported.output_item, original.output_item);
+            assert_eq!(imported.output_item_count, original.output_item_count);
+            assert_eq!(imported.facility, original.facility);
+            assert_eq!(imported.facilities, original.facilities);
+            assert_eq!(imported.technology, original.technology);
+            assert_eq!(imported.time, original.time);
+            assert_eq!(imported.materials, original.materials);
+            assert_eq!(imported.image, original.image);
+        }
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        assert_round_trip(DatasetFormat::Json);
+    }
+
+    #[test]
+    fn test_yaml_round_trip() {
+        assert_round_trip(DatasetFormat::Yaml);
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        assert_round_trip(DatasetFormat::Csv);
+    }
+
+    #[test]
+    fn test_csv_one_row_per_material() {
+        let exported = export_recipes(&recipes(), DatasetFormat::Csv).unwrap();
+        // header + 1 iron ingot edge + 2 magnetic coil edges + 1 water row
+        assert_eq!(exported.lines().count(), 5);
+        assert!(exported
+            .lines()
+            .any(|line| line.ends_with("Copper Ingot,1.0")));
+    }
+
+    #[test]
+    fn test_import_nested_json() {
+        let nested = serde_json::to_string(&vec![recipes()]).unwrap();
+        let imported = import_recipes(&nested, DatasetFormat::Json).unwrap();
+        assert_eq!(imported.len(), 3);
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            DatasetFormat::from_path("recipes.yml"),
+            Some(DatasetFormat::Yaml)
+        );
+        assert_eq!(
+            DatasetFormat::from_path("out/recipes.CSV"),
+            Some(DatasetFormat::Csv)
+        );
+        assert_eq!(DatasetFormat::from_path("recipes"), None);
+    }
+}
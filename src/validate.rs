@@ -0,0 +1,433 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use utoipa::ToSchema;
+
+use crate::data::Recipe;
+
+/// Crafting times above this many seconds are almost certainly a parsing mistake.
+const MAX_REASONABLE_TIME: f64 = 3600.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    DanglingMaterial,
+    InvalidTime,
+    InvalidOutputCount,
+    DuplicateRecipe,
+    UnreachableItem,
+    SuspiciousValue,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ValidationIssue {
+    pub kind: IssueKind,
+    pub severity: Severity,
+    pub item: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct ValidationReport {
+    pub recipe_count: usize,
+    pub error_count: usize,
+    pub warning_count: usize,
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// A report is valid when it has no errors. Warnings never block a promotion.
+    pub fn is_valid(&self) -> bool {
+        self.error_count == 0
+    }
+
+    fn push(&mut self, kind: IssueKind, severity: Severity, item: &str, message: String) {
+        match severity {
+            Severity::Warning => self.warning_count += 1,
+            Severity::Error => self.error_count += 1,
+        }
+        self.issues.push(ValidationIssue {
+            kind,
+            severity,
+            item: item.to_string(),
+            message,
+        });
+    }
+}
+
+#[derive(Debug)]
+pub struct Validator {}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    #[tracing::instrument(skip(self, recipes))]
+    pub fn validate(&self, recipes: &[Recipe]) -> ValidationReport {
+        let mut report = ValidationReport {
+            recipe_count: recipes.len(),
+            ..Default::default()
+        };
+
+        for recipe in recipes {
+            self.check_values(recipe, &mut report);
+        }
+        self.check_duplicates(recipes, &mut report);
+        self.check_dangling_materials(recipes, &mut report);
+        self.check_reachability(recipes, &mut report);
+
+        report
+    }
+
+    fn check_values(&self, recipe: &Recipe, report: &mut ValidationReport) {
+        let item = recipe.output_item.as_str();
+
+        if item.trim().is_empty() {
+            report.push(
+                IssueKind::SuspiciousValue,
+                Severity::Error,
+                item,
+                format!("recipe made in '{}' has no output item", recipe.facility),
+            );
+        }
+
+        if recipe.time < 0.0 || !recipe.time.is_finite() {
+            report.push(
+                IssueKind::InvalidTime,
+                Severity::Error,
+                item,
+                format!("crafting time {} is not a valid duration", recipe.time),
+            );
+        } else if recipe.time == 0.0 && consumes_materials(recipe) {
+            // the optimizer would plan 0 facilities and 0 materials for it
+            report.push(
+                IssueKind::InvalidTime,
+                Severity::Error,
+                item,
+                "crafting time is 0".to_string(),
+            );
+        } else if recipe.time > MAX_REASONABLE_TIME {
+            report.push(
+                IssueKind::SuspiciousValue,
+                Severity::Warning,
+                item,
+                format!("crafting time {}s is unusually long", recipe.time),
+            );
+        }
+
+        if recipe.output_item_count <= 0.0 || !recipe.output_item_count.is_finite() {
+            report.push(
+                IssueKind::InvalidOutputCount,
+                Severity::Error,
+                item,
+                format!("output count {} must be positive", recipe.output_item_count),
+            );
+        } else if is_fractional(recipe.output_item_count) {
+            report.push(
+                IssueKind::SuspiciousValue,
+                Severity::Warning,
+                item,
+                format!("output count {} is fractional", recipe.output_item_count),
+            );
+        }
+
+        if recipe.facility.trim().is_empty() {
+            report.push(
+                IssueKind::SuspiciousValue,
+                Severity::Warning,
+                item,
+                "recipe has no facility".to_string(),
+            );
+        } else if !recipe.facilities.is_empty() && !recipe.facilities.contains(&recipe.facility) {
+            report.push(
+                IssueKind::SuspiciousValue,
+                Severity::Warning,
+                item,
+                format!(
+                    "default facility '{}' is not one of its facilities",
+                    recipe.facility
+                ),
+            );
+        }
+
+        let mut materials: Vec<_> = recipe.materials.iter().collect();
+        materials.sort_by(|a, b| a.0.cmp(b.0));
+        for (material_name, count) in materials {
+            if *count < 0.0 || !count.is_finite() {
+                report.push(
+                    IssueKind::SuspiciousValue,
+                    Severity::Error,
+                    item,
+                    format!("material '{}' has invalid count {}", material_name, count),
+                );
+            } else if *count == 0.0 && recipe.time > 0.0 {
+                report.push(
+                    IssueKind::SuspiciousValue,
+                    Severity::Warning,
+                    item,
+                    format!("material '{}' has a count of 0", material_name),
+                );
+            } else if is_fractional(*count) {
+                report.push(
+                    IssueKind::SuspiciousValue,
+                    Severity::Warning,
+                    item,
+                    format!(
+                        "material '{}' has fractional count {}",
+                        material_name, count
+                    ),
+                );
+            }
+        }
+    }
+
+    fn check_duplicates(&self, recipes: &[Recipe], report: &mut ValidationReport) {
+        let mut seen: HashSet<String> = HashSet::new();
+        for recipe in recipes {
+            if !seen.insert(recipe_key(recipe)) {
+                report.push(
+                    IssueKind::DuplicateRecipe,
+                    Severity::Warning,
+                    &recipe.output_item,
+                    format!("duplicate recipe in '{}'", recipe.facility),
+                );
+            }
+        }
+    }
+
+    fn check_dangling_materials(&self, recipes: &[Recipe], report: &mut ValidationReport) {
+        let produced = produced_items(recipes);
+
+        // material name -> items that consume it
+        let mut dangling: HashMap<&str, Vec<&str>> = HashMap::new();
+        for recipe in recipes {
+            for material_name in recipe.materials.keys() {
+                if !produced.contains(&material_name.to_lowercase()) {
+                    dangling
+                        .entry(material_name.as_str())
+                        .or_default()
+                        .push(recipe.output_item.as_str());
+                }
+            }
+        }
+
+        let mut dangling: Vec<_> = dangling.into_iter().collect();
+        dangling.sort_by(|a, b| a.0.cmp(b.0));
+        for (material_name, mut used_by) in dangling {
+            used_by.sort();
+            used_by.dedup();
+            report.push(
+                IssueKind::DanglingMaterial,
+                Severity::Warning,
+                material_name,
+                format!(
+                    "no recipe produces this item (used by {})",
+                    used_by.join(", ")
+                ),
+            );
+        }
+    }
+
+    /// Flags items that can only be crafted from other craftable items in a cycle, i.e. items
+    /// that can never be produced starting from raw resources. Materials without any recipe
+    /// are treated as raw resources; they are already reported as dangling.
+    fn check_reachability(&self, recipes: &[Recipe], report: &mut ValidationReport) {
+        let produced = produced_items(recipes);
+        let mut reachable: HashSet<String> = HashSet::new();
+
+        loop {
+            let mut changed = false;
+            for recipe in recipes {
+                let output = recipe.output_item.to_lowercase();
+                if reachable.contains(&output) {
+                    continue;
+                }
+                let craftable = recipe.materials.keys().all(|material_name| {
+                    let material_name = material_name.to_lowercase();
+                    !produced.contains(&material_name) || reachable.contains(&material_name)
+                });
+                if craftable {
+                    reachable.insert(output);
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let mut unreachable: Vec<&str> = vec![];
+        for recipe in recipes {
+            let output = recipe.output_item.to_lowercase();
+            if !reachable.contains(&output) && !unreachable.contains(&recipe.output_item.as_str()) {
+                unreachable.push(recipe.output_item.as_str());
+            }
+        }
+        unreachable.sort();
+
+        for item in unreachable {
+            report.push(
+                IssueKind::UnreachableItem,
+                Severity::Warning,
+                item,
+                "item cannot be produced from raw resources".to_string(),
+            );
+        }
+    }
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn produced_items(recipes: &[Recipe]) -> HashSet<String> {
+    recipes
+        .iter()
+        .map(|recipe| recipe.output_item.to_lowercase())
+        .collect()
+}
+
+fn recipe_key(recipe: &Recipe) -> String {
+    let mut materials: Vec<_> = recipe.materials.iter().collect();
+    materials.sort_by(|a, b| a.0.cmp(b.0));
+    format!(
+        "{}|{}|{}|{}|{:?}",
+        recipe.output_item.to_lowercase(),
+        recipe.facility,
+        recipe.time,
+        recipe.output_item_count,
+        materials
+    )
+}
+
+fn is_fractional(value: f64) -> bool {
+    value.fract() != 0.0
+}
+
+/// Extraction recipes, e.g. an Oil Extractor on a Crude Oil Vein, consume nothing and have no
+/// crafting time on the wiki.
+fn consumes_materials(recipe: &Recipe) -> bool {
+    recipe.materials.values().any(|count| *count > 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recipe(output_item: &str, time: f64, materials: &[(&str, f64)]) -> Recipe {
+        let mut recipe = Recipe::new();
+        recipe.output_item = output_item.to_string();
+        recipe.output_item_count = 1.0;
+        recipe.facility = "Arc Smelter".to_string();
+        recipe.time = time;
+        recipe.materials = materials
+            .iter()
+            .map(|(name, count)| (name.to_string(), *count))
+            .collect();
+        recipe
+    }
+
+    fn kinds(report: &ValidationReport) -> Vec<IssueKind> {
+        report.issues.iter().map(|issue| issue.kind).collect()
+    }
+
+    #[test]
+    fn test_valid_dataset() {
+        let recipes = vec![
+            recipe("Iron Ore", 2.0, &[]),
+            recipe("Iron Ingot", 1.0, &[("Iron Ore", 1.0)]),
+        ];
+
+        let report = Validator::new().validate(&recipes);
+        assert!(report.is_valid());
+        assert_eq!(report.recipe_count, 2);
+        assert!(report.issues.is_empty());
+    }
+
+    #[test]
+    fn test_dangling_material() {
+        let recipes = vec![recipe("Iron Ingot", 1.0, &[("Iron Ore", 1.0)])];
+
+        let report = Validator::new().validate(&recipes);
+        assert!(report.is_valid());
+        assert_eq!(kinds(&report), vec![IssueKind::DanglingMaterial]);
+        assert_eq!(report.issues[0].item, "Iron Ore");
+    }
+
+    #[test]
+    fn test_invalid_time_and_output() {
+        let mut zero_output = recipe("Magnet", 1.0, &[]);
+        zero_output.output_item_count = 0.0;
+        let recipes = vec![
+            recipe("Iron Ingot", 0.0, &[("Iron Ore", 1.0)]),
+            recipe("Copper Ore", -1.0, &[]),
+            zero_output,
+            // extraction recipes have no crafting time
+            recipe("Iron Ore", 0.0, &[("Iron Ore Vein", 0.0)]),
+        ];
+
+        let report = Validator::new().validate(&recipes);
+        assert!(!report.is_valid());
+        assert_eq!(report.error_count, 3);
+        // only the vein the extraction recipe mines is missing
+        assert_eq!(report.warning_count, 1);
+        assert_eq!(
+            kinds(&report),
+            vec![
+                IssueKind::InvalidTime,
+                IssueKind::InvalidTime,
+                IssueKind::InvalidOutputCount,
+                IssueKind::DanglingMaterial
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_recipe() {
+        let recipes = vec![recipe("Iron Ore", 2.0, &[]), recipe("Iron Ore", 2.0, &[])];
+
+        let report = Validator::new().validate(&recipes);
+        assert_eq!(kinds(&report), vec![IssueKind::DuplicateRecipe]);
+    }
+
+    #[test]
+    fn test_unreachable_item() {
+        let recipes = vec![
+            recipe("Iron Ore", 2.0, &[]),
+            recipe("Widget", 1.0, &[("Gadget", 1.0), ("Iron Ore", 1.0)]),
+            recipe("Gadget", 1.0, &[("Widget", 1.0)]),
+        ];
+
+        let report = Validator::new().validate(&recipes);
+        assert_eq!(
+            kinds(&report),
+            vec![IssueKind::UnreachableItem, IssueKind::UnreachableItem]
+        );
+        assert_eq!(report.issues[0].item, "Gadget");
+        assert_eq!(report.issues[1].item, "Widget");
+    }
+
+    #[test]
+    fn test_suspicious_values() {
+        let mut fractional = recipe("Iron Ingot", 1.0, &[("Iron Ore", 0.5)]);
+        fractional.output_item_count = 1.5;
+        let recipes = vec![recipe("Iron Ore", 7200.0, &[]), fractional];
+
+        let report = Validator::new().validate(&recipes);
+        assert!(report.is_valid());
+        assert_eq!(report.warning_count, 3);
+        assert!(report
+            .issues
+            .iter()
+            .all(|issue| issue.kind == IssueKind::SuspiciousValue));
+    }
+}